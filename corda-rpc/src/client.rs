@@ -1,17 +1,19 @@
-use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::time::SystemTime;
 
-use oasis_amqp::{amqp, proto::BytesFrame};
+#[cfg(feature = "tls")]
+use oasis_amqp::tls::TlsConfig;
+use oasis_amqp::{amqp, proto::BytesFrame, ConnectionError, Credit, Outcome, Sender, Session};
 use rand::{self, Rng};
-use serde_bytes::Bytes;
 use tokio::net::ToSocketAddrs;
 use uuid::Uuid;
 
 use crate::types::Rpc;
 
 pub struct Client {
-    inner: oasis_amqp::Client,
+    connection: oasis_amqp::Client,
+    session: Session,
+    sender: Sender,
     user: String,
     container: String,
 }
@@ -22,91 +24,95 @@ impl Client {
         user: String,
         password: &str,
         container: String,
-    ) -> Result<Self, ()> {
-        let mut inner = oasis_amqp::Client::connect(address).await.map_err(|_| ())?;
-        inner.login(&user, password).await?;
-        inner.open(&container).await?;
-        inner.begin().await?;
+    ) -> Result<Self, ConnectionError> {
+        let connection = oasis_amqp::Client::connect(address).await?;
+        Self::start(connection, user, password, container).await
+    }
+
+    /// Connect to the node's RPC endpoint over TLS
+    #[cfg(feature = "tls")]
+    pub async fn new_tls(
+        host: &str,
+        port: u16,
+        tls: &TlsConfig,
+        user: String,
+        password: &str,
+        container: String,
+    ) -> Result<Self, ConnectionError> {
+        let connection = oasis_amqp::Client::connect_tls(host, port, tls).await?;
+        Self::start(connection, user, password, container).await
+    }
+
+    /// Connect to the node's RPC endpoint through a WebSocket at a `ws://` URL
+    #[cfg(feature = "websocket")]
+    pub async fn new_websocket(
+        url: &str,
+        user: String,
+        password: &str,
+        container: String,
+    ) -> Result<Self, ConnectionError> {
+        let connection = oasis_amqp::Client::connect_websocket(url).await?;
+        Self::start(connection, user, password, container).await
+    }
+
+    /// Connect to the node's RPC endpoint through a WebSocket at a `wss://` URL
+    #[cfg(all(feature = "websocket", feature = "tls"))]
+    pub async fn new_websocket_tls(
+        url: &str,
+        tls: &TlsConfig,
+        user: String,
+        password: &str,
+        container: String,
+    ) -> Result<Self, ConnectionError> {
+        let connection = oasis_amqp::Client::connect_websocket_tls(url, tls).await?;
+        Self::start(connection, user, password, container).await
+    }
+
+    async fn start(
+        mut connection: oasis_amqp::Client,
+        user: String,
+        password: &str,
+        container: String,
+    ) -> Result<Self, ConnectionError> {
+        connection.login(&user, password).await?;
+        connection.open(&container).await?;
+        let session = connection.begin().await?;
 
         let sender_name = format!("corda-rpc-{:x}", Uuid::new_v4().hyphenated());
-        inner
-            .attach(amqp::Attach {
-                name: &sender_name,
-                handle: 0,
-                role: amqp::Role::Sender,
-                snd_settle_mode: None,
-                rcv_settle_mode: None,
-                source: Some(amqp::Source {
-                    address: Some(&container),
-                    ..Default::default()
-                }),
-                target: Some(amqp::Target {
-                    address: Some("rpc.server"),
-                    ..Default::default()
-                }),
-                unsettled: None,
-                incomplete_unsettled: None,
-                initial_delivery_count: Some(0),
-                max_message_size: None,
-                offered_capabilities: None,
-                desired_capabilities: None,
-                properties: None,
-            })
+        let sender = session
+            .sender("rpc.server")
+            .name(&sender_name)
+            .source(&container)
+            .attach()
             .await?;
 
         Ok(Self {
-            inner,
+            connection,
+            session,
+            sender,
             user,
             container,
         })
     }
 
-    pub async fn call<'r, T: Rpc<'static>>(&mut self, rpc: &T) -> Result<BytesFrame, T::Error> {
+    /// Call `rpc`, returning the raw response to decode with `Rpc::response()`
+    ///
+    /// Errors are about sending the request or receiving the response; an error raised by the
+    /// RPC itself comes back as a response, and is returned by `Rpc::response()`.
+    pub async fn call<T: Rpc<'static>>(&mut self, rpc: &T) -> Result<BytesFrame, ConnectionError> {
         let rcv_queue_name = format!(
             "rpc.client.{}.{}",
             self.user,
             rand::thread_rng().gen::<u64>() & 0xefff_ffff_ffff_ffff,
         );
 
-        self.inner
-            .attach(amqp::Attach {
-                name: &rcv_queue_name,
-                handle: 1,
-                role: amqp::Role::Receiver,
-                snd_settle_mode: None,
-                rcv_settle_mode: None,
-                source: Some(amqp::Source {
-                    address: Some(&rcv_queue_name),
-                    ..Default::default()
-                }),
-                target: Some(amqp::Target {
-                    address: Some(&self.container),
-                    ..Default::default()
-                }),
-                unsettled: None,
-                incomplete_unsettled: None,
-                initial_delivery_count: None,
-                max_message_size: None,
-                offered_capabilities: None,
-                desired_capabilities: None,
-                properties: None,
-            })
-            .await?;
-
-        self.inner
-            .flow(amqp::Flow {
-                next_incoming_id: Some(1),
-                incoming_window: 2_147_483_647,
-                next_outgoing_id: 1,
-                outgoing_window: 2_147_483_647,
-                handle: Some(1),
-                delivery_count: Some(0),
-                link_credit: Some(1000),
-                available: None,
-                drain: None,
-                echo: None,
-                properties: None,
-            })
+        let receiver = self
+            .session
+            .receiver(&rcv_queue_name)
+            .name(&rcv_queue_name)
+            .target(&self.container)
+            .credit(Credit::Prefetch(1000))
+            .attach()
             .await?;
 
         let now = SystemTime::now();
@@ -115,48 +121,42 @@ impl Client {
 
         let rpc_id = format!("{:x}", Uuid::new_v4().hyphenated());
         let rpc_session_id = format!("{:x}", Uuid::new_v4().hyphenated());
-        let delivery_tag = Uuid::new_v4();
-
-        let mut properties = HashMap::new();
-        properties.insert("_AMQ_VALIDATED_USER", amqp::Any::Str(&self.user));
-        properties.insert("tag", amqp::Any::I32(0));
-        properties.insert("method-name", amqp::Any::Str(rpc.method()));
-        properties.insert("rpc-id", amqp::Any::Str(&rpc_id));
-        properties.insert("rpc-id-timestamp", amqp::Any::I64(timestamp));
-        properties.insert("rpc-session-id", amqp::Any::Str(&rpc_session_id));
-        properties.insert("rpc-session-id-timestamp", amqp::Any::I64(timestamp));
-        properties.insert("deduplication-sequence-number", amqp::Any::I64(0));
 
         let mut body = vec![];
-        rpc.request().encode(&mut body).unwrap();
-
-        self.inner
-            .transfer(
-                amqp::Transfer {
-                    handle: 0,
-                    delivery_id: Some(0),
-                    delivery_tag: Some(delivery_tag.as_bytes().to_vec()),
-                    message_format: Some(0),
-                    ..Default::default()
-                },
-                amqp::Message {
-                    properties: Some(amqp::Properties {
-                        message_id: Some(rpc_id.clone().into()),
-                        reply_to: Some(rcv_queue_name.clone().into()),
-                        user_id: Some(Bytes::new(self.user.as_bytes())),
-                        ..Default::default()
-                    }),
-                    application_properties: Some(amqp::ApplicationProperties(properties)),
-                    body: Some(amqp::Body::Data(amqp::Data(&body))),
-                    ..Default::default()
-                },
-            )
-            .await
-            .unwrap();
-
-        match self.inner.next().await {
-            Some(Ok(frame)) => Ok(frame),
-            _ => Err(().into()),
+        rpc.request().encode(&mut body)?;
+
+        let message = amqp::Message::builder()
+            .message_id(rpc_id.as_str())
+            .reply_to(rcv_queue_name.as_str())
+            .user_id(self.user.as_bytes())
+            .application_property("_AMQ_VALIDATED_USER", self.user.as_str())
+            .application_property("tag", 0i32)
+            .application_property("method-name", rpc.method())
+            .application_property("rpc-id", rpc_id.as_str())
+            .application_property("rpc-id-timestamp", timestamp)
+            .application_property("rpc-session-id", rpc_session_id.as_str())
+            .application_property("rpc-session-id-timestamp", timestamp)
+            .application_property("deduplication-sequence-number", 0i64)
+            .data(body)
+            .build();
+        // Without an accepted request, no response will ever come
+        match self.sender.send(message).await? {
+            Outcome::Accepted => {}
+            outcome => return Err(ConnectionError::NotAccepted(outcome)),
         }
+
+        // Settle the response before detaching, so that the broker doesn't redeliver it
+        let response = receiver.recv().await?;
+        let frame = response.frame().clone();
+        response.accept().await?;
+        receiver.close().await?;
+        Ok(frame)
+    }
+
+    /// Detach from the RPC server and close the connection
+    pub async fn close(self) -> Result<(), ConnectionError> {
+        self.sender.close().await?;
+        self.session.close().await?;
+        self.connection.close().await
     }
 }
@@ -1,5 +1,7 @@
 mod client;
 pub use client::Client;
+#[cfg(feature = "tls")]
+pub use oasis_amqp::tls::TlsConfig;
 
 mod network_map_snapshot;
 pub use network_map_snapshot::{NetworkMapSnapshot, NodeInfo};
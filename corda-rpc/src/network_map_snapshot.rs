@@ -20,7 +20,7 @@ impl<'r> Rpc<'r> for NetworkMapSnapshot {
         "networkMapSnapshot"
     }
 
-    fn request(&self) -> Envelope<Self::Arguments> {
+    fn request(&self) -> Envelope<'_, Self::Arguments> {
         Envelope {
             obj: ObjectList(amqp::List::default()),
             schema: Schema {
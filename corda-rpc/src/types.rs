@@ -33,8 +33,8 @@ where
         impl<T, E> Default for FieldVisitor<T, E> {
             fn default() -> Self {
                 Self {
-                    t1: PhantomData::default(),
-                    t2: PhantomData::default(),
+                    t1: PhantomData,
+                    t2: PhantomData,
                 }
             }
         }
@@ -49,8 +49,8 @@ where
                 __E: serde::de::Error,
             {
                 match Some(value) {
-                    Success::<T>::NAME => Ok(Field::F0(PhantomData::default())),
-                    Failure::<E>::NAME => Ok(Field::F1(PhantomData::default())),
+                    Success::<T>::NAME => Ok(Field::F0(PhantomData)),
+                    Failure::<E>::NAME => Ok(Field::F1(PhantomData)),
                     _ => {
                         let value = &std::string::String::from_utf8_lossy(value);
                         Err(serde::de::Error::unknown_variant(value, VARIANTS))
@@ -133,14 +133,18 @@ pub struct Failure<T> {
     pub(crate) value: T,
 }
 
+/// A Corda RPC method, with its arguments and the decoding of its response
+///
+/// `Client::call()` reports transport failures as `ConnectionError`; `Error` is only the error
+/// returned by the RPC itself, as decoded by `response()`.
 pub trait Rpc<'r> {
     type Arguments: Serialize;
     type OkResult: 'r;
-    type Error: From<()> + 'r;
+    type Error: 'r;
 
     fn method(&self) -> &'static str;
 
-    fn request(&self) -> Envelope<Self::Arguments>;
+    fn request(&self) -> Envelope<'_, Self::Arguments>;
 
     fn response(&self, response: &'r BytesFrame) -> Result<Self::OkResult, Self::Error>;
 }
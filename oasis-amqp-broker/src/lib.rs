@@ -0,0 +1,347 @@
+//! An in-memory AMQP 1.0 broker, for testing AMQP applications without a real broker
+//!
+//! Queues are created when a link first attaches to their address and live as long as the
+//! broker, except for dynamic queues (such as reply queues), which are deleted once the link
+//! they were created for is detached. Messages sent to a queue are accepted as soon as they
+//! have been stored, and are handed out to the queue's consumers as they issue credit.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use oasis_amqp::server::Authenticator;
+use oasis_amqp::{amqp, ConnectionError, Credit, IncomingLink, Outcome, Receiver, Sender};
+use oasis_amqp::{Listener, ServerConnection, Session};
+use tokio::net::ToSocketAddrs;
+use tokio::sync::Notify;
+use tokio::task::JoinSet;
+
+/// A broker holding named queues in memory
+///
+/// The broker can be cloned cheaply; clones share the same queues.
+#[derive(Clone, Default)]
+pub struct Broker {
+    queues: Arc<Queues>,
+    authenticator: Option<Arc<dyn Authenticator>>,
+}
+
+impl Broker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require clients to authenticate with SASL
+    ///
+    /// Without an authenticator, clients must open the connection without a SASL layer.
+    pub fn authenticator(mut self, authenticator: impl Authenticator + 'static) -> Self {
+        self.authenticator = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Listen on `addr` from a background task, returning the address actually bound
+    ///
+    /// Binding to port 0 picks a free port, which is convenient for tests.
+    pub async fn spawn<A: ToSocketAddrs>(&self, addr: A) -> Result<SocketAddr, ConnectionError> {
+        let listener = Listener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let broker = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = broker.serve(listener).await {
+                eprintln!("broker stopped listening on {}: {}", addr, e);
+            }
+        });
+        Ok(addr)
+    }
+
+    /// Accept connections from `listener`, serving each of them from its own task
+    ///
+    /// Connections that fail are reported on stderr. Errors accepting a single connection do
+    /// not stop the broker; this only returns if the listener itself fails.
+    pub async fn serve(&self, listener: Listener) -> Result<(), ConnectionError> {
+        loop {
+            let (conn, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(ConnectionError::Io(e)) if is_connection_error(&e) => continue,
+                Err(ConnectionError::Io(e)) if is_resource_error(&e) => {
+                    // Give other connections a chance to close and free up descriptors
+                    eprintln!("failed to accept connection: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let broker = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = broker.handle(conn).await {
+                    eprintln!("connection from {} failed: {}", addr, e);
+                }
+            });
+        }
+    }
+
+    /// Serve a single connection until it is closed
+    pub async fn handle(&self, mut conn: ServerConnection) -> Result<(), ConnectionError> {
+        if let Some(authenticator) = &self.authenticator {
+            conn.authenticate(&**authenticator).await?;
+        }
+        conn.open(CONTAINER_ID).await?;
+
+        // Dropping the set stops the sessions and their links once the connection is gone
+        let mut sessions = JoinSet::new();
+        loop {
+            let session = match conn.accept_session().await {
+                Ok(session) => session,
+                Err(ConnectionError::RemoteClose(None)) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+
+            while sessions.try_join_next().is_some() {}
+            sessions.spawn(self.clone().session(session));
+        }
+    }
+
+    /// The names of all queues, in alphabetical order
+    pub fn queues(&self) -> Vec<String> {
+        let mut names = self
+            .queues
+            .queues
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// The number of messages waiting in the queue `name`, if it exists
+    ///
+    /// Messages that have been sent to a consumer but not yet settled are not counted.
+    pub fn queue_len(&self, name: &str) -> Option<usize> {
+        let queues = self.queues.queues.lock().unwrap();
+        queues.get(name).map(|queue| queue.len())
+    }
+
+    async fn session(self, session: Session) {
+        let mut links = JoinSet::new();
+        while let Ok(link) = session.accept_link().await {
+            while links.try_join_next().is_some() {}
+            if self.attach(link, &mut links).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Accept a link attached by a client, routing it to the queue at its address
+    async fn attach(
+        &self,
+        link: IncomingLink,
+        links: &mut JoinSet<()>,
+    ) -> Result<(), ConnectionError> {
+        let address = match (link.dynamic(), link.role()) {
+            (true, _) => Some(self.queues.dynamic()),
+            (false, amqp::Role::Receiver) => link.target().map(String::from),
+            (false, amqp::Role::Sender) => link.source().map(String::from),
+        };
+
+        let address = match address {
+            Some(address) => address,
+            None => {
+                let error = amqp::Error {
+                    condition: "amqp:not-implemented",
+                    description: Some("links without an address are not supported"),
+                    info: None,
+                };
+                return link.reject(Some(error));
+            }
+        };
+
+        let dynamic = link.dynamic().then(|| DeleteOnDrop {
+            queues: self.queues.clone(),
+            address: address.clone(),
+        });
+        self.queues.declare(&address);
+        match link.role() {
+            amqp::Role::Receiver => {
+                let receiver = link
+                    .address(&address)
+                    .accept_receiver(Credit::Prefetch(PREFETCH))?;
+                let queues = self.queues.clone();
+                links.spawn(async move {
+                    let _dynamic = dynamic;
+                    queues.publish(&address, receiver).await
+                });
+            }
+            amqp::Role::Sender => {
+                let sender = link.address(&address).accept_sender()?;
+                let queues = self.queues.clone();
+                links.spawn(async move {
+                    let _dynamic = dynamic;
+                    queues.consume(&address, sender).await
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Queues {
+    /// Encoded messages, by queue name
+    queues: Mutex<HashMap<String, VecDeque<Vec<u8>>>>,
+    /// Notified whenever a message is added to any of the queues
+    added: Notify,
+    next_dynamic: AtomicU64,
+}
+
+impl Queues {
+    /// Store messages sent on `receiver` in the queue at `address`
+    async fn publish(&self, address: &str, receiver: Receiver) {
+        while let Ok(delivery) = receiver.recv().await {
+            let payload = delivery.frame().payload().to_vec();
+            let result = match self.push(address, payload, false) {
+                true => delivery.accept().await,
+                false => {
+                    let error = amqp::Error {
+                        condition: "amqp:not-found",
+                        description: Some("queue has been deleted"),
+                        info: None,
+                    };
+                    delivery.reject(Some(error)).await
+                }
+            };
+
+            if result.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Send messages from the queue at `address` on `sender`, as far as it has credit
+    async fn consume(&self, address: &str, sender: Sender) {
+        while sender.wait_for_credit().await.is_ok() {
+            let payload = tokio::select! {
+                payload = self.pop(address) => payload,
+                _ = sender.detached() => break,
+            };
+
+            let mut message = InFlight {
+                queues: self,
+                address,
+                payload: Some(payload),
+            };
+
+            // Messages that can't be decoded are dropped
+            let decoded = amqp::Message::decode(message.payload.as_deref().unwrap());
+            let outcome = match decoded {
+                Ok(decoded) => sender.send(decoded).await,
+                Err(_) => Ok(Outcome::Rejected(None)),
+            };
+
+            match outcome {
+                Ok(Outcome::Accepted) | Ok(Outcome::Rejected(_)) => message.payload = None,
+                // Released and modified messages are put back by `InFlight`
+                Ok(Outcome::Released) | Ok(Outcome::Modified(_)) => {}
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Wait for the next message in the queue at `address`
+    async fn pop(&self, address: &str) -> Vec<u8> {
+        loop {
+            let mut added = pin!(self.added.notified());
+            added.as_mut().enable();
+            if let Some(queue) = self.queues.lock().unwrap().get_mut(address) {
+                if let Some(payload) = queue.pop_front() {
+                    return payload;
+                }
+            }
+            added.await;
+        }
+    }
+
+    /// Add a message to the queue at `address`, returning false if it doesn't exist
+    fn push(&self, address: &str, payload: Vec<u8>, front: bool) -> bool {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = match queues.get_mut(address) {
+            Some(queue) => queue,
+            None => return false,
+        };
+
+        match front {
+            true => queue.push_front(payload),
+            false => queue.push_back(payload),
+        }
+        self.added.notify_waiters();
+        true
+    }
+
+    fn declare(&self, address: &str) {
+        let mut queues = self.queues.lock().unwrap();
+        queues.entry(address.to_owned()).or_default();
+    }
+
+    /// Pick a name for a new dynamic queue
+    fn dynamic(&self) -> String {
+        let id = self.next_dynamic.fetch_add(1, Ordering::Relaxed);
+        format!("dynamic.{}", id)
+    }
+}
+
+/// A message taken from a queue, which is put back unless it has been settled
+struct InFlight<'a> {
+    queues: &'a Queues,
+    address: &'a str,
+    payload: Option<Vec<u8>>,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if let Some(payload) = self.payload.take() {
+            self.queues.push(self.address, payload, true);
+        }
+    }
+}
+
+/// Deletes a dynamic queue once the link it was created for is gone
+struct DeleteOnDrop {
+    queues: Arc<Queues>,
+    address: String,
+}
+
+impl Drop for DeleteOnDrop {
+    fn drop(&mut self) {
+        if let Ok(mut queues) = self.queues.queues.lock() {
+            queues.remove(&self.address);
+        }
+    }
+}
+
+/// Whether accepting failed because of the connection being accepted, rather than the listener
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+    )
+}
+
+/// Whether accepting failed because the process or system ran out of file descriptors or memory
+fn is_resource_error(e: &io::Error) -> bool {
+    // `EMFILE` and `ENFILE`, which do not have an `io::ErrorKind` of their own
+    matches!(e.raw_os_error(), Some(23 | 24) if cfg!(unix))
+        || e.kind() == io::ErrorKind::OutOfMemory
+}
+
+/// Credit issued to clients sending messages to the broker
+const PREFETCH: u32 = 100;
+const CONTAINER_ID: &str = "oasis-amqp-broker";
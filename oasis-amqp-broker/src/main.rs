@@ -0,0 +1,60 @@
+use std::process;
+
+use structopt::StructOpt;
+
+use oasis_amqp::server::Users;
+use oasis_amqp::Listener;
+use oasis_amqp_broker::Broker;
+
+#[tokio::main]
+async fn main() {
+    let options = Options::from_args();
+    let mut broker = Broker::new();
+    if !options.users.is_empty() || options.anonymous {
+        let mut users = Users::new();
+        for (name, password) in &options.users {
+            users = users.user(name, password);
+        }
+        if options.anonymous {
+            users = users.anonymous();
+        }
+        broker = broker.authenticator(users);
+    }
+
+    let listener = match Listener::bind(&options.bind).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("failed to listen on {}: {}", options.bind, e);
+            process::exit(1);
+        }
+    };
+    match listener.local_addr() {
+        Ok(addr) => println!("listening on {}", addr),
+        Err(_) => println!("listening on {}", options.bind),
+    }
+
+    if let Err(e) = broker.serve(listener).await {
+        eprintln!("failed to accept connections: {}", e);
+        process::exit(1);
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct Options {
+    /// Address to listen on
+    #[structopt(short, long, default_value = "127.0.0.1:5672")]
+    bind: String,
+    /// Accept a PLAIN login as `name:password`; without users, SASL is not used
+    #[structopt(short, long = "user", parse(try_from_str = parse_user))]
+    users: Vec<(String, String)>,
+    /// Accept ANONYMOUS logins
+    #[structopt(long)]
+    anonymous: bool,
+}
+
+fn parse_user(s: &str) -> Result<(String, String), String> {
+    match s.split_once(':') {
+        Some((name, password)) => Ok((name.to_owned(), password.to_owned())),
+        None => Err(format!("expected name:password, found {:?}", s)),
+    }
+}
@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use corda_rpc::NetworkMapSnapshot;
+use oasis_amqp::server::Users;
+use oasis_amqp::{amqp, Client, ConnectionError, Credit, Outcome};
+use oasis_amqp_broker::Broker;
+
+fn message(body: &[u8]) -> amqp::Message<'_> {
+    amqp::Message::builder().data(body).build()
+}
+
+#[tokio::test]
+async fn queue() {
+    let broker = Broker::new();
+    let addr = broker.spawn("127.0.0.1:0").await.unwrap();
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    let session = client.begin().await.unwrap();
+    let sender = session.sender("work").attach().await.unwrap();
+    for body in &[&b"one"[..], b"two", b"three"] {
+        let outcome = sender.send(message(body)).await.unwrap();
+        assert_eq!(outcome, Outcome::Accepted);
+    }
+    assert_eq!(broker.queues(), vec!["work".to_owned()]);
+    assert_eq!(broker.queue_len("work"), Some(3));
+
+    // A released message is delivered again before the rest of the queue
+    let receiver = session.receiver("work").attach().await.unwrap();
+    receiver.flow(1).await.unwrap();
+    let delivery = receiver.recv().await.unwrap();
+    assert_eq!(delivery.body(), Some(&b"one"[..]));
+    delivery.release().await.unwrap();
+
+    receiver.flow(1).await.unwrap();
+    let delivery = receiver.recv().await.unwrap();
+    assert_eq!(delivery.body(), Some(&b"one"[..]));
+    delivery.accept().await.unwrap();
+
+    receiver.flow(2).await.unwrap();
+    for expected in &[&b"two"[..], b"three"] {
+        let delivery = receiver.recv().await.unwrap();
+        assert_eq!(delivery.body(), Some(*expected));
+        delivery.accept().await.unwrap();
+    }
+
+    assert_eq!(broker.queue_len("work"), Some(0));
+    client.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn dynamic_queue() {
+    let broker = Broker::new().authenticator(Users::new().user("user", "secret"));
+    let addr = broker.spawn("127.0.0.1:0").await.unwrap();
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.login("user", "secret").await.unwrap();
+    client.open("client").await.unwrap();
+    let session = client.begin().await.unwrap();
+    let receiver = session
+        .dynamic_receiver()
+        .credit(Credit::Prefetch(4))
+        .attach()
+        .await
+        .unwrap();
+    let address = receiver.address().unwrap();
+    assert!(address.starts_with("dynamic."));
+
+    let sender = session.sender(&address).attach().await.unwrap();
+    assert_eq!(
+        sender.send(message(b"reply")).await.unwrap(),
+        Outcome::Accepted
+    );
+    let delivery = receiver.recv().await.unwrap();
+    assert_eq!(delivery.body(), Some(&b"reply"[..]));
+    delivery.accept().await.unwrap();
+
+    // The queue is deleted once its receiver has detached
+    receiver.close().await.unwrap();
+    for _ in 0..100 {
+        if broker.queues().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(broker.queues(), Vec::<String>::new());
+
+    match sender.send(message(b"late")).await.unwrap() {
+        Outcome::Rejected(Some(error)) => assert_eq!(error.condition, "amqp:not-found"),
+        outcome => panic!("unexpected outcome: {:?}", outcome),
+    }
+    client.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn unauthenticated() {
+    let broker = Broker::new().authenticator(Users::new().user("user", "secret"));
+    let addr = broker.spawn("127.0.0.1:0").await.unwrap();
+
+    let mut client = Client::connect(addr).await.unwrap();
+    match client.login("user", "wrong").await {
+        Err(ConnectionError::Sasl { .. }) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    // A failed connection does not stop the broker from serving others
+    let mut client = Client::connect(addr).await.unwrap();
+    client.login("user", "secret").await.unwrap();
+    client.open("client").await.unwrap();
+    client.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn unexpected_login() {
+    let broker = Broker::new();
+    let addr = broker.spawn("127.0.0.1:0").await.unwrap();
+
+    // Without an authenticator, the broker answers a SASL header with the AMQP header
+    let mut client = Client::connect(addr).await.unwrap();
+    match client.login("user", "secret").await {
+        Err(ConnectionError::UnexpectedFrame { found, .. }) => assert_eq!(found, "AMQP header"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[tokio::test]
+async fn corda_rpc() {
+    let broker = Broker::new().authenticator(Users::new().user("user", "secret"));
+    let addr = broker.spawn("127.0.0.1:0").await.unwrap();
+
+    // Stands in for the node, answering each request on the queue named by its `reply_to`
+    let mut node = Client::connect(addr).await.unwrap();
+    node.login("user", "secret").await.unwrap();
+    node.open("node").await.unwrap();
+    let session = node.begin().await.unwrap();
+    let requests = session
+        .receiver("rpc.server")
+        .credit(Credit::Prefetch(1))
+        .attach()
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        let delivery = requests.recv().await.unwrap();
+        let properties = delivery.message().properties.as_ref().unwrap();
+        let reply_to = properties.reply_to.as_deref().unwrap().to_owned();
+        delivery.accept().await.unwrap();
+
+        let sender = session.sender(&reply_to).attach().await.unwrap();
+        let outcome = sender.send(message(b"response")).await.unwrap();
+        assert_eq!(outcome, Outcome::Accepted);
+    });
+
+    let mut client = corda_rpc::Client::new(addr, "user".into(), "secret", "client".into())
+        .await
+        .unwrap();
+    let response = client.call(&NetworkMapSnapshot).await.unwrap();
+    assert_eq!(response.body(), Some(&b"response"[..]));
+
+    // The response has been settled, so it isn't put back on the reply queue
+    let replies = broker
+        .queues()
+        .into_iter()
+        .find(|name| name.starts_with("rpc.client."))
+        .unwrap();
+    for _ in 0..100 {
+        if broker.queue_len(&replies) == Some(0) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(broker.queue_len(&replies), Some(0));
+    client.close().await.unwrap();
+}
@@ -55,8 +55,6 @@ fn enum_serde(def: syn::ItemEnum) -> proc_macro::TokenStream {
     let de_life = syn::Lifetime::new("'de", Span::call_site());
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
-    let screaming = translate(&def.ident.to_string());
-    let scope = format_ident!("_IMPL_DESERIALIZER_FOR_{}", screaming);
     let name_str = syn::LitStr::new(&name.to_string(), Span::call_site());
 
     let mut field_variants = TokenStream::new();
@@ -93,7 +91,7 @@ fn enum_serde(def: syn::ItemEnum) -> proc_macro::TokenStream {
 
         let variant = format_ident!("F{}", i);
         let mut ty_name = ty.clone();
-        let mut segment = ty_name.path.segments.last_mut().unwrap();
+        let segment = ty_name.path.segments.last_mut().unwrap();
         segment.arguments = syn::PathArguments::None;
         int_arms.append_all(quote!(#ty_name::CODE => std::result::Result::Ok(Field::#variant),));
         bytes_arms.append_all(quote!(#ty_name::NAME => std::result::Result::Ok(Field::#variant),));
@@ -129,7 +127,7 @@ fn enum_serde(def: syn::ItemEnum) -> proc_macro::TokenStream {
     ));
 
     let res = quote!(
-        const #scope: () = {
+        const _: () = {
             use serde;
             use std::fmt;
 
@@ -316,14 +314,3 @@ fn struct_serde(
     let rename = quote!(#[derive(Deserialize)] #[serde(rename = #renamed)]);
     (described.into(), Some(rename.into()))
 }
-
-fn translate(s: &str) -> String {
-    let mut snake = String::new();
-    for (i, ch) in s.char_indices() {
-        if i > 0 && ch.is_uppercase() {
-            snake.push('_');
-        }
-        snake.push(ch.to_ascii_uppercase());
-    }
-    snake
-}
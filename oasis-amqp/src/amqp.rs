@@ -5,10 +5,11 @@ use std::fmt;
 use std::marker::PhantomData;
 
 use oasis_amqp_macros::amqp;
-use serde::{self, ser::SerializeTuple, Deserialize, Serialize};
+use serde::ser::{SerializeMap, SerializeSeq, SerializeTuple};
+use serde::{self, Deserialize, Serialize};
 use serde_bytes::Bytes;
 
-use crate::{de, Described};
+use crate::{de, ser, Described};
 
 #[derive(Debug, PartialEq, Serialize)]
 pub struct Frame<'a> {
@@ -20,55 +21,41 @@ pub struct Frame<'a> {
 }
 
 impl<'a> Frame<'a> {
-    pub(crate) fn decode(doff: u8, buf: &'a [u8]) -> Result<Self, crate::Error> {
+    /// Decode the frame body, returning the frame along with its raw payload
+    ///
+    /// The message carried by a transfer is not decoded here: it may be spread over multiple
+    /// frames, so it is up to the receiving link to decode it once the delivery is complete.
+    pub(crate) fn decode(doff: u8, buf: &'a [u8]) -> Result<(Self, &'a [u8]), crate::Error> {
         let (channel, buf) = buf.split_at(2);
         let channel =
             u16::from_be_bytes(channel.try_into().map_err(|_| crate::Error::InvalidData)?);
 
-        let (extended, buf) = buf.split_at((doff - 2) as usize);
+        // The data offset counts 4-byte words, including the 8-byte frame header
+        let (extended, buf) = buf.split_at(doff as usize * 4 - 8);
         let extended_header = if !extended.is_empty() {
             Some(extended)
         } else {
             None
         };
 
-        let (performative, buf) = de::deserialize(buf)?;
-        let message = if !buf.is_empty() {
-            let mut deserializer = de::Deserializer::from_bytes(buf);
-            let mut reader = deserializer.reader()?;
-            let header = reader.read(&mut deserializer, true)?;
-            let delivery_annotations = reader.read(&mut deserializer, true)?;
-            let message_annotations = reader.read(&mut deserializer, true)?;
-            let properties = reader.read(&mut deserializer, true)?;
-            let application_properties = reader.read(&mut deserializer, false)?;
-            // TODO: allow deserialization of messages that don't have a body
-            let body = Some(Body::deserialize(&mut deserializer)?);
-            reader.next(&mut deserializer)?;
-            let footer = reader.read(&mut deserializer, false)?;
-
-            Some(Message {
-                header,
-                delivery_annotations,
-                message_annotations,
-                properties,
-                application_properties,
-                body,
-                footer,
-            })
-        } else {
-            None
+        let (performative, payload) = de::deserialize(buf)?;
+        let message = match performative {
+            Performative::Transfer(_) => None,
+            _ if payload.is_empty() => None,
+            _ => Some(Message::decode(payload)?),
         };
 
-        Ok(Self {
+        let frame = Self {
             channel,
             extended_header,
             performative,
             message,
-        })
+        };
+        Ok((frame, payload))
     }
 }
 
-#[derive(Debug, Default, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
 pub struct Message<'a> {
     pub header: Option<Header>,
     #[serde(borrow)]
@@ -80,8 +67,103 @@ pub struct Message<'a> {
     pub footer: Option<Footer<'a>>,
 }
 
+impl<'a> Message<'a> {
+    /// Decode a message from the (reassembled) payload of a delivery
+    pub fn decode(buf: &'a [u8]) -> Result<Self, crate::Error> {
+        let mut deserializer = de::Deserializer::from_bytes(buf);
+        let mut reader = deserializer.reader()?;
+        let header = reader.read(&mut deserializer, true)?;
+        let delivery_annotations = reader.read(&mut deserializer, true)?;
+        let message_annotations = reader.read(&mut deserializer, true)?;
+        let properties = reader.read(&mut deserializer, true)?;
+        let application_properties = reader.read(&mut deserializer, false)?;
+        if application_properties.is_none() {
+            // The body is decoded along with its descriptor
+            reader.unread(&mut deserializer);
+        }
+        // TODO: allow deserialization of messages that don't have a body
+        let body = Some(Body::deserialize(&mut deserializer)?);
+        reader.next(&mut deserializer)?;
+        let footer = reader.read(&mut deserializer, false)?;
+
+        Ok(Message {
+            header,
+            delivery_annotations,
+            message_annotations,
+            properties,
+            application_properties,
+            body,
+            footer,
+        })
+    }
+
+    pub fn builder() -> MessageBuilder<'a> {
+        MessageBuilder::default()
+    }
+
+    /// Copy any borrowed data, so the message can outlive the buffer it was decoded from
+    pub fn into_owned(self) -> Message<'static> {
+        Message {
+            header: self.header,
+            delivery_annotations: self
+                .delivery_annotations
+                .map(DeliveryAnnotations::into_owned),
+            message_annotations: self.message_annotations.map(MessageAnnotations::into_owned),
+            properties: self.properties.map(Properties::into_owned),
+            application_properties: self
+                .application_properties
+                .map(ApplicationProperties::into_owned),
+            body: self.body.map(Body::into_owned),
+            footer: self.footer.map(Footer::into_owned),
+        }
+    }
+
+    /// A message borrowing all of its data from this one
+    pub fn to_borrowed(&self) -> Message<'_> {
+        Message {
+            header: self.header.clone(),
+            delivery_annotations: self
+                .delivery_annotations
+                .as_ref()
+                .map(|da| da.to_borrowed()),
+            message_annotations: self.message_annotations.as_ref().map(|ma| ma.to_borrowed()),
+            properties: self.properties.as_ref().map(|props| props.to_borrowed()),
+            application_properties: self
+                .application_properties
+                .as_ref()
+                .map(|ap| ap.to_borrowed()),
+            body: self.body.as_ref().map(|body| body.to_borrowed()),
+            footer: self.footer.as_ref().map(|footer| footer.to_borrowed()),
+        }
+    }
+
+    /// Encode the message sections, as carried in the payload of a delivery
+    pub fn encode(&self, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        if let Some(header) = &self.header {
+            ser::into_bytes(header, buf)?;
+        }
+        if let Some(da) = &self.delivery_annotations {
+            ser::into_bytes(da, buf)?;
+        }
+        if let Some(ma) = &self.message_annotations {
+            ser::into_bytes(ma, buf)?;
+        }
+        if let Some(props) = &self.properties {
+            ser::into_bytes(props, buf)?;
+        }
+        if let Some(ap) = &self.application_properties {
+            ser::into_bytes(ap, buf)?;
+        }
+        ser::into_bytes(&self.body, buf)?;
+        if let Some(footer) = &self.footer {
+            ser::into_bytes(footer, buf)?;
+        }
+        Ok(())
+    }
+}
+
 #[amqp(descriptor("amqp:header:list", 0x0000_0000_0000_0070))]
-#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
 pub struct Header {
     pub durable: Option<bool>,
     pub priority: Option<u8>,
@@ -91,59 +173,394 @@ pub struct Header {
 }
 
 #[amqp(descriptor("amqp:delivery-annotations:map", 0x0000_0000_0000_0071))]
-#[derive(Debug, PartialEq, Eq, Serialize)]
-pub struct DeliveryAnnotations<'a>(#[serde(borrow)] pub HashMap<&'a str, &'a str>);
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DeliveryAnnotations<'a>(
+    #[serde(borrow, deserialize_with = "borrowed_map::<_, BorrowedStr, _>")]
+    pub  HashMap<Cow<'a, str>, Cow<'a, str>>,
+);
+
+impl DeliveryAnnotations<'_> {
+    pub fn into_owned(self) -> DeliveryAnnotations<'static> {
+        DeliveryAnnotations(owned_strings(self.0))
+    }
+
+    pub fn to_borrowed(&self) -> DeliveryAnnotations<'_> {
+        DeliveryAnnotations(borrowed_strings(&self.0))
+    }
+}
 
 #[amqp(descriptor("amqp:message-annotations:map", 0x0000_0000_0000_0072))]
-#[derive(Debug, PartialEq, Serialize)]
-pub struct MessageAnnotations<'a>(#[serde(borrow)] pub HashMap<&'a str, Any<'a>>);
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MessageAnnotations<'a>(
+    #[serde(borrow, deserialize_with = "borrowed_map::<_, Any, _>")]
+    pub  HashMap<Cow<'a, str>, Any<'a>>,
+);
+
+impl MessageAnnotations<'_> {
+    pub fn into_owned(self) -> MessageAnnotations<'static> {
+        MessageAnnotations(owned_values(self.0))
+    }
+
+    pub fn to_borrowed(&self) -> MessageAnnotations<'_> {
+        MessageAnnotations(borrowed_values(&self.0))
+    }
+}
 
 #[amqp(descriptor("amqp:properties:list", 0x0000_0000_0000_0073))]
-#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
 pub struct Properties<'a> {
+    #[serde(borrow, default, deserialize_with = "borrowed_str")]
     pub message_id: Option<Cow<'a, str>>,
-    #[serde(with = "serde_bytes")]
-    pub user_id: Option<&'a [u8]>,
-    pub to: Option<&'a str>,
-    pub subject: Option<&'a str>,
+    #[serde(borrow, with = "serde_bytes")]
+    pub user_id: Option<Cow<'a, [u8]>>,
+    #[serde(borrow, default, deserialize_with = "borrowed_str")]
+    pub to: Option<Cow<'a, str>>,
+    #[serde(borrow, default, deserialize_with = "borrowed_str")]
+    pub subject: Option<Cow<'a, str>>,
+    #[serde(borrow, default, deserialize_with = "borrowed_str")]
     pub reply_to: Option<Cow<'a, str>>,
-    pub correlation_id: Option<&'a str>,
-    pub content_type: Option<&'a str>,
-    pub content_encoding: Option<&'a str>,
-    pub absolute_expiry_time: Option<i64>,
-    pub creation_time: Option<i64>,
-    pub group_id: Option<&'a str>,
+    #[serde(borrow, default, deserialize_with = "borrowed_str")]
+    pub correlation_id: Option<Cow<'a, str>>,
+    #[serde(borrow, default, deserialize_with = "borrowed_str")]
+    pub content_type: Option<Cow<'a, str>>,
+    #[serde(borrow, default, deserialize_with = "borrowed_str")]
+    pub content_encoding: Option<Cow<'a, str>>,
+    pub absolute_expiry_time: Option<Timestamp>,
+    pub creation_time: Option<Timestamp>,
+    #[serde(borrow, default, deserialize_with = "borrowed_str")]
+    pub group_id: Option<Cow<'a, str>>,
     pub group_sequence: Option<u32>,
-    pub reply_to_group_id: Option<&'a str>,
+    #[serde(borrow, default, deserialize_with = "borrowed_str")]
+    pub reply_to_group_id: Option<Cow<'a, str>>,
+}
+
+impl Properties<'_> {
+    pub fn into_owned(self) -> Properties<'static> {
+        Properties {
+            message_id: self.message_id.map(owned),
+            user_id: self.user_id.map(owned),
+            to: self.to.map(owned),
+            subject: self.subject.map(owned),
+            reply_to: self.reply_to.map(owned),
+            correlation_id: self.correlation_id.map(owned),
+            content_type: self.content_type.map(owned),
+            content_encoding: self.content_encoding.map(owned),
+            absolute_expiry_time: self.absolute_expiry_time,
+            creation_time: self.creation_time,
+            group_id: self.group_id.map(owned),
+            group_sequence: self.group_sequence,
+            reply_to_group_id: self.reply_to_group_id.map(owned),
+        }
+    }
+
+    pub fn to_borrowed(&self) -> Properties<'_> {
+        Properties {
+            message_id: self.message_id.as_deref().map(Cow::Borrowed),
+            user_id: self.user_id.as_deref().map(Cow::Borrowed),
+            to: self.to.as_deref().map(Cow::Borrowed),
+            subject: self.subject.as_deref().map(Cow::Borrowed),
+            reply_to: self.reply_to.as_deref().map(Cow::Borrowed),
+            correlation_id: self.correlation_id.as_deref().map(Cow::Borrowed),
+            content_type: self.content_type.as_deref().map(Cow::Borrowed),
+            content_encoding: self.content_encoding.as_deref().map(Cow::Borrowed),
+            absolute_expiry_time: self.absolute_expiry_time,
+            creation_time: self.creation_time,
+            group_id: self.group_id.as_deref().map(Cow::Borrowed),
+            group_sequence: self.group_sequence,
+            reply_to_group_id: self.reply_to_group_id.as_deref().map(Cow::Borrowed),
+        }
+    }
 }
 
 #[amqp(descriptor("amqp:application-properties:map", 0x0000_0000_0000_0074))]
-#[derive(Debug, PartialEq, Serialize)]
-pub struct ApplicationProperties<'a>(#[serde(borrow)] pub HashMap<&'a str, Any<'a>>);
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ApplicationProperties<'a>(
+    #[serde(borrow, deserialize_with = "borrowed_map::<_, Any, _>")]
+    pub  HashMap<Cow<'a, str>, Any<'a>>,
+);
+
+impl ApplicationProperties<'_> {
+    pub fn into_owned(self) -> ApplicationProperties<'static> {
+        ApplicationProperties(owned_values(self.0))
+    }
+
+    pub fn to_borrowed(&self) -> ApplicationProperties<'_> {
+        ApplicationProperties(borrowed_values(&self.0))
+    }
+}
 
 #[amqp]
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub enum Body<'a> {
     Data(Data<'a>),
     Sequence(Sequence),
     Value(Value<'a>),
 }
 
+impl Body<'_> {
+    pub fn into_owned(self) -> Body<'static> {
+        match self {
+            Body::Data(Data(data)) => Body::Data(Data(owned(data))),
+            Body::Sequence(seq) => Body::Sequence(seq),
+            Body::Value(Value(value)) => Body::Value(Value(value.into_owned())),
+        }
+    }
+
+    pub fn to_borrowed(&self) -> Body<'_> {
+        match self {
+            Body::Data(Data(data)) => Body::Data(Data(Cow::Borrowed(data))),
+            Body::Sequence(seq) => Body::Sequence(seq.clone()),
+            Body::Value(Value(value)) => Body::Value(Value(value.to_borrowed())),
+        }
+    }
+}
+
 #[amqp(descriptor("amqp:data:binary", 0x0000_0000_0000_0075))]
-#[derive(Debug, Default, PartialEq, Eq, Serialize)]
-pub struct Data<'a>(#[serde(with = "serde_bytes")] pub &'a [u8]);
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Data<'a>(#[serde(borrow, with = "serde_bytes")] pub Cow<'a, [u8]>);
 
 #[amqp(descriptor("amqp:amqp-sequence:list", 0x0000_0000_0000_0076))]
-#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
 pub struct Sequence {}
 
 #[amqp(descriptor("amqp:value:*", 0x0000_0000_0000_0077))]
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct Value<'a>(#[serde(borrow)] pub Any<'a>);
 
 #[amqp(descriptor("amqp:footer:map", 0x0000_0000_0000_0078))]
-#[derive(Debug, PartialEq, Eq, Serialize)]
-pub struct Footer<'a>(#[serde(borrow)] pub HashMap<&'a str, &'a str>);
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Footer<'a>(
+    #[serde(borrow, deserialize_with = "borrowed_map::<_, BorrowedStr, _>")]
+    pub  HashMap<Cow<'a, str>, Cow<'a, str>>,
+);
+
+impl Footer<'_> {
+    pub fn into_owned(self) -> Footer<'static> {
+        Footer(owned_strings(self.0))
+    }
+
+    pub fn to_borrowed(&self) -> Footer<'_> {
+        Footer(borrowed_strings(&self.0))
+    }
+}
+
+/// Builds a `Message` section by section
+///
+/// ```
+/// use oasis_amqp::amqp::Message;
+///
+/// let message = Message::builder()
+///     .message_id("request-1")
+///     .reply_to("replies")
+///     .application_property("method-name", "ping")
+///     .data(&b"hello"[..])
+///     .build();
+/// assert_eq!(message.properties.unwrap().reply_to.as_deref(), Some("replies"));
+/// ```
+#[derive(Debug, Default)]
+pub struct MessageBuilder<'a> {
+    message: Message<'a>,
+}
+
+impl<'a> MessageBuilder<'a> {
+    pub fn header(mut self, header: Header) -> Self {
+        self.message.header = Some(header);
+        self
+    }
+
+    pub fn delivery_annotation(
+        mut self,
+        key: impl Into<Cow<'a, str>>,
+        value: impl Into<Cow<'a, str>>,
+    ) -> Self {
+        let annotations = self
+            .message
+            .delivery_annotations
+            .get_or_insert_with(Default::default);
+        annotations.0.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn message_annotation(
+        mut self,
+        key: impl Into<Cow<'a, str>>,
+        value: impl Into<Any<'a>>,
+    ) -> Self {
+        let annotations = self
+            .message
+            .message_annotations
+            .get_or_insert_with(Default::default);
+        annotations.0.insert(key.into(), value.into());
+        self
+    }
+
+    /// Set all properties at once, replacing any set before
+    pub fn properties(mut self, properties: Properties<'a>) -> Self {
+        self.message.properties = Some(properties);
+        self
+    }
+
+    pub fn message_id(mut self, id: impl Into<Cow<'a, str>>) -> Self {
+        self.properties_mut().message_id = Some(id.into());
+        self
+    }
+
+    pub fn user_id(mut self, id: impl Into<Cow<'a, [u8]>>) -> Self {
+        self.properties_mut().user_id = Some(id.into());
+        self
+    }
+
+    pub fn to(mut self, address: impl Into<Cow<'a, str>>) -> Self {
+        self.properties_mut().to = Some(address.into());
+        self
+    }
+
+    pub fn subject(mut self, subject: impl Into<Cow<'a, str>>) -> Self {
+        self.properties_mut().subject = Some(subject.into());
+        self
+    }
+
+    pub fn reply_to(mut self, address: impl Into<Cow<'a, str>>) -> Self {
+        self.properties_mut().reply_to = Some(address.into());
+        self
+    }
+
+    pub fn correlation_id(mut self, id: impl Into<Cow<'a, str>>) -> Self {
+        self.properties_mut().correlation_id = Some(id.into());
+        self
+    }
+
+    pub fn content_type(mut self, content_type: impl Into<Cow<'a, str>>) -> Self {
+        self.properties_mut().content_type = Some(content_type.into());
+        self
+    }
+
+    pub fn application_property(
+        mut self,
+        key: impl Into<Cow<'a, str>>,
+        value: impl Into<Any<'a>>,
+    ) -> Self {
+        let properties = self
+            .message
+            .application_properties
+            .get_or_insert_with(Default::default);
+        properties.0.insert(key.into(), value.into());
+        self
+    }
+
+    /// Use binary data as the body
+    pub fn data(mut self, data: impl Into<Cow<'a, [u8]>>) -> Self {
+        self.message.body = Some(Body::Data(Data(data.into())));
+        self
+    }
+
+    /// Use a single AMQP value as the body
+    pub fn value(mut self, value: impl Into<Any<'a>>) -> Self {
+        self.message.body = Some(Body::Value(Value(value.into())));
+        self
+    }
+
+    pub fn footer(mut self, footer: Footer<'a>) -> Self {
+        self.message.footer = Some(footer);
+        self
+    }
+
+    pub fn build(self) -> Message<'a> {
+        self.message
+    }
+
+    fn properties_mut(&mut self) -> &mut Properties<'a> {
+        self.message.properties.get_or_insert_with(Default::default)
+    }
+}
+
+fn owned<T: ToOwned + ?Sized>(cow: Cow<'_, T>) -> Cow<'static, T> {
+    Cow::Owned(cow.into_owned())
+}
+
+fn owned_strings(
+    map: HashMap<Cow<'_, str>, Cow<'_, str>>,
+) -> HashMap<Cow<'static, str>, Cow<'static, str>> {
+    map.into_iter().map(|(k, v)| (owned(k), owned(v))).collect()
+}
+
+fn borrowed_strings<'a>(
+    map: &'a HashMap<Cow<'_, str>, Cow<'_, str>>,
+) -> HashMap<Cow<'a, str>, Cow<'a, str>> {
+    map.iter()
+        .map(|(k, v)| (Cow::Borrowed(&**k), Cow::Borrowed(&**v)))
+        .collect()
+}
+
+fn owned_values(map: HashMap<Cow<'_, str>, Any<'_>>) -> HashMap<Cow<'static, str>, Any<'static>> {
+    map.into_iter()
+        .map(|(k, v)| (owned(k), v.into_owned()))
+        .collect()
+}
+
+fn borrowed_values<'a>(map: &'a HashMap<Cow<'_, str>, Any<'_>>) -> HashMap<Cow<'a, str>, Any<'a>> {
+    map.iter()
+        .map(|(k, v)| (Cow::Borrowed(&**k), v.to_borrowed()))
+        .collect()
+}
+
+/// A string that borrows from the input where possible, unlike a plain `Cow<str>`
+#[derive(Deserialize)]
+struct BorrowedStr<'a>(#[serde(borrow)] Cow<'a, str>);
+
+impl<'a> From<BorrowedStr<'a>> for Cow<'a, str> {
+    fn from(s: BorrowedStr<'a>) -> Self {
+        s.0
+    }
+}
+
+/// Deserialize an optional string, borrowing it from the input
+///
+/// `#[serde(borrow)]` alone only borrows a bare `Cow<str>`, not one wrapped in an `Option`.
+fn borrowed_str<'de, D>(deserializer: D) -> Result<Option<Cow<'de, str>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<BorrowedStr>::deserialize(deserializer)?.map(Cow::from))
+}
+
+/// Deserialize a map with string keys, borrowing the keys from the input
+///
+/// The values are deserialized as `V` and converted to `T`, so that `BorrowedStr` values can
+/// borrow as well.
+fn borrowed_map<'de, D, V, T>(deserializer: D) -> Result<HashMap<Cow<'de, str>, T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    V: Deserialize<'de>,
+    T: From<V>,
+{
+    struct Visitor<V, T>(PhantomData<(V, T)>);
+
+    impl<'de, V, T> serde::de::Visitor<'de> for Visitor<V, T>
+    where
+        V: Deserialize<'de>,
+        T: From<V>,
+    {
+        type Value = HashMap<Cow<'de, str>, T>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a map with string keys")
+        }
+
+        fn visit_map<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+        where
+            A: serde::de::MapAccess<'de>,
+        {
+            let mut map = HashMap::with_capacity(access.size_hint().unwrap_or(0).min(1024));
+            while let Some((BorrowedStr(key), value)) = access.next_entry::<BorrowedStr, V>()? {
+                map.insert(key, T::from(value));
+            }
+            Ok(map)
+        }
+    }
+
+    deserializer.deserialize_map(Visitor(PhantomData))
+}
 
 #[allow(clippy::large_enum_variant)]
 #[amqp]
@@ -153,9 +570,10 @@ pub enum Performative<'a> {
     Begin(Begin<'a>),
     Attach(Attach<'a>),
     Flow(Flow<'a>),
-    Transfer(Transfer),
-    Disposition(Disposition),
+    Transfer(Transfer<'a>),
+    Disposition(Disposition<'a>),
     Detach(Detach<'a>),
+    End(End<'a>),
     Close(Close<'a>),
 }
 
@@ -170,7 +588,8 @@ pub struct Open<'a> {
     pub outgoing_locales: Option<Vec<&'a str>>,
     pub incoming_locales: Option<Vec<&'a str>>,
     pub offered_capabilities: Option<Vec<&'a str>>,
-    pub properties: Option<Vec<(&'a [u8], &'a [u8])>>,
+    pub desired_capabilities: Option<Vec<&'a str>>,
+    pub properties: Option<Fields<'a>>,
 }
 
 #[amqp(descriptor("amqp:begin:list", 0x0000_0000_0000_0011))]
@@ -184,7 +603,7 @@ pub struct Begin<'a> {
     #[serde(borrow)]
     pub offered_capabilities: Option<Vec<&'a str>>,
     pub desired_capabilities: Option<Vec<&'a str>>,
-    pub properties: Option<Vec<(&'a [u8], &'a [u8])>>,
+    pub properties: Option<Fields<'a>>,
 }
 
 #[amqp(descriptor("amqp:attach:list", 0x0000_0000_0000_0012))]
@@ -204,11 +623,11 @@ pub struct Attach<'a> {
     pub max_message_size: Option<u64>,
     pub offered_capabilities: Option<Vec<&'a str>>,
     pub desired_capabilities: Option<Vec<&'a str>>,
-    pub properties: Option<Vec<(&'a Bytes, &'a Bytes)>>,
+    pub properties: Option<Fields<'a>>,
 }
 
 #[amqp(descriptor("amqp:flow:list", 0x0000_0000_0000_0013))]
-#[derive(Debug, PartialEq, Eq, Serialize)]
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
 pub struct Flow<'a> {
     pub next_incoming_id: Option<u32>,
     pub incoming_window: u32,
@@ -221,12 +640,12 @@ pub struct Flow<'a> {
     pub drain: Option<bool>,
     pub echo: Option<bool>,
     #[serde(borrow)]
-    pub properties: Option<Vec<(&'a Bytes, &'a Bytes)>>,
+    pub properties: Option<Fields<'a>>,
 }
 
 #[amqp(descriptor("amqp:transfer:list", 0x0000_0000_0000_0014))]
 #[derive(Debug, Default, Eq, PartialEq, Serialize)]
-pub struct Transfer {
+pub struct Transfer<'a> {
     pub handle: u32,
     pub delivery_id: Option<u32>,
     #[serde(with = "serde_bytes")]
@@ -235,7 +654,8 @@ pub struct Transfer {
     pub settled: Option<bool>,
     pub more: Option<bool>,
     pub rcv_settle_mode: Option<ReceiverSettleMode>,
-    pub state: Option<DeliveryState>,
+    #[serde(borrow)]
+    pub state: Option<DeliveryState<'a>>,
     pub resume: Option<bool>,
     pub aborted: Option<bool>,
     pub batchable: Option<bool>,
@@ -243,12 +663,13 @@ pub struct Transfer {
 
 #[amqp(descriptor("amqp:disposition:list", 0x0000_0000_0000_0015))]
 #[derive(Debug, Eq, PartialEq, Serialize)]
-pub struct Disposition {
+pub struct Disposition<'a> {
     pub role: Role,
     pub first: u32,
     pub last: Option<u32>,
     pub settled: Option<bool>,
-    pub state: Option<DeliveryState>,
+    #[serde(borrow)]
+    pub state: Option<DeliveryState<'a>>,
     pub batchable: Option<bool>,
 }
 
@@ -261,6 +682,13 @@ pub struct Detach<'a> {
     pub error: Option<Error<'a>>,
 }
 
+#[amqp(descriptor("amqp:end:list", 0x0000_0000_0000_0017))]
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct End<'a> {
+    #[serde(borrow)]
+    pub error: Option<Error<'a>>,
+}
+
 #[amqp(descriptor("amqp:close:list", 0x0000_0000_0000_0018))]
 #[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct Close<'a> {
@@ -272,12 +700,12 @@ pub struct Close<'a> {
 #[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct Error<'a> {
     #[serde(borrow)]
-    condition: &'a str,
-    description: &'a str,
-    info: Option<Vec<(&'a Bytes, &'a Bytes)>>,
+    pub condition: &'a str,
+    pub description: Option<&'a str>,
+    pub info: Option<Fields<'a>>,
 }
 
-#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
 pub enum Role {
     Sender,
     Receiver,
@@ -304,27 +732,22 @@ pub struct Source<'a> {
     pub timeout: Option<u32>,
     pub dynamic: Option<bool>,
     #[serde(borrow)]
-    pub dynamic_node_properties: Option<Vec<(&'a str, &'a str)>>,
+    pub dynamic_node_properties: Option<Fields<'a>>,
     pub distribution_mode: Option<DistributionMode>,
-    pub filter: Option<Vec<(&'a str, &'a str)>>,
-    pub default_outcome: Option<Outcome>,
+    pub filter: Option<Fields<'a>>,
+    pub default_outcome: Option<Outcome<'a>>,
     pub outcomes: Option<Vec<&'a str>>,
     pub capabilities: Option<Vec<&'a str>>,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
 pub enum TerminusDurability {
+    #[default]
     None,
     Configuration,
     UnsettledState,
 }
 
-impl Default for TerminusDurability {
-    fn default() -> Self {
-        TerminusDurability::None
-    }
-}
-
 impl Serialize for TerminusDurability {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -348,10 +771,10 @@ pub enum DistributionMode {
 #[amqp]
 #[derive(Debug, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "kebab-case")]
-pub enum DeliveryState {
+pub enum DeliveryState<'a> {
     Received(Received),
     Accepted(Accepted),
-    Rejected(Rejected),
+    Rejected(Rejected<'a>),
     Released(Released),
     Modified(Modified),
     Declared(Declared),
@@ -361,10 +784,10 @@ pub enum DeliveryState {
 #[amqp]
 #[derive(Debug, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "kebab-case")]
-pub enum Outcome {
+pub enum Outcome<'a> {
     Received(Received),
     Accepted(Accepted),
-    Rejected(Rejected),
+    Rejected(Rejected<'a>),
     Released(Released),
     Modified(Modified),
     Declared(Declared),
@@ -372,7 +795,10 @@ pub enum Outcome {
 
 #[amqp(descriptor("amqp:received:list", 0x0000_0000_0000_0023))]
 #[derive(Debug, PartialEq, Eq, Serialize)]
-pub struct Received {}
+pub struct Received {
+    pub section_number: u32,
+    pub section_offset: u64,
+}
 
 #[amqp(descriptor("amqp:accepted:list", 0x0000_0000_0000_0024))]
 #[derive(Debug, PartialEq, Eq, Serialize)]
@@ -380,15 +806,21 @@ pub struct Accepted {}
 
 #[amqp(descriptor("amqp:rejected:list", 0x0000_0000_0000_0025))]
 #[derive(Debug, PartialEq, Eq, Serialize)]
-pub struct Rejected {}
+pub struct Rejected<'a> {
+    #[serde(borrow)]
+    pub error: Option<Error<'a>>,
+}
 
 #[amqp(descriptor("amqp:released:list", 0x0000_0000_0000_0026))]
 #[derive(Debug, PartialEq, Eq, Serialize)]
 pub struct Released {}
 
 #[amqp(descriptor("amqp:modified:list", 0x0000_0000_0000_0027))]
-#[derive(Debug, PartialEq, Eq, Serialize)]
-pub struct Modified {}
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Modified {
+    pub delivery_failed: Option<bool>,
+    pub undeliverable_here: Option<bool>,
+}
 
 #[amqp(descriptor("amqp:declared:list", 0x0000_0000_0000_0033))]
 #[derive(Debug, PartialEq, Eq, Serialize)]
@@ -407,7 +839,7 @@ pub struct Target<'a> {
     pub timeout: Option<u32>,
     pub dynamic: Option<bool>,
     #[serde(borrow)]
-    pub dynamic_node_properties: Option<Vec<(&'a str, &'a str)>>,
+    pub dynamic_node_properties: Option<Fields<'a>>,
     pub capabilities: Option<Vec<&'a str>>,
 }
 
@@ -420,20 +852,47 @@ pub enum ExpiryPolicy {
     Never,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
 pub enum SenderSettleMode {
     Unsettled,
     Settled,
+    #[default]
     Mixed,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+impl Serialize for SenderSettleMode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u8(match self {
+            SenderSettleMode::Unsettled => 0,
+            SenderSettleMode::Settled => 1,
+            SenderSettleMode::Mixed => 2,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
 pub enum ReceiverSettleMode {
+    #[default]
     First,
     Second,
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize)]
+impl Serialize for ReceiverSettleMode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u8(match self {
+            ReceiverSettleMode::First => 0,
+            ReceiverSettleMode::Second => 1,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize)]
 #[serde(rename = "amqp:symbol")]
 pub struct Symbol<'a>(pub &'a str);
 
@@ -466,7 +925,8 @@ impl<'de> serde::de::Visitor<'de> for SymbolVisitor {
     }
 }
 
-#[derive(Deserialize, PartialEq, Eq)]
+/// An AMQP list, whose elements can be of different types
+#[derive(Clone, Deserialize, PartialEq, Eq)]
 #[serde(transparent)]
 pub struct List<T>(pub Vec<T>);
 
@@ -507,7 +967,150 @@ where
     }
 }
 
-#[derive(Debug, PartialEq, Serialize)]
+/// An AMQP array, whose elements all share a single constructor
+#[derive(Clone, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Array<T>(pub Vec<T>);
+
+impl<T> fmt::Debug for Array<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T> Default for Array<T> {
+    fn default() -> Self {
+        Array(Vec::new())
+    }
+}
+
+impl<T> From<Vec<T>> for Array<T> {
+    fn from(v: Vec<T>) -> Self {
+        Array(v)
+    }
+}
+
+impl<T> Serialize for Array<T>
+where
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut s = serializer.serialize_seq(Some(self.0.len()))?;
+        for elem in self.0.iter() {
+            s.serialize_element(elem)?;
+        }
+        s.end()
+    }
+}
+
+/// An AMQP map, with its entries in the order they were encoded
+///
+/// Keys need not be hashable, since AMQP allows any type as a map key.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Map<K, V>(pub Vec<(K, V)>);
+
+impl<K, V> Map<K, V> {
+    /// The value for the first entry with a key equal to `key`
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: PartialEq<Q>,
+        Q: ?Sized,
+    {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+}
+
+impl<K, V> fmt::Debug for Map<K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|(k, v)| (k, v)))
+            .finish()
+    }
+}
+
+impl<K, V> Default for Map<K, V> {
+    fn default() -> Self {
+        Map(Vec::new())
+    }
+}
+
+impl<K, V> From<Vec<(K, V)>> for Map<K, V> {
+    fn from(v: Vec<(K, V)>) -> Self {
+        Map(v)
+    }
+}
+
+impl<K, V> Serialize for Map<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut s = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in self.0.iter() {
+            s.serialize_entry(key, value)?;
+        }
+        s.end()
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for Map<K, V>
+where
+    K: Deserialize<'de>,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor<K, V>(PhantomData<Map<K, V>>);
+
+        impl<'de, K, V> serde::de::Visitor<'de> for Visitor<K, V>
+        where
+            K: Deserialize<'de>,
+            V: Deserialize<'de>,
+        {
+            type Value = Map<K, V>;
+
+            fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Formatter::write_str(fmt, "a map")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                // The size hint comes from the peer, so don't trust it too far
+                let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0).min(1024));
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Map(entries))
+            }
+        }
+
+        deserializer.deserialize_map(Visitor(PhantomData))
+    }
+}
+
+/// A map of symbols to values, as used for extension points throughout the protocol
+pub type Fields<'a> = Map<Symbol<'a>, crate::Value<'a>>;
+
+/// Any AMQP value, as found in application properties and annotations
+#[derive(Clone, Debug, PartialEq)]
 pub enum Any<'a> {
     None,
     Bool(bool),
@@ -521,9 +1124,179 @@ pub enum Any<'a> {
     I64(i64),
     F32(f32),
     F64(f64),
-    Bytes(#[serde(with = "serde_bytes")] &'a [u8]),
-    Symbol(&'a str),
-    Str(&'a str),
+    /// IEEE 754-2008 decimal32, in its binary encoding
+    Decimal32([u8; 4]),
+    Decimal64([u8; 8]),
+    Decimal128([u8; 16]),
+    Char(char),
+    Timestamp(Timestamp),
+    Uuid([u8; 16]),
+    Bytes(Cow<'a, [u8]>),
+    Symbol(Cow<'a, str>),
+    Str(Cow<'a, str>),
+    List(Vec<Any<'a>>),
+    /// Map entries in the order they were encoded; keys can be of any type
+    Map(Vec<(Any<'a>, Any<'a>)>),
+    /// A sequence of values, which must all be of the same type
+    Array(Vec<Any<'a>>),
+}
+
+impl Any<'_> {
+    pub fn into_owned(self) -> Any<'static> {
+        match self {
+            Any::None => Any::None,
+            Any::Bool(v) => Any::Bool(v),
+            Any::U8(v) => Any::U8(v),
+            Any::U16(v) => Any::U16(v),
+            Any::U32(v) => Any::U32(v),
+            Any::U64(v) => Any::U64(v),
+            Any::I8(v) => Any::I8(v),
+            Any::I16(v) => Any::I16(v),
+            Any::I32(v) => Any::I32(v),
+            Any::I64(v) => Any::I64(v),
+            Any::F32(v) => Any::F32(v),
+            Any::F64(v) => Any::F64(v),
+            Any::Decimal32(v) => Any::Decimal32(v),
+            Any::Decimal64(v) => Any::Decimal64(v),
+            Any::Decimal128(v) => Any::Decimal128(v),
+            Any::Char(v) => Any::Char(v),
+            Any::Timestamp(v) => Any::Timestamp(v),
+            Any::Uuid(v) => Any::Uuid(v),
+            Any::Bytes(v) => Any::Bytes(owned(v)),
+            Any::Symbol(v) => Any::Symbol(owned(v)),
+            Any::Str(v) => Any::Str(owned(v)),
+            Any::List(items) => Any::List(items.into_iter().map(Any::into_owned).collect()),
+            Any::Map(entries) => Any::Map(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                    .collect(),
+            ),
+            Any::Array(items) => Any::Array(items.into_iter().map(Any::into_owned).collect()),
+        }
+    }
+
+    pub fn to_borrowed(&self) -> Any<'_> {
+        match self {
+            Any::Bytes(v) => Any::Bytes(Cow::Borrowed(v)),
+            Any::Symbol(v) => Any::Symbol(Cow::Borrowed(v)),
+            Any::Str(v) => Any::Str(Cow::Borrowed(v)),
+            Any::List(items) => Any::List(items.iter().map(Any::to_borrowed).collect()),
+            Any::Map(entries) => Any::Map(
+                entries
+                    .iter()
+                    .map(|(k, v)| (k.to_borrowed(), v.to_borrowed()))
+                    .collect(),
+            ),
+            Any::Array(items) => Any::Array(items.iter().map(Any::to_borrowed).collect()),
+            // All other variants are plain values
+            other => other.clone(),
+        }
+    }
+}
+
+macro_rules! any_from {
+    ($($ty:ty => $variant:ident),*) => {
+        $(impl From<$ty> for Any<'_> {
+            fn from(v: $ty) -> Self {
+                Any::$variant(v)
+            }
+        })*
+    };
+}
+
+any_from!(
+    bool => Bool, u8 => U8, u16 => U16, u32 => U32, u64 => U64,
+    i8 => I8, i16 => I16, i32 => I32, i64 => I64, f32 => F32, f64 => F64,
+    char => Char, Timestamp => Timestamp
+);
+
+impl<'a> From<&'a str> for Any<'a> {
+    fn from(s: &'a str) -> Self {
+        Any::Str(Cow::Borrowed(s))
+    }
+}
+
+impl From<String> for Any<'_> {
+    fn from(s: String) -> Self {
+        Any::Str(Cow::Owned(s))
+    }
+}
+
+impl<'a> From<&'a [u8]> for Any<'a> {
+    fn from(bytes: &'a [u8]) -> Self {
+        Any::Bytes(Cow::Borrowed(bytes))
+    }
+}
+
+impl From<Vec<u8>> for Any<'_> {
+    fn from(bytes: Vec<u8>) -> Self {
+        Any::Bytes(Cow::Owned(bytes))
+    }
+}
+
+impl<'a> From<Symbol<'a>> for Any<'a> {
+    fn from(s: Symbol<'a>) -> Self {
+        Any::Symbol(Cow::Borrowed(s.0))
+    }
+}
+
+impl Serialize for Any<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Any::None => serializer.serialize_unit(),
+            Any::Bool(v) => serializer.serialize_bool(*v),
+            Any::U8(v) => serializer.serialize_u8(*v),
+            Any::U16(v) => serializer.serialize_u16(*v),
+            Any::U32(v) => serializer.serialize_u32(*v),
+            Any::U64(v) => serializer.serialize_u64(*v),
+            Any::I8(v) => serializer.serialize_i8(*v),
+            Any::I16(v) => serializer.serialize_i16(*v),
+            Any::I32(v) => serializer.serialize_i32(*v),
+            Any::I64(v) => serializer.serialize_i64(*v),
+            Any::F32(v) => serializer.serialize_f32(*v),
+            Any::F64(v) => serializer.serialize_f64(*v),
+            Any::Decimal32(v) => {
+                serializer.serialize_newtype_struct("amqp:decimal32", Bytes::new(v))
+            }
+            Any::Decimal64(v) => {
+                serializer.serialize_newtype_struct("amqp:decimal64", Bytes::new(v))
+            }
+            Any::Decimal128(v) => {
+                serializer.serialize_newtype_struct("amqp:decimal128", Bytes::new(v))
+            }
+            Any::Char(v) => serializer.serialize_char(*v),
+            Any::Timestamp(v) => v.serialize(serializer),
+            Any::Uuid(v) => serializer.serialize_newtype_struct("amqp:uuid", Bytes::new(v)),
+            Any::Bytes(v) => serializer.serialize_bytes(v),
+            Any::Symbol(v) => serializer.serialize_newtype_struct("amqp:symbol", &**v),
+            Any::Str(v) => serializer.serialize_str(v),
+            Any::List(items) => {
+                let mut list = serializer.serialize_tuple(items.len())?;
+                for item in items {
+                    list.serialize_element(item)?;
+                }
+                list.end()
+            }
+            Any::Map(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+            Any::Array(items) => {
+                let mut array = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    array.serialize_element(item)?;
+                }
+                array.end()
+            }
+        }
+    }
 }
 
 impl<'a, 'de: 'a> Deserialize<'de> for Any<'a> {
@@ -531,18 +1304,37 @@ impl<'a, 'de: 'a> Deserialize<'de> for Any<'a> {
     where
         D: serde::Deserializer<'de>,
     {
+        // The deserializer identifies the variant by the value's format code
         enum AnyType {
             None,
+            Bool,
+            U8,
+            U16,
+            U32,
+            U64,
             I8,
+            I16,
             I32,
             I64,
+            F32,
+            F64,
+            Decimal32,
+            Decimal64,
+            Decimal128,
+            Char,
+            Timestamp,
+            Uuid,
             Bytes,
+            Symbol,
             Str,
+            List,
+            Map,
+            Array,
         }
 
         struct FieldVisitor;
 
-        impl<'de: 'a, 'a> serde::de::Visitor<'de> for FieldVisitor {
+        impl<'de> serde::de::Visitor<'de> for FieldVisitor {
             type Value = AnyType;
             fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
                 fmt::Formatter::write_str(fmt, "variant identifier")
@@ -552,18 +1344,38 @@ impl<'a, 'de: 'a> Deserialize<'de> for Any<'a> {
             where
                 E: serde::de::Error,
             {
-                match value {
-                    0x40 => Ok(AnyType::None),
-                    0x51 => Ok(AnyType::I8),
-                    0x54 => Ok(AnyType::I32),
-                    0x55 | 0x81 => Ok(AnyType::I64),
-                    0xa1 => Ok(AnyType::Str),
-                    0xb0 => Ok(AnyType::Bytes),
-                    _ => Err(serde::de::Error::invalid_value(
-                        serde::de::Unexpected::Unsigned(value),
-                        &"constructor code",
-                    )),
-                }
+                Ok(match value {
+                    0x40 => AnyType::None,
+                    0x41 | 0x42 | 0x56 => AnyType::Bool,
+                    0x50 => AnyType::U8,
+                    0x60 => AnyType::U16,
+                    0x43 | 0x52 | 0x70 => AnyType::U32,
+                    0x44 | 0x53 | 0x80 => AnyType::U64,
+                    0x51 => AnyType::I8,
+                    0x61 => AnyType::I16,
+                    0x54 | 0x71 => AnyType::I32,
+                    0x55 | 0x81 => AnyType::I64,
+                    0x72 => AnyType::F32,
+                    0x82 => AnyType::F64,
+                    0x74 => AnyType::Decimal32,
+                    0x84 => AnyType::Decimal64,
+                    0x94 => AnyType::Decimal128,
+                    0x73 => AnyType::Char,
+                    0x83 => AnyType::Timestamp,
+                    0x98 => AnyType::Uuid,
+                    0xa0 | 0xb0 => AnyType::Bytes,
+                    0xa3 | 0xb3 => AnyType::Symbol,
+                    0xa1 | 0xb1 => AnyType::Str,
+                    0x45 | 0xc0 | 0xd0 => AnyType::List,
+                    0xc1 | 0xd1 => AnyType::Map,
+                    0xe0 | 0xf0 => AnyType::Array,
+                    _ => {
+                        return Err(serde::de::Error::invalid_value(
+                            serde::de::Unexpected::Unsigned(value),
+                            &"constructor code",
+                        ))
+                    }
+                })
             }
         }
 
@@ -593,49 +1405,78 @@ impl<'a, 'de: 'a> Deserialize<'de> for Any<'a> {
             where
                 A: serde::de::EnumAccess<'de>,
             {
-                let val = match serde::de::EnumAccess::variant(data) {
-                    Ok(val) => val,
-                    Err(err) => return Err(err),
-                };
-
-                match val {
-                    (AnyType::None, variant) => Result::map(
-                        serde::de::VariantAccess::newtype_variant::<()>(variant),
-                        |_| Any::None,
-                    ),
-                    (AnyType::I8, variant) => Result::map(
-                        serde::de::VariantAccess::newtype_variant::<i8>(variant),
-                        Any::I8,
-                    ),
-                    (AnyType::I32, variant) => Result::map(
-                        serde::de::VariantAccess::newtype_variant::<i32>(variant),
-                        Any::I32,
-                    ),
-                    (AnyType::I64, variant) => Result::map(
-                        serde::de::VariantAccess::newtype_variant::<i64>(variant),
-                        Any::I64,
-                    ),
-                    (AnyType::Bytes, variant) => Result::map(
-                        serde::de::VariantAccess::newtype_variant::<&[u8]>(variant),
-                        Any::Bytes,
-                    ),
-                    (AnyType::Str, variant) => Result::map(
-                        serde::de::VariantAccess::newtype_variant::<&str>(variant),
-                        Any::Str,
-                    ),
-                }
+                use serde::de::VariantAccess;
+                let (ty, variant) = serde::de::EnumAccess::variant(data)?;
+                Ok(match ty {
+                    AnyType::None => variant.newtype_variant::<()>().map(|_| Any::None)?,
+                    AnyType::Bool => Any::Bool(variant.newtype_variant()?),
+                    AnyType::U8 => Any::U8(variant.newtype_variant()?),
+                    AnyType::U16 => Any::U16(variant.newtype_variant()?),
+                    AnyType::U32 => Any::U32(variant.newtype_variant()?),
+                    AnyType::U64 => Any::U64(variant.newtype_variant()?),
+                    AnyType::I8 => Any::I8(variant.newtype_variant()?),
+                    AnyType::I16 => Any::I16(variant.newtype_variant()?),
+                    AnyType::I32 => Any::I32(variant.newtype_variant()?),
+                    AnyType::I64 => Any::I64(variant.newtype_variant()?),
+                    AnyType::F32 => Any::F32(variant.newtype_variant()?),
+                    AnyType::F64 => Any::F64(variant.newtype_variant()?),
+                    AnyType::Decimal32 => Any::Decimal32(fixed(variant.newtype_variant()?)?),
+                    AnyType::Decimal64 => Any::Decimal64(fixed(variant.newtype_variant()?)?),
+                    AnyType::Decimal128 => Any::Decimal128(fixed(variant.newtype_variant()?)?),
+                    AnyType::Char => Any::Char(variant.newtype_variant()?),
+                    AnyType::Timestamp => Any::Timestamp(variant.newtype_variant()?),
+                    AnyType::Uuid => Any::Uuid(fixed(variant.newtype_variant()?)?),
+                    AnyType::Bytes => {
+                        Any::Bytes(Cow::Borrowed(variant.newtype_variant::<&[u8]>()?))
+                    }
+                    AnyType::Symbol => {
+                        Any::Symbol(Cow::Borrowed(variant.newtype_variant::<Symbol<'_>>()?.0))
+                    }
+                    AnyType::Str => Any::Str(Cow::Borrowed(variant.newtype_variant::<&str>()?)),
+                    AnyType::List => Any::List(variant.newtype_variant()?),
+                    AnyType::Map => Any::Map(variant.newtype_variant::<Map<_, _>>()?.0),
+                    AnyType::Array => Any::Array(variant.newtype_variant()?),
+                })
             }
         }
 
-        const VARIANTS: &[&str] = &["None", "I8", "I32", "I64", "Str"];
+        fn fixed<E: serde::de::Error, const N: usize>(bytes: &[u8]) -> Result<[u8; N], E> {
+            bytes
+                .try_into()
+                .map_err(|_| E::invalid_length(bytes.len(), &"fixed-width value"))
+        }
+
+        const VARIANTS: &[&str] = &["Any"];
         serde::Deserializer::deserialize_enum(
             deserializer,
             "Any",
             VARIANTS,
             Visitor {
-                marker: PhantomData::default(),
-                lifetime: PhantomData::default(),
+                marker: PhantomData,
+                lifetime: PhantomData,
             },
         )
     }
 }
+
+/// A point in time, in milliseconds since the Unix epoch
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Timestamp(pub i64);
+
+impl Serialize for Timestamp {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_newtype_struct("amqp:timestamp", &self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        i64::deserialize(deserializer).map(Timestamp)
+    }
+}
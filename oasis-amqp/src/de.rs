@@ -31,9 +31,9 @@ impl<'de> Deserializer<'de> {
     }
 
     fn next(&mut self) -> Result<u8> {
-        let res = self.peek();
+        let res = self.peek()?;
         self.input = &self.input[1..];
-        res
+        Ok(res)
     }
 
     fn assume(&mut self, assumed: u8) -> Result<()> {
@@ -45,10 +45,23 @@ impl<'de> Deserializer<'de> {
         }
     }
 
-    fn read_u32(&mut self) -> Result<u32> {
-        let (val, rest) = self.input.split_at(4);
+    /// Consume the next `len` bytes of input
+    fn take(&mut self, len: usize) -> Result<&'de [u8]> {
+        if self.input.len() < len {
+            return Err(Error::UnexpectedEnd);
+        }
+
+        let (val, rest) = self.input.split_at(len);
         self.input = rest;
-        Ok(u32::from_be_bytes(val.try_into()?))
+        Ok(val)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into()?))
     }
 
     fn parse_bool(&mut self) -> Result<bool> {
@@ -74,29 +87,26 @@ impl<'de> Deserializer<'de> {
     }
 
     fn parse_u64(&mut self) -> Result<u64> {
-        Ok(match self.next()? {
+        Ok(match self.next_constructor()? {
             0x44 => 0,
             0x53 => self.next()? as u64,
-            0x80 => {
-                let (val, rest) = self.input.split_at(8);
-                self.input = rest;
-                let val = val.try_into()?;
-                u64::from_be_bytes(val)
-            }
-            t => return Err(InvalidFormatCode::new("u64", t).into()),
+            0x80 => self.read_u64()?,
+            t => return Err(InvalidFormatCode::new("u64", t as u8).into()),
         })
     }
 
+    /// Binary data, including the fixed-width decimal and uuid types
     fn parse_bytes(&mut self) -> Result<&'de [u8]> {
         let len = match self.next_constructor()? {
             0xa0 | 0xa3 => self.next()? as usize,
             0xb0 | 0xb3 => self.read_u32()? as usize,
+            0x74 => 4,
+            0x84 => 8,
+            0x94 | 0x98 => 16,
             t => return Err(InvalidFormatCode::new("bytes", t as u8).into()),
         };
 
-        let (val, rest) = self.input.split_at(len);
-        self.input = rest;
-        Ok(val)
+        self.take(len)
     }
 
     fn peek_constructor(&mut self) -> Result<usize> {
@@ -115,40 +125,48 @@ impl<'de> Deserializer<'de> {
 
     // size, len, constructor
     fn composite(&mut self) -> Result<(usize, usize, Option<usize>)> {
-        Ok(match self.next()? {
-            0x45 => (0, 0, None),
-            0xc0 => (self.next()? as usize - 1, self.next()? as usize, None),
-            0xc1 => (self.next()? as usize - 1, self.next()? as usize, None),
-            0xd0 => (
-                self.read_u32()? as usize - 4,
-                self.read_u32()? as usize,
+        // The encoded size includes the count (and the array constructor), which have been
+        // read by the time the remaining size is known.
+        let (size, len, constructor) = match self.next_constructor()? as u8 {
+            0x45 => return Ok((0, 0, None)),
+            0xc0 | 0xc1 => (
+                (self.next()? as usize).checked_sub(1),
+                self.next()? as usize,
                 None,
             ),
-            0xd1 => (
-                self.read_u32()? as usize - 4,
+            0xd0 | 0xd1 => (
+                (self.read_u32()? as usize).checked_sub(4),
                 self.read_u32()? as usize,
                 None,
             ),
             0xe0 => (
-                self.next()? as usize - 2,
+                (self.next()? as usize).checked_sub(2),
                 self.next()? as usize,
                 Some(self.next()? as usize),
             ),
             0xf0 => (
-                self.read_u32()? as usize - 8,
+                (self.read_u32()? as usize).checked_sub(5),
                 self.read_u32()? as usize,
-                Some(self.read_u32()? as usize),
+                Some(self.next()? as usize),
             ),
             t => return Err(InvalidFormatCode::new("composite type", t).into()),
-        })
+        };
+
+        match size {
+            Some(size) => Ok((size, len, constructor)),
+            None => Err(Error::InvalidData),
+        }
     }
 
     pub fn reader(&mut self) -> Result<DescribedReader<'de>> {
-        DescribedReader::new(self.parse_descriptor()?)
+        let start = self.input;
+        let mut reader = DescribedReader::new(self.parse_descriptor()?)?;
+        reader.start = start;
+        Ok(reader)
     }
 }
 
-impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     type Error = Error;
 
     fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
@@ -168,11 +186,14 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
             0x55 | 0x81 | 0x83 => self.deserialize_i64(visitor),
             0x72 => self.deserialize_f32(visitor),
             0x82 => self.deserialize_f64(visitor),
-            0x45 | 0xc0 | 0xd0 => self.deserialize_seq(visitor),
-            0x74 | 0x84 | 0x94 => unimplemented!(), // decimal32, decimal64, decimal128
+            0x45 | 0xc0 | 0xd0 | 0xe0 | 0xf0 => self.deserialize_seq(visitor),
+            0xc1 | 0xd1 => self.deserialize_map(visitor),
             0x73 => self.deserialize_char(visitor),
             0xa1 | 0xb1 => self.deserialize_str(visitor),
-            0xa0 | 0xa3 | 0xb0 | 0xb3 => self.deserialize_bytes(visitor),
+            // Decimals and uuids are passed on as their raw bytes
+            0xa0 | 0xa3 | 0xb0 | 0xb3 | 0x74 | 0x84 | 0x94 | 0x98 => {
+                self.deserialize_bytes(visitor)
+            }
             t => Err(InvalidFormatCode::new("any", t as u8).into()),
         }
     }
@@ -188,30 +209,31 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        self.assume(0x50)?;
-        visitor.visit_u8(self.next()?)
+        match self.next_constructor()? {
+            0x50 => visitor.visit_u8(self.next()?),
+            t => Err(InvalidFormatCode::new("u8", t as u8).into()),
+        }
     }
 
     fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.assume(0x60)?;
-        let (val, rest) = self.input.split_at(2);
-        self.input = rest;
-        let val = val.try_into()?;
-        visitor.visit_u16(u16::from_be_bytes(val))
+        match self.next_constructor()? {
+            0x60 => visitor.visit_u16(u16::from_be_bytes(self.take(2)?.try_into()?)),
+            t => Err(InvalidFormatCode::new("u16", t as u8).into()),
+        }
     }
 
     fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.next()? {
+        match self.next_constructor()? {
             0x43 => visitor.visit_u32(0),
             0x52 => visitor.visit_u32(self.next()? as u32),
             0x70 => visitor.visit_u32(self.read_u32()?),
-            t => Err(InvalidFormatCode::new("u32", t).into()),
+            t => Err(InvalidFormatCode::new("u32", t as u8).into()),
         }
     }
 
@@ -226,34 +248,30 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        self.assume(0x51)?;
-        visitor.visit_i8(self.next()? as i8)
+        match self.next_constructor()? {
+            0x51 => visitor.visit_i8(self.next()? as i8),
+            t => Err(InvalidFormatCode::new("i8", t as u8).into()),
+        }
     }
 
     fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.assume(0x61)?;
-        let (val, rest) = self.input.split_at(2);
-        self.input = rest;
-        let val = val.try_into()?;
-        visitor.visit_i16(i16::from_be_bytes(val))
+        match self.next_constructor()? {
+            0x61 => visitor.visit_i16(i16::from_be_bytes(self.take(2)?.try_into()?)),
+            t => Err(InvalidFormatCode::new("i16", t as u8).into()),
+        }
     }
 
     fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        match self.next()? {
-            0x54 => visitor.visit_i32(self.next()? as i32),
-            0x71 => {
-                let (val, rest) = self.input.split_at(4);
-                self.input = rest;
-                let val = val.try_into()?;
-                visitor.visit_i32(i32::from_be_bytes(val))
-            }
-            t => Err(InvalidFormatCode::new("i32", t).into()),
+        match self.next_constructor()? {
+            0x54 => visitor.visit_i32(self.next()? as i8 as i32),
+            0x71 => visitor.visit_i32(self.read_u32()? as i32),
+            t => Err(InvalidFormatCode::new("i32", t as u8).into()),
         }
     }
 
@@ -261,15 +279,11 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        match self.next()? {
-            0x55 => visitor.visit_i64(self.next()? as i64),
-            0x81 | 0x83 => {
-                let (val, rest) = self.input.split_at(8);
-                self.input = rest;
-                let val = val.try_into()?;
-                visitor.visit_i64(i64::from_be_bytes(val))
-            }
-            t => Err(InvalidFormatCode::new("i64", t).into()),
+        match self.next_constructor()? {
+            0x55 => visitor.visit_i64(self.next()? as i8 as i64),
+            // Timestamps are milliseconds since the Unix epoch
+            0x81 | 0x83 => visitor.visit_i64(self.read_u64()? as i64),
+            t => Err(InvalidFormatCode::new("i64", t as u8).into()),
         }
     }
 
@@ -277,26 +291,33 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        self.assume(0x72)?;
-        visitor.visit_f32(f32::from_bits(self.read_u32()?))
+        match self.next_constructor()? {
+            0x72 => visitor.visit_f32(f32::from_bits(self.read_u32()?)),
+            t => Err(InvalidFormatCode::new("f32", t as u8).into()),
+        }
     }
 
     fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.assume(0x82)?;
-        let (val, rest) = self.input.split_at(8);
-        self.input = rest;
-        let val = u64::from_be_bytes(val.try_into()?);
-        visitor.visit_f64(f64::from_bits(val))
+        match self.next_constructor()? {
+            0x82 => visitor.visit_f64(f64::from_bits(self.read_u64()?)),
+            t => Err(InvalidFormatCode::new("f64", t as u8).into()),
+        }
     }
 
-    fn deserialize_char<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        match self.next_constructor()? {
+            0x73 => match char::from_u32(self.read_u32()?) {
+                Some(c) => visitor.visit_char(c),
+                None => Err(Error::InvalidData),
+            },
+            t => Err(InvalidFormatCode::new("char", t as u8).into()),
+        }
     }
 
     fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
@@ -309,9 +330,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
             t => return Err(InvalidFormatCode::new("str", t as u8).into()),
         };
 
-        let (val, rest) = self.input.split_at(len);
-        self.input = rest;
-        match str::from_utf8(val) {
+        match str::from_utf8(self.take(len)?) {
             Ok(s) => visitor.visit_borrowed_str(s),
             Err(_) => Err(Error::InvalidData),
         }
@@ -342,10 +361,10 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        if self.input.is_empty() {
+        if self.input.is_empty() && self.constructor.is_none() {
             visitor.visit_none()
-        } else if self.peek()? == 0x40 {
-            self.assume(0x40)?;
+        } else if self.peek_constructor()? == 0x40 {
+            self.next_constructor()?;
             visitor.visit_none()
         } else {
             visitor.visit_some(self)
@@ -356,8 +375,10 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        self.assume(0x40)?;
-        visitor.visit_unit()
+        match self.next_constructor()? {
+            0x40 => visitor.visit_unit(),
+            t => Err(InvalidFormatCode::new("null", t as u8).into()),
+        }
     }
 
     fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
@@ -380,13 +401,12 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     {
         // Ignore potential descriptors, which are likely here because
         // Corda confuses (heterogeneous) lists and (homogeneous) arrays.
-        if self.peek()? == 0 {
+        if self.constructor.is_none() && self.peek()? == 0 {
             let _ = self.parse_descriptor()?;
         }
 
         let (size, len, constructor) = self.composite()?;
-        let (input, rest) = self.input.split_at(size);
-        self.input = rest;
+        let input = self.take(size)?;
 
         let mut nested = Deserializer {
             input,
@@ -399,11 +419,11 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         })
     }
 
-    fn deserialize_tuple<V>(self, _len: usize, _visitor: V) -> Result<V::Value>
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        self.deserialize_seq(visitor)
     }
 
     fn deserialize_tuple_struct<V>(
@@ -435,13 +455,12 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        if self.peek()? == 0 {
+        if self.constructor.is_none() && self.peek()? == 0 {
             let _ = self.parse_descriptor()?;
         }
 
         let (size, _, constructor) = self.composite()?;
-        let (input, rest) = self.input.split_at(size);
-        self.input = rest;
+        let input = self.take(size)?;
 
         let mut nested = Deserializer {
             input,
@@ -463,14 +482,17 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        if self.peek_constructor()? == 0 {
-            self.assume(0)?;
-            visitor.visit_enum(Enum { de: self })
-        } else {
-            self.any = name == "Any";
+        // Self-describing types are identified by their constructor, even if it is described
+        if name == "Any" || name == "Value" {
+            self.any = true;
             let res = visitor.visit_enum(Enum { de: self });
             self.any = false;
             res
+        } else if self.peek_constructor()? == 0 {
+            self.assume(0)?;
+            visitor.visit_enum(Enum { de: self })
+        } else {
+            visitor.visit_enum(Enum { de: self })
         }
     }
 
@@ -481,12 +503,8 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         if !self.any {
             match self.peek_constructor()? {
                 0x56 | 0x41 | 0x42 => visitor.visit_u64(if self.parse_bool()? { 1 } else { 0 }),
-                0x50 => {
-                    self.assume(0x50)?;
-                    let id = self.next()?;
-                    visitor.visit_u64(id as u64)
-                }
-                0x43 | 0x52 | 0x70 => visitor.visit_u64(self.read_u32()? as u64),
+                0x50 => self.deserialize_u8(visitor),
+                0x43 | 0x52 | 0x70 => self.deserialize_u32(visitor),
                 0x44 | 0x53 | 0x80 => self.deserialize_u64(visitor),
                 0xa3 | 0xb3 => self.deserialize_bytes(visitor),
                 t => Err(InvalidFormatCode::new("variant identifier", t as u8).into()),
@@ -506,22 +524,33 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
 
 pub struct DescribedReader<'de> {
     descriptor: Option<Descriptor<'de>>,
+    /// Input starting at the current descriptor
+    start: &'de [u8],
 }
 
 impl<'de> DescribedReader<'de> {
     pub fn new(descriptor: Descriptor<'de>) -> Result<Self> {
         Ok(Self {
             descriptor: Some(descriptor),
+            start: &[],
         })
     }
 
     pub fn next(&mut self, deserializer: &mut Deserializer<'de>) -> Result<()> {
         if !deserializer.input.is_empty() {
+            self.start = deserializer.input;
             self.descriptor = Some(deserializer.parse_descriptor()?);
         }
         Ok(())
     }
 
+    /// Put back the current descriptor, so that it can be read as part of the next value
+    pub fn unread(&mut self, deserializer: &mut Deserializer<'de>) {
+        if self.descriptor.take().is_some() && !self.start.is_empty() {
+            deserializer.input = self.start;
+        }
+    }
+
     pub fn read<T: Described + serde::de::Deserialize<'de>>(
         &mut self,
         deserializer: &mut Deserializer<'de>,
@@ -606,11 +635,19 @@ impl<'de, 'a> VariantAccess<'de> for Enum<'a, 'de> {
         seed.deserialize(self.de)
     }
 
-    fn tuple_variant<V>(self, _len: usize, _visitor: V) -> Result<V::Value>
+    /// A described value, as its descriptor followed by the value itself
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        unimplemented!()
+        if self.de.constructor.is_some() {
+            return Err(Error::Deserialization(
+                "arrays of described values are not supported".into(),
+            ));
+        }
+
+        self.de.assume(0)?;
+        visitor.visit_seq(Access { de: self.de, len })
     }
 
     fn struct_variant<V>(self, _fields: &'static [&'static str], _visitor: V) -> Result<V::Value>
@@ -1,15 +1,30 @@
 use std::array::TryFromSliceError;
+use std::sync::Arc;
+use std::time::Duration;
 use std::{fmt, io};
 
 use thiserror::Error;
 
 pub mod amqp;
 pub mod de;
+pub mod link;
 pub mod proto;
 pub mod sasl;
+pub mod scram;
 pub mod ser;
+pub mod server;
+pub mod session;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod value;
+#[cfg(feature = "websocket")]
+pub mod ws;
 
+pub use link::{Credit, Delivery, IncomingLink, Outcome, Receiver, Sender};
 pub use proto::Client;
+pub use server::{Listener, ServerConnection};
+pub use session::Session;
+pub use value::Value;
 
 pub trait Described {
     const NAME: Option<&'static [u8]>;
@@ -36,6 +51,117 @@ pub enum Error {
     TrailingCharacters,
 }
 
+#[derive(Debug, Error)]
+pub enum ConnectionError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("codec error: {0}")]
+    Codec(Error),
+    #[error("unexpected frame: expected {expected}, found {found}")]
+    UnexpectedFrame {
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[error("connection closed by peer{}", remote_cause(.0))]
+    RemoteClose(Option<RemoteError>),
+    #[error("session ended by peer{}", remote_cause(.0))]
+    RemoteEnd(Option<RemoteError>),
+    #[error("link detached by peer{}", remote_cause(.0))]
+    RemoteDetach(Option<RemoteError>),
+    #[error("link detached because of a peer error: {0}")]
+    LocalDetach(RemoteError),
+    #[error("SASL authentication failed with code {code:?}")]
+    Sasl {
+        code: sasl::Code,
+        additional_data: Option<Vec<u8>>,
+    },
+    #[error("no acceptable SASL mechanism offered by server (offered: {0:?})")]
+    SaslMechanisms(Vec<sasl::Mechanism>),
+    #[error("SCRAM authentication failed: {0}")]
+    Scram(String),
+    #[error("SASL mechanism {0} is not supported")]
+    UnsupportedSaslMechanism(sasl::Mechanism),
+    #[error("cannot send {frame} frame in connection state {state:?}")]
+    IllegalSend {
+        state: proto::ConnectionState,
+        frame: &'static str,
+    },
+    #[error("received {frame} frame in connection state {state:?}")]
+    IllegalReceive {
+        state: proto::ConnectionState,
+        frame: &'static str,
+    },
+    #[error("received frame on unmapped channel {0}")]
+    UnmappedChannel(u16),
+    #[error("no free channels left on the connection")]
+    ChannelsExhausted,
+    #[error("no free handles left on the session")]
+    HandlesExhausted,
+    #[error("incoming link can only be accepted as {0:?}")]
+    LinkRole(amqp::Role),
+    #[cfg(feature = "tls")]
+    #[error("TLS error: {0}")]
+    Tls(#[from] tokio_rustls::rustls::Error),
+    #[cfg(feature = "tls")]
+    #[error("invalid TLS server name: {0}")]
+    InvalidServerName(String),
+    #[cfg(feature = "websocket")]
+    #[error("WebSocket error: {0}")]
+    WebSocket(#[from] tokio_tungstenite::tungstenite::Error),
+    #[error("no frames received from peer within idle timeout of {0:?}")]
+    IdleTimeout(Duration),
+    #[error("connection closed unexpectedly")]
+    Disconnected,
+    /// The connection failed while in use, every handle sees the same cause
+    #[error("connection failed: {0}")]
+    Failed(Arc<ConnectionError>),
+    #[error("message was not accepted by peer: {0:?}")]
+    NotAccepted(Outcome),
+}
+
+impl From<Error> for ConnectionError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => ConnectionError::Io(e),
+            e => ConnectionError::Codec(e),
+        }
+    }
+}
+
+/// Owned version of the `amqp:error` sent by the peer in `Close`, `End` or `Detach`
+///
+/// `ConnectionError::LocalDetach` also uses it for the error we detached a link with.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RemoteError {
+    pub condition: String,
+    pub description: Option<String>,
+}
+
+impl From<&amqp::Error<'_>> for RemoteError {
+    fn from(e: &amqp::Error<'_>) -> Self {
+        Self {
+            condition: e.condition.to_owned(),
+            description: e.description.map(|s| s.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.description {
+            Some(description) => write!(f, "{} ({})", self.condition, description),
+            None => write!(f, "{}", self.condition),
+        }
+    }
+}
+
+fn remote_cause(error: &Option<RemoteError>) -> String {
+    match error {
+        Some(error) => format!(": {}", error),
+        None => String::new(),
+    }
+}
+
 impl serde::de::Error for Error {
     fn custom<T>(msg: T) -> Self
     where
@@ -0,0 +1,1426 @@
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use bytes::BytesMut;
+use futures::Stream;
+use tokio_util::sync::ReusableBoxFuture;
+
+use crate::proto::{unexpected, BytesFrame, Frame, Inner, Shared};
+use crate::session::{amqp_frame, SessionData};
+use crate::{amqp, ConnectionError, RemoteError, Session};
+
+/// Builder for a sending link, created with `Session::sender()`
+pub struct SenderBuilder<'a> {
+    session: &'a Session,
+    target: &'a str,
+    name: Option<&'a str>,
+    source: Option<&'a str>,
+    settle_mode: amqp::SenderSettleMode,
+}
+
+impl<'a> SenderBuilder<'a> {
+    pub(crate) fn new(session: &'a Session, target: &'a str) -> Self {
+        Self {
+            session,
+            target,
+            name: None,
+            source: None,
+            settle_mode: amqp::SenderSettleMode::default(),
+        }
+    }
+
+    /// Set the link name (defaults to a name unique to the connection)
+    pub fn name(mut self, name: &'a str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Set the address of the local source terminus
+    pub fn source(mut self, address: &'a str) -> Self {
+        self.source = Some(address);
+        self
+    }
+
+    /// Set the settlement mode for deliveries sent on this link
+    ///
+    /// With `SenderSettleMode::Settled`, messages are sent pre-settled (at-most-once delivery).
+    /// Otherwise, they are only settled once the receiver has decided on the outcome.
+    pub fn settle_mode(mut self, mode: amqp::SenderSettleMode) -> Self {
+        self.settle_mode = mode;
+        self
+    }
+
+    /// Attach the link, waiting for the peer's `Attach` in response
+    pub async fn attach(self) -> Result<Sender, ConnectionError> {
+        let (handle, name) = self
+            .session
+            .allocate_handle(amqp::Role::Sender, self.name)?;
+
+        self.session
+            .attach(amqp::Attach {
+                name: &name,
+                handle,
+                role: amqp::Role::Sender,
+                snd_settle_mode: Some(self.settle_mode),
+                rcv_settle_mode: None,
+                source: Some(amqp::Source {
+                    address: self.source,
+                    ..Default::default()
+                }),
+                target: Some(amqp::Target {
+                    address: Some(self.target),
+                    ..Default::default()
+                }),
+                unsettled: None,
+                incomplete_unsettled: None,
+                initial_delivery_count: Some(0),
+                max_message_size: None,
+                offered_capabilities: None,
+                desired_capabilities: None,
+                properties: None,
+            })
+            .await?;
+
+        Ok(Sender {
+            link: Link::new(self.session, handle),
+            settled: self.settle_mode == amqp::SenderSettleMode::Settled,
+        })
+    }
+}
+
+/// Builder for a receiving link, created with `Session::receiver()`
+pub struct ReceiverBuilder<'a> {
+    session: &'a Session,
+    source: Option<&'a str>,
+    dynamic: bool,
+    name: Option<&'a str>,
+    target: Option<&'a str>,
+    credit: Credit,
+    max_message_size: u64,
+}
+
+impl<'a> ReceiverBuilder<'a> {
+    pub(crate) fn new(session: &'a Session, source: &'a str) -> Self {
+        Self {
+            session,
+            source: Some(source),
+            dynamic: false,
+            name: None,
+            target: None,
+            credit: Credit::Manual,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    pub(crate) fn dynamic(session: &'a Session) -> Self {
+        Self {
+            source: None,
+            dynamic: true,
+            ..Self::new(session, "")
+        }
+    }
+
+    /// Set the link name (defaults to a name unique to the connection)
+    pub fn name(mut self, name: &'a str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Set the address of the local target terminus
+    pub fn target(mut self, address: &'a str) -> Self {
+        self.target = Some(address);
+        self
+    }
+
+    /// Set the policy for issuing credit to the sender (defaults to `Credit::Manual`)
+    pub fn credit(mut self, credit: Credit) -> Self {
+        self.credit = credit;
+        self
+    }
+
+    /// Set the largest message the sender may deliver, or 0 for no limit
+    ///
+    /// Defaults to `DEFAULT_MAX_MESSAGE_SIZE`. The link is detached if the peer exceeds it.
+    pub fn max_message_size(mut self, size: u64) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Attach the link, waiting for the peer's `Attach` in response
+    pub async fn attach(self) -> Result<Receiver, ConnectionError> {
+        let (handle, name) = self
+            .session
+            .allocate_handle(amqp::Role::Receiver, self.name)?;
+
+        self.session
+            .attach(amqp::Attach {
+                name: &name,
+                handle,
+                role: amqp::Role::Receiver,
+                snd_settle_mode: None,
+                rcv_settle_mode: None,
+                source: Some(amqp::Source {
+                    address: self.source,
+                    dynamic: self.dynamic.then_some(true),
+                    ..Default::default()
+                }),
+                target: Some(amqp::Target {
+                    address: self.target,
+                    ..Default::default()
+                }),
+                unsettled: None,
+                incomplete_unsettled: None,
+                initial_delivery_count: None,
+                max_message_size: Some(self.max_message_size),
+                offered_capabilities: None,
+                desired_capabilities: None,
+                properties: None,
+            })
+            .await?;
+
+        let link = Link::new(self.session, handle);
+        link.update(|session| {
+            let data = session.link(handle)?;
+            data.credit = self.credit;
+            data.max_message_size = self.max_message_size;
+            Ok(session.replenish(link.channel, handle))
+        })?;
+        Ok(Receiver::new(link))
+    }
+}
+
+/// A link attached by the peer, returned by `Session::accept_link()`
+///
+/// The peer is waiting for our `Attach` in response, which is sent by accepting the link. If it
+/// is dropped without being accepted, the link is rejected. Either way, the answer is handed to
+/// the driver task to send, so there is nothing to wait for.
+pub struct IncomingLink {
+    session: Session,
+    handle: u32,
+    frame: BytesFrame,
+    address: Option<String>,
+    max_message_size: u64,
+    answered: bool,
+}
+
+impl IncomingLink {
+    pub(crate) fn new(session: Session, handle: u32, frame: BytesFrame) -> Self {
+        Self {
+            session,
+            handle,
+            frame,
+            address: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            answered: false,
+        }
+    }
+
+    /// The `Attach` sent by the peer
+    pub fn attach(&self) -> &amqp::Attach<'_> {
+        match self.frame.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Attach(attach),
+                ..
+            }) => attach,
+            _ => unreachable!("incoming link without attach"),
+        }
+    }
+
+    /// The role we take on this link, which is the opposite of the peer's
+    pub fn role(&self) -> amqp::Role {
+        match self.attach().role {
+            amqp::Role::Sender => amqp::Role::Receiver,
+            amqp::Role::Receiver => amqp::Role::Sender,
+        }
+    }
+
+    /// The address the peer wants to receive from, if it is the receiver
+    pub fn source(&self) -> Option<&str> {
+        self.attach()
+            .source
+            .as_ref()
+            .and_then(|source| source.address)
+    }
+
+    /// The address the peer wants to send to, if it is the sender
+    pub fn target(&self) -> Option<&str> {
+        self.attach()
+            .target
+            .as_ref()
+            .and_then(|target| target.address)
+    }
+
+    /// Whether the peer asks us to create a node for the local terminus
+    pub fn dynamic(&self) -> bool {
+        let attach = self.attach();
+        let dynamic = match self.role() {
+            amqp::Role::Sender => attach.source.as_ref().and_then(|source| source.dynamic),
+            amqp::Role::Receiver => attach.target.as_ref().and_then(|target| target.dynamic),
+        };
+        dynamic == Some(true)
+    }
+
+    /// Set the address of the local terminus, such as the node created for a dynamic link
+    pub fn address(mut self, address: &str) -> Self {
+        self.address = Some(address.to_owned());
+        self
+    }
+
+    /// Set the largest message the peer may send on a receiving link, or 0 for no limit
+    ///
+    /// Defaults to `DEFAULT_MAX_MESSAGE_SIZE`. The link is detached if the peer exceeds it.
+    pub fn max_message_size(mut self, size: u64) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Accept a link on which the peer receives, honouring its settlement mode
+    pub fn accept_sender(mut self) -> Result<Sender, ConnectionError> {
+        let settled = self.attach().snd_settle_mode == Some(amqp::SenderSettleMode::Settled);
+        let link = self.accept(amqp::Role::Sender)?;
+        Ok(Sender { link, settled })
+    }
+
+    /// Accept a link on which the peer sends, issuing credit according to `credit`
+    pub fn accept_receiver(mut self, credit: Credit) -> Result<Receiver, ConnectionError> {
+        let link = self.accept(amqp::Role::Receiver)?;
+        let handle = link.handle;
+        link.update(|session| {
+            session.link(handle)?.credit = credit;
+            Ok(session.replenish(link.channel, handle))
+        })?;
+        Ok(Receiver::new(link))
+    }
+
+    /// Refuse the link, letting the peer know why
+    pub fn reject(mut self, error: Option<amqp::Error<'_>>) -> Result<(), ConnectionError> {
+        self.refuse(error)
+    }
+
+    fn accept(&mut self, role: amqp::Role) -> Result<Link, ConnectionError> {
+        if role != self.role() {
+            return Err(ConnectionError::LinkRole(self.role()));
+        }
+
+        self.answered = true;
+        let peer = self.attach();
+        let local = self.address.as_deref();
+        let source = peer.source.as_ref().map(|source| amqp::Source {
+            address: match role {
+                amqp::Role::Sender => local.or(source.address),
+                amqp::Role::Receiver => source.address,
+            },
+            dynamic: source.dynamic,
+            ..Default::default()
+        });
+        let target = peer.target.as_ref().map(|target| amqp::Target {
+            address: match role {
+                amqp::Role::Sender => target.address,
+                amqp::Role::Receiver => local.or(target.address),
+            },
+            dynamic: target.dynamic,
+            ..Default::default()
+        });
+
+        let attach = amqp::Attach {
+            name: peer.name,
+            handle: self.handle,
+            role,
+            snd_settle_mode: peer.snd_settle_mode,
+            rcv_settle_mode: peer.rcv_settle_mode,
+            source,
+            target,
+            unsettled: None,
+            incomplete_unsettled: None,
+            initial_delivery_count: (role == amqp::Role::Sender).then_some(0),
+            max_message_size: (role == amqp::Role::Receiver).then_some(self.max_message_size),
+            offered_capabilities: None,
+            desired_capabilities: None,
+            properties: None,
+        };
+
+        {
+            let mut inner = self.session.shared.inner.lock().unwrap();
+            let link = inner.session(self.session.channel)?.link(self.handle)?;
+            link.state = LinkState::Attached;
+            link.max_message_size = self.max_message_size;
+            inner.queue(&amqp_frame(
+                self.session.channel,
+                amqp::Performative::Attach(attach),
+            ))?;
+        }
+        self.session.shared.flush();
+        Ok(Link::new(&self.session, self.handle))
+    }
+
+    /// Answer with an `Attach` without local terminus, followed by a `Detach` (section 2.6.3)
+    fn refuse(&mut self, error: Option<amqp::Error<'_>>) -> Result<(), ConnectionError> {
+        self.answered = true;
+        let (channel, handle) = (self.session.channel, self.handle);
+        let attach = amqp::Attach {
+            name: self.attach().name,
+            handle,
+            role: self.role(),
+            snd_settle_mode: None,
+            rcv_settle_mode: None,
+            source: None,
+            target: None,
+            unsettled: None,
+            incomplete_unsettled: None,
+            initial_delivery_count: None,
+            max_message_size: None,
+            offered_capabilities: None,
+            desired_capabilities: None,
+            properties: None,
+        };
+
+        {
+            let mut inner = self.session.shared.inner.lock().unwrap();
+            let link = inner.session(channel)?.link(handle)?;
+            link.state = LinkState::DetachSent;
+            inner.queue(&amqp_frame(channel, amqp::Performative::Attach(attach)))?;
+            let detach = amqp::Detach {
+                handle,
+                closed: Some(true),
+                error,
+            };
+            inner.queue(&amqp_frame(channel, amqp::Performative::Detach(detach)))?;
+        }
+        self.session.shared.flush();
+        Ok(())
+    }
+}
+
+impl Drop for IncomingLink {
+    fn drop(&mut self) {
+        if !self.answered {
+            let _ = self.refuse(None);
+        }
+    }
+}
+
+impl fmt::Debug for IncomingLink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IncomingLink")
+            .field("handle", &self.handle)
+            .field("attach", self.attach())
+            .finish()
+    }
+}
+
+/// Policy for issuing link credit on a `Receiver`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Credit {
+    /// Credit is only issued by calling `Receiver::flow()`
+    Manual,
+    /// Keep credit and buffered deliveries at the given amount
+    ///
+    /// Credit is topped up once less than half of the prefetch window is left.
+    Prefetch(u32),
+}
+
+/// The sending end of a link
+#[derive(Clone)]
+pub struct Sender {
+    link: Link,
+    /// Whether deliveries are sent pre-settled
+    settled: bool,
+}
+
+impl Sender {
+    /// Send a message, waiting for the receiver's outcome
+    ///
+    /// This waits until the peer grants credit to the link. Messages larger than the maximum
+    /// frame size are split up, and their frames sent as the peer's session window allows.
+    /// Pre-settled deliveries are considered `Outcome::Accepted` as soon as they have been
+    /// handed off to the connection.
+    pub async fn send(&self, message: amqp::Message<'_>) -> Result<Outcome, ConnectionError> {
+        let (channel, handle, settled) = (self.link.channel, self.link.handle, self.settled);
+        let mut payload = Vec::new();
+        message.encode(&mut payload)?;
+
+        // The tag is filled in along with the delivery id, once we have credit
+        let transfer = amqp::Transfer {
+            handle,
+            delivery_tag: Some(vec![0; 4]),
+            message_format: Some(0),
+            settled: Some(settled),
+            ..Default::default()
+        };
+        let max_frame_size = self.link.shared.inner.lock().unwrap().max_frame_size as usize;
+        let mut fragments = Some(Frame::fragment(
+            channel,
+            transfer,
+            &payload,
+            max_frame_size,
+        )?);
+
+        // Each frame takes up a slot in the peer's incoming window, so send them as it opens up
+        let pending = PendingSend::new(&self.link);
+        let mut transmission = None;
+        let id = self
+            .link
+            .shared
+            .wait(|inner| {
+                let session = inner.session(channel)?;
+                if session.link(handle)?.link_credit == 0
+                    || session.sending
+                    || session.remote_incoming_window == 0
+                {
+                    return Ok(None);
+                }
+
+                let id = session.next_delivery_id;
+                let frames = match fragments.take() {
+                    Some(fragments) => fragments.encode(id, id.to_be_bytes().to_vec())?,
+                    None => unreachable!("delivery numbered twice"),
+                };
+
+                // Hold on to the session until all frames are out, so delivery ids go in order
+                session.sending = true;
+                session.next_delivery_id = id.wrapping_add(1);
+                if !settled {
+                    session.deliveries.insert(id, None);
+                }
+
+                let link = session.link(handle)?;
+                link.delivery_count = link.delivery_count.wrapping_add(1);
+                link.link_credit -= 1;
+
+                let transmission = transmission.insert(Transmission {
+                    link: &self.link,
+                    frames: frames.into(),
+                });
+                transmission.next(inner)?;
+                Ok(Some(id))
+            })
+            .await?;
+        drop(pending);
+        self.link.shared.flush();
+
+        // Stop tracking the delivery if we are cancelled before the outcome arrives
+        let _pending = (!settled).then(|| PendingDelivery {
+            link: &self.link,
+            id,
+        });
+
+        if let Some(mut transmission) = transmission {
+            while !transmission.frames.is_empty() {
+                self.link
+                    .shared
+                    .wait(|inner| Ok(transmission.next(inner)?.then_some(())))
+                    .await?;
+                self.link.shared.flush();
+            }
+        }
+
+        if settled {
+            return Ok(Outcome::Accepted);
+        }
+
+        self.link
+            .shared
+            .wait(|inner| {
+                let session = inner.session(channel)?;
+                match session.deliveries.get(&id) {
+                    Some(Some(_)) => Ok(session.deliveries.remove(&id).flatten()),
+                    _ => session.link(handle).map(|_| None),
+                }
+            })
+            .await
+    }
+
+    /// Detach the link, waiting for the peer to detach its side
+    pub async fn close(self) -> Result<(), ConnectionError> {
+        self.link.close().await
+    }
+
+    /// Wait until the peer detaches the link or the connection fails, returning the reason
+    pub async fn detached(&self) -> ConnectionError {
+        let (channel, handle) = (self.link.channel, self.link.handle);
+        let result = self
+            .link
+            .shared
+            .wait::<Infallible>(|inner| inner.session(channel)?.link(handle).map(|_| None))
+            .await;
+        match result {
+            Ok(never) => match never {},
+            Err(error) => error,
+        }
+    }
+
+    /// Wait until the peer has granted credit to this link, returning the amount
+    pub async fn wait_for_credit(&self) -> Result<u32, ConnectionError> {
+        let (channel, handle) = (self.link.channel, self.link.handle);
+        self.link
+            .shared
+            .wait(|inner| {
+                let credit = inner.session(channel)?.link(handle)?.link_credit;
+                Ok((credit > 0).then_some(credit))
+            })
+            .await
+    }
+
+    /// The amount of credit the peer has granted to this link
+    pub fn credit(&self) -> u32 {
+        self.link.with(|link| link.link_credit).unwrap_or(0)
+    }
+
+    /// The number of deliveries sent on this link (modulo 2^32)
+    pub fn delivery_count(&self) -> u32 {
+        self.link.with(|link| link.delivery_count).unwrap_or(0)
+    }
+
+    pub fn handle(&self) -> u32 {
+        self.link.handle
+    }
+
+    pub fn state(&self) -> LinkState {
+        self.link.state()
+    }
+}
+
+/// An unsettled delivery sent on a link, whose outcome is being waited for
+///
+/// Dropping it removes the delivery from the session, whether or not the outcome has arrived.
+struct PendingDelivery<'a> {
+    link: &'a Link,
+    id: u32,
+}
+
+impl Drop for PendingDelivery<'_> {
+    fn drop(&mut self) {
+        let mut inner = match self.link.shared.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return,
+        };
+
+        if let Some(session) = inner.sessions.get_mut(&self.link.channel) {
+            session.deliveries.remove(&self.id);
+        }
+    }
+}
+
+/// The frames of a delivery that have yet to be sent, while holding the session's send lock
+///
+/// If it is dropped before all frames are out, the delivery is aborted.
+struct Transmission<'a> {
+    link: &'a Link,
+    frames: VecDeque<Vec<u8>>,
+}
+
+impl Transmission<'_> {
+    /// Queue as many frames as the peer's incoming window allows, returning false if none fit
+    fn next(&mut self, inner: &mut Inner) -> Result<bool, ConnectionError> {
+        let (channel, handle) = (self.link.channel, self.link.handle);
+        let session = inner.session(channel)?;
+        session.link(handle)?;
+        let count = (session.remote_incoming_window as usize).min(self.frames.len());
+        if count == 0 {
+            return Ok(false);
+        }
+
+        session.next_outgoing_id = session.next_outgoing_id.wrapping_add(count as u32);
+        session.remote_incoming_window -= count as u32;
+        let frames = self.frames.drain(..count).collect();
+        inner.queue_encoded(&transfer_frame(channel, handle), frames)?;
+        Ok(true)
+    }
+}
+
+impl Drop for Transmission<'_> {
+    fn drop(&mut self) {
+        let (channel, handle) = (self.link.channel, self.link.handle);
+        let mut inner = match self.link.shared.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return,
+        };
+
+        let session = match inner.sessions.get_mut(&channel) {
+            Some(session) => session,
+            None => return,
+        };
+        session.sending = false;
+
+        let attached =
+            matches!(session.links.get(&handle), Some(link) if link.state == LinkState::Attached);
+        if !self.frames.is_empty() && attached {
+            // The receiver discards what it has of the delivery, see section 2.6.14
+            session.next_outgoing_id = session.next_outgoing_id.wrapping_add(1);
+            session.remote_incoming_window = session.remote_incoming_window.saturating_sub(1);
+            let abort = amqp_frame(
+                channel,
+                amqp::Performative::Transfer(amqp::Transfer {
+                    handle,
+                    aborted: Some(true),
+                    ..Default::default()
+                }),
+            );
+            let _ = inner.queue(&abort);
+        }
+
+        drop(inner);
+        self.link.shared.flush();
+        self.link.shared.notify();
+    }
+}
+
+/// A `send()` waiting for link credit
+///
+/// While any are pending, a drain requested by the peer waits for them to use up the credit
+/// first; dropping the last one completes the drain.
+struct PendingSend<'a> {
+    link: &'a Link,
+}
+
+impl<'a> PendingSend<'a> {
+    fn new(link: &'a Link) -> Self {
+        let mut inner = link.shared.inner.lock().unwrap();
+        if let Ok(session) = inner.session(link.channel) {
+            if let Some(data) = session.links.get_mut(&link.handle) {
+                data.pending_sends += 1;
+            }
+        }
+        Self { link }
+    }
+}
+
+impl Drop for PendingSend<'_> {
+    fn drop(&mut self) {
+        let (channel, handle) = (self.link.channel, self.link.handle);
+        let mut inner = match self.link.shared.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return,
+        };
+
+        let session = match inner.sessions.get_mut(&channel) {
+            Some(session) => session,
+            None => return,
+        };
+        let link = match session.links.get_mut(&handle) {
+            Some(link) => link,
+            None => return,
+        };
+
+        link.pending_sends -= 1;
+        if !link.draining || (link.pending_sends > 0 && link.link_credit > 0) {
+            return;
+        }
+
+        link.drain();
+        let flow = session.flow(channel, Some((handle, &session.links[&handle])));
+        if inner.queue(&flow).is_ok() {
+            drop(inner);
+            self.link.shared.flush();
+        }
+    }
+}
+
+/// An empty transfer, standing in for encoded transfer frames in the connection state machine
+fn transfer_frame(channel: u16, handle: u32) -> Frame<'static> {
+    amqp_frame(
+        channel,
+        amqp::Performative::Transfer(amqp::Transfer {
+            handle,
+            ..Default::default()
+        }),
+    )
+}
+
+/// The outcome of a delivery, as decided by the receiver
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Accepted,
+    Rejected(Option<RemoteError>),
+    Released,
+    Modified(amqp::Modified),
+}
+
+impl Outcome {
+    /// Convert a terminal delivery state into an `Outcome`
+    pub(crate) fn from_state(state: &amqp::DeliveryState<'_>) -> Option<Self> {
+        Some(match state {
+            amqp::DeliveryState::Accepted(_) => Outcome::Accepted,
+            amqp::DeliveryState::Rejected(rejected) => {
+                Outcome::Rejected(rejected.error.as_ref().map(RemoteError::from))
+            }
+            amqp::DeliveryState::Released(_) => Outcome::Released,
+            amqp::DeliveryState::Modified(modified) => Outcome::Modified(*modified),
+            _ => return None,
+        })
+    }
+}
+
+/// The receiving end of a link
+///
+/// Deliveries can be received with `recv()`, or by using the `Receiver` as a `Stream`. The stream
+/// yields the error once the link or connection fails, and ends after that.
+pub struct Receiver {
+    link: Link,
+    /// Pending wait for the next delivery, driven by the `Stream` implementation
+    next: Option<ReusableBoxFuture<'static, Result<Delivery, ConnectionError>>>,
+    /// Set once the stream has yielded an error
+    failed: bool,
+}
+
+impl Receiver {
+    fn new(link: Link) -> Self {
+        Self {
+            link,
+            next: None,
+            failed: false,
+        }
+    }
+
+    /// Grant the sender `link_credit` credit, replacing any previously issued credit
+    pub async fn flow(&self, link_credit: u32) -> Result<(), ConnectionError> {
+        let (channel, handle) = (self.link.channel, self.link.handle);
+        self.link.update(|session| {
+            session.link(handle)?.link_credit = link_credit;
+            let link = &session.links[&handle];
+            Ok(Some(session.flow(channel, Some((handle, link)))))
+        })
+    }
+
+    /// Use up all outstanding credit, waiting for the sender to confirm
+    ///
+    /// Once drained, the sender will not send any more deliveries until more credit is issued;
+    /// with `Credit::Prefetch`, this happens as soon as the drain has completed.
+    pub async fn drain(&self) -> Result<(), ConnectionError> {
+        let (channel, handle) = (self.link.channel, self.link.handle);
+        self.link.update(|session| {
+            let link = session.link(handle)?;
+            if link.link_credit == 0 {
+                return Ok(None);
+            }
+
+            link.draining = true;
+            let link = &session.links[&handle];
+            Ok(Some(session.flow(channel, Some((handle, link)))))
+        })?;
+
+        self.link
+            .shared
+            .wait(|inner| Ok((!inner.session(channel)?.link(handle)?.draining).then_some(())))
+            .await?;
+        self.link
+            .update(|session| Ok(session.replenish(channel, handle)))
+    }
+
+    /// Wait for the next delivery on this link
+    pub async fn recv(&self) -> Result<Delivery, ConnectionError> {
+        Delivery::new(self.link.clone(), self.link.next().await?)
+    }
+
+    /// Detach the link, waiting for the peer to detach its side
+    pub async fn close(self) -> Result<(), ConnectionError> {
+        self.link.close().await
+    }
+
+    /// The address of the source, which is assigned by the peer for dynamic links
+    pub fn address(&self) -> Option<String> {
+        self.link.with(|link| link.address.clone()).flatten()
+    }
+
+    /// The amount of credit currently available to the sender
+    pub fn credit(&self) -> u32 {
+        self.link.with(|link| link.link_credit).unwrap_or(0)
+    }
+
+    /// The number of deliveries received on this link (modulo 2^32)
+    pub fn delivery_count(&self) -> u32 {
+        self.link.with(|link| link.delivery_count).unwrap_or(0)
+    }
+
+    pub fn handle(&self) -> u32 {
+        self.link.handle
+    }
+
+    pub fn state(&self) -> LinkState {
+        self.link.state()
+    }
+}
+
+impl Clone for Receiver {
+    fn clone(&self) -> Self {
+        Self::new(self.link.clone())
+    }
+}
+
+impl Stream for Receiver {
+    type Item = Result<Delivery, ConnectionError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.failed {
+            return Poll::Ready(None);
+        }
+
+        let link = self.link.clone();
+        let next = self
+            .next
+            .get_or_insert_with(|| ReusableBoxFuture::new(next_delivery(link.clone())));
+        let result = ready!(next.poll(cx));
+
+        // Reuse the allocation to wait for the delivery after this one
+        next.set(next_delivery(link));
+        self.failed = result.is_err();
+        Poll::Ready(Some(result))
+    }
+}
+
+async fn next_delivery(link: Link) -> Result<Delivery, ConnectionError> {
+    let frame = link.next().await?;
+    Delivery::new(link, frame)
+}
+
+/// A message received on a `Receiver`
+///
+/// Unless the sender settled the delivery up front, it should be settled by calling one of
+/// `accept()`, `reject()`, `release()` or `modify()`.
+pub struct Delivery {
+    link: Link,
+    frame: BytesFrame,
+    id: u32,
+    tag: Vec<u8>,
+    settled: bool,
+}
+
+impl Delivery {
+    fn new(link: Link, frame: BytesFrame) -> Result<Self, ConnectionError> {
+        let (id, tag, settled) = match frame.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative:
+                    amqp::Performative::Transfer(amqp::Transfer {
+                        delivery_id: Some(id),
+                        delivery_tag: Some(tag),
+                        settled,
+                        ..
+                    }),
+                message: Some(_),
+                ..
+            }) => (*id, tag.clone(), *settled == Some(true)),
+            frame => return Err(unexpected("transfer", frame)),
+        };
+
+        Ok(Self {
+            link,
+            frame,
+            id,
+            tag,
+            settled,
+        })
+    }
+
+    /// The decoded message
+    pub fn message(&self) -> &amqp::Message<'_> {
+        match self.frame.frame() {
+            Frame::Amqp(amqp::Frame {
+                message: Some(message),
+                ..
+            }) => message,
+            _ => unreachable!("delivery without message"),
+        }
+    }
+
+    /// The message body, if it consists of binary data
+    pub fn body(&self) -> Option<&[u8]> {
+        self.frame.body()
+    }
+
+    /// The delivery id, unique among unsettled deliveries on the session
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The delivery tag, unique among unsettled deliveries on the link
+    pub fn tag(&self) -> &[u8] {
+        &self.tag
+    }
+
+    /// Whether the sender has already settled the delivery
+    pub fn settled(&self) -> bool {
+        self.settled
+    }
+
+    pub fn frame(&self) -> &BytesFrame {
+        &self.frame
+    }
+
+    pub fn into_frame(self) -> BytesFrame {
+        self.frame
+    }
+
+    /// Accept the message
+    pub async fn accept(self) -> Result<(), ConnectionError> {
+        self.settle(amqp::DeliveryState::Accepted(amqp::Accepted {}))
+            .await
+    }
+
+    /// Reject the message as invalid and unprocessable
+    pub async fn reject(self, error: Option<amqp::Error<'_>>) -> Result<(), ConnectionError> {
+        self.settle(amqp::DeliveryState::Rejected(amqp::Rejected { error }))
+            .await
+    }
+
+    /// Release the message without processing it, so that it can be redelivered
+    pub async fn release(self) -> Result<(), ConnectionError> {
+        self.settle(amqp::DeliveryState::Released(amqp::Released {}))
+            .await
+    }
+
+    /// Release the message, asking the sender to modify it before redelivery
+    pub async fn modify(self, modified: amqp::Modified) -> Result<(), ConnectionError> {
+        self.settle(amqp::DeliveryState::Modified(modified)).await
+    }
+
+    async fn settle(self, state: amqp::DeliveryState<'_>) -> Result<(), ConnectionError> {
+        if self.settled {
+            return Ok(());
+        }
+
+        let disposition = amqp::Disposition {
+            role: amqp::Role::Receiver,
+            first: self.id,
+            last: None,
+            settled: Some(true),
+            state: Some(state),
+            batchable: None,
+        };
+
+        self.link
+            .shared
+            .send(&amqp_frame(
+                self.link.channel,
+                amqp::Performative::Disposition(disposition),
+            ))
+            .await
+    }
+}
+
+impl fmt::Debug for Delivery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Delivery")
+            .field("id", &self.id)
+            .field("tag", &self.tag)
+            .field("settled", &self.settled)
+            .field("frame", &self.frame)
+            .finish()
+    }
+}
+
+/// Common implementation for `Sender` and `Receiver`
+///
+/// The link is detached when the last `Link` referring to it is dropped.
+struct Link {
+    shared: Arc<Shared>,
+    channel: u16,
+    handle: u32,
+}
+
+impl Link {
+    fn new(session: &Session, handle: u32) -> Self {
+        let link = Self {
+            shared: session.shared.clone(),
+            channel: session.channel,
+            handle,
+        };
+        link.acquire();
+        link
+    }
+
+    /// Detach the link, waiting for the peer to detach its side
+    async fn close(&self) -> Result<(), ConnectionError> {
+        let (channel, handle) = (self.channel, self.handle);
+        self.update(|session| {
+            let link = match session.links.get_mut(&handle) {
+                Some(link) if link.state == LinkState::Attached => link,
+                _ => return Ok(None),
+            };
+
+            link.state = LinkState::DetachSent;
+            let detach = amqp::Detach {
+                handle,
+                closed: Some(true),
+                error: None,
+            };
+            Ok(Some(amqp_frame(
+                channel,
+                amqp::Performative::Detach(detach),
+            )))
+        })?;
+
+        // The link state is cleaned up once the last handle has been dropped
+        let error = self
+            .shared
+            .wait(|inner| {
+                Ok(match inner.session(channel)?.links.get(&handle) {
+                    Some(link) if link.state == LinkState::Detached => {
+                        Some(link.remote_detach.clone().flatten())
+                    }
+                    Some(_) => None,
+                    None => Some(None),
+                })
+            })
+            .await?;
+
+        match error {
+            Some(error) => Err(ConnectionError::RemoteDetach(Some(error))),
+            None => Ok(()),
+        }
+    }
+
+    /// Count another handle for this link and its session
+    fn acquire(&self) {
+        if let Ok(session) = self.shared.inner.lock().unwrap().session(self.channel) {
+            session.refs += 1;
+            if let Some(link) = session.links.get_mut(&self.handle) {
+                link.refs += 1;
+            }
+        }
+    }
+
+    /// Wait for the next frame routed to this link, topping up credit if necessary
+    async fn next(&self) -> Result<BytesFrame, ConnectionError> {
+        let (channel, handle) = (self.channel, self.handle);
+        let mut flush = false;
+        let frame = self
+            .shared
+            .wait(|inner| {
+                let link = match inner.session(channel)?.links.get_mut(&handle) {
+                    Some(link) => link,
+                    None => return Err(ConnectionError::RemoteDetach(None)),
+                };
+
+                if let (true, Some(error)) = (link.queue.is_empty(), &link.error) {
+                    return Err(ConnectionError::LocalDetach(error.clone()));
+                }
+
+                let frame = match (link.queue.pop_front(), &link.remote_detach) {
+                    (Some(frame), _) => frame,
+                    (None, Some(error)) => {
+                        return Err(ConnectionError::RemoteDetach(error.clone()))
+                    }
+                    (None, None) => return Ok(None),
+                };
+
+                if let Some(flow) = inner.session(channel)?.replenish(channel, handle) {
+                    inner.queue(&flow)?;
+                    flush = true;
+                }
+                Ok(Some(frame))
+            })
+            .await?;
+
+        if flush {
+            self.shared.flush();
+        }
+        Ok(frame)
+    }
+
+    /// Update session state, sending the frame returned by `f` (if any)
+    fn update(
+        &self,
+        f: impl FnOnce(&mut SessionData) -> Result<Option<Frame<'static>>, ConnectionError>,
+    ) -> Result<(), ConnectionError> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        let frame = match f(inner.session(self.channel)?)? {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+
+        inner.queue(&frame)?;
+        drop(inner);
+        self.shared.flush();
+        Ok(())
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&LinkData) -> T) -> Option<T> {
+        let inner = self.shared.inner.lock().unwrap();
+        let link = inner.sessions.get(&self.channel)?.links.get(&self.handle)?;
+        Some(f(link))
+    }
+
+    fn state(&self) -> LinkState {
+        self.with(|link| link.state).unwrap_or(LinkState::Detached)
+    }
+}
+
+impl Clone for Link {
+    fn clone(&self) -> Self {
+        let link = Self {
+            shared: self.shared.clone(),
+            channel: self.channel,
+            handle: self.handle,
+        };
+        link.acquire();
+        link
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        let mut inner = match self.shared.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return,
+        };
+
+        let detach = match inner.sessions.get_mut(&self.channel) {
+            Some(session) => session.release_link(self.channel, self.handle),
+            None => return,
+        };
+        if let Some(detach) = detach {
+            let _ = inner.queue(&detach);
+        }
+
+        inner.release_session(self.channel);
+        drop(inner);
+        self.shared.flush();
+    }
+}
+
+/// Link state shared between the session and the `Sender` or `Receiver`
+pub(crate) struct LinkData {
+    pub(crate) name: String,
+    role: amqp::Role,
+    pub(crate) state: LinkState,
+    pub(crate) delivery_count: u32,
+    pub(crate) link_credit: u32,
+    /// Credit policy for receiving links
+    pub(crate) credit: Credit,
+    /// Set while waiting for the sender to use up all credit
+    ///
+    /// On sending links, this is set while pending sends use up the credit of a drain.
+    pub(crate) draining: bool,
+    /// Number of `send()` calls waiting for credit on a sending link
+    pending_sends: usize,
+    /// Frames routed to this link that have not been picked up yet
+    pub(crate) queue: VecDeque<BytesFrame>,
+    /// Delivery ID, first transfer and payload received so far for an incomplete delivery
+    partial: Option<(u32, BytesFrame, BytesMut)>,
+    /// Largest delivery accepted on a receiving link, 0 if there is no limit
+    pub(crate) max_message_size: u64,
+    /// Set when we detached the link because the peer misbehaved
+    pub(crate) error: Option<RemoteError>,
+    /// Address of the peer's terminus, as given in its `Attach`
+    address: Option<String>,
+    /// Set when the peer has detached the link
+    pub(crate) remote_detach: Option<Option<RemoteError>>,
+    /// Number of handles referring to this link
+    pub(crate) refs: usize,
+}
+
+impl LinkData {
+    pub(crate) fn new(name: String, role: amqp::Role) -> Self {
+        Self {
+            name,
+            role,
+            state: LinkState::AttachSent,
+            delivery_count: 0,
+            link_credit: 0,
+            credit: Credit::Manual,
+            draining: false,
+            pending_sends: 0,
+            queue: VecDeque::new(),
+            partial: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            error: None,
+            address: None,
+            remote_detach: None,
+            refs: 0,
+        }
+    }
+
+    /// Update the link for the `Attach` the peer sent in response to ours
+    pub(crate) fn attached(&mut self, attach: &amqp::Attach<'_>) {
+        let address = match self.role {
+            amqp::Role::Sender => attach.target.as_ref().and_then(|target| target.address),
+            amqp::Role::Receiver => {
+                self.delivery_count = attach.initial_delivery_count.unwrap_or(0);
+                attach.source.as_ref().and_then(|source| source.address)
+            }
+        };
+        self.address = address.map(|address| address.to_owned());
+        self.state = LinkState::Attached;
+    }
+
+    /// Update the link flow state from a `Flow` sent by the peer
+    ///
+    /// Returns true if the peer expects a `Flow` in response.
+    pub(crate) fn flow(&mut self, flow: &amqp::Flow<'_>) -> bool {
+        match self.role {
+            // See section 2.6.7 of the AMQP 1.0 specification
+            amqp::Role::Sender => {
+                let delivery_count = flow.delivery_count.unwrap_or(0);
+                let link_credit = flow.link_credit.unwrap_or(0);
+                self.link_credit = delivery_count
+                    .wrapping_add(link_credit)
+                    .wrapping_sub(self.delivery_count);
+
+                // With sends waiting for credit, the drain completes once they have used what
+                // they can (see `PendingSend`), otherwise the credit is used up right away
+                self.draining = flow.drain == Some(true) && self.link_credit > 0;
+                if self.draining && self.pending_sends == 0 {
+                    self.drain();
+                    return true;
+                }
+            }
+            amqp::Role::Receiver => {
+                if let Some(delivery_count) = flow.delivery_count {
+                    let advanced = delivery_count.wrapping_sub(self.delivery_count);
+                    self.link_credit = self.link_credit.saturating_sub(advanced);
+                    self.delivery_count = delivery_count;
+                }
+
+                if self.link_credit == 0 {
+                    self.draining = false;
+                }
+            }
+        }
+
+        flow.echo == Some(true)
+    }
+
+    /// Use up the remaining credit on a sending link, completing a drain
+    fn drain(&mut self) {
+        self.delivery_count = self.delivery_count.wrapping_add(self.link_credit);
+        self.link_credit = 0;
+        self.draining = false;
+    }
+
+    /// Update the link for a `Transfer` received from the peer, queueing complete deliveries
+    ///
+    /// Transfers with `more` set are buffered until the final frame of the delivery arrives. If
+    /// the delivery grows beyond `max_message_size`, or a continuation frame belongs to another
+    /// delivery, the link is detached and the `Detach` to send is returned.
+    pub(crate) fn transferred(
+        &mut self,
+        channel: u16,
+        handle: u32,
+        frame: BytesFrame,
+    ) -> Result<Option<Frame<'static>>, ConnectionError> {
+        let (delivery_id, more, aborted) = match frame.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Transfer(transfer),
+                ..
+            }) => (
+                transfer.delivery_id,
+                transfer.more == Some(true),
+                transfer.aborted == Some(true),
+            ),
+            _ => return Ok(None),
+        };
+
+        // Anything still in flight after we detached is of no use anymore
+        if self.error.is_some() {
+            return Ok(None);
+        }
+
+        let buffered = match &self.partial {
+            Some((id, _, payload)) => {
+                if delivery_id.is_some_and(|delivery_id| delivery_id != *id) {
+                    return Ok(Some(self.detach(
+                        channel,
+                        handle,
+                        "amqp:invalid-field",
+                        "transfer continues a different delivery",
+                    )));
+                }
+                payload.len()
+            }
+            None if delivery_id.is_none() => {
+                return Ok(Some(self.detach(
+                    channel,
+                    handle,
+                    "amqp:invalid-field",
+                    "first transfer of a delivery has no delivery-id",
+                )));
+            }
+            None => 0,
+        };
+
+        let size = (buffered + frame.payload().len()) as u64;
+        if !aborted && self.max_message_size > 0 && size > self.max_message_size {
+            return Ok(Some(self.detach(
+                channel,
+                handle,
+                "amqp:link:message-size-exceeded",
+                "delivery exceeds the maximum message size",
+            )));
+        }
+
+        if aborted {
+            // The sender gave up on the delivery, discard whatever we have buffered
+            self.partial = None;
+        } else if more {
+            match &mut self.partial {
+                Some((_, _, payload)) => payload.extend_from_slice(frame.payload()),
+                None => {
+                    let payload = BytesMut::from(frame.payload());
+                    // Checked above, the first transfer of a delivery carries its id
+                    let delivery_id = delivery_id.unwrap_or_default();
+                    self.partial = Some((delivery_id, frame, payload));
+                }
+            }
+            return Ok(None);
+        } else {
+            let (first, payload) = match self.partial.take() {
+                Some((_, first, mut payload)) => {
+                    payload.extend_from_slice(frame.payload());
+                    (first, payload.freeze())
+                }
+                None => {
+                    let payload = frame.payload_bytes();
+                    (frame, payload)
+                }
+            };
+            self.queue.push_back(BytesFrame::assemble(first, payload)?);
+        }
+
+        self.delivery_count = self.delivery_count.wrapping_add(1);
+        self.link_credit = self.link_credit.saturating_sub(1);
+        Ok(None)
+    }
+
+    /// Detach the link with an error, dropping any partial delivery
+    fn detach(
+        &mut self,
+        channel: u16,
+        handle: u32,
+        condition: &'static str,
+        description: &'static str,
+    ) -> Frame<'static> {
+        let error = amqp::Error {
+            condition,
+            description: Some(description),
+            info: None,
+        };
+        self.error = Some(RemoteError::from(&error));
+        self.partial = None;
+        self.state = LinkState::DetachSent;
+        amqp_frame(
+            channel,
+            amqp::Performative::Detach(amqp::Detach {
+                handle,
+                closed: Some(true),
+                error: Some(error),
+            }),
+        )
+    }
+}
+
+/// Largest delivery accepted on receiving links unless configured otherwise
+pub const DEFAULT_MAX_MESSAGE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Link states
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LinkState {
+    AttachSent,
+    AttachRcvd,
+    Attached,
+    DetachSent,
+    Detached,
+}
@@ -1,142 +1,771 @@
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryInto;
+use std::pin::pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
 use std::{mem, str};
 
 use bytes::{self, BufMut, BytesMut};
-use futures::{sink::SinkExt, stream::StreamExt};
+use futures::stream::StreamExt;
 use serde_bytes::Bytes;
+use tokio::io::{self, AsyncRead, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::net::{TcpStream, ToSocketAddrs};
-use tokio_util::codec::{Decoder, Encoder, Framed};
+use tokio::sync::Notify;
+use tokio::time::{sleep_until, Instant};
+use tokio_util::codec::{Decoder, Encoder, FramedRead};
+use yoke::{Yoke, Yokeable};
 
-use super::{amqp, de, sasl, ser, Error};
+use super::session::{amqp_frame, SessionData, SessionState};
+use super::{amqp, de, sasl, scram, ser, ConnectionError, Error, RemoteError, Session};
 
+/// A connection to an AMQP peer
+///
+/// The socket is owned by a driver task which is spawned when connecting. `Client`, `Session`
+/// and link handles can be cloned cheaply to use the connection from several tasks at once.
+#[derive(Clone)]
 pub struct Client {
-    transport: tokio_util::codec::Framed<TcpStream, Codec>,
+    shared: Arc<Shared>,
 }
 
 impl Client {
-    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, ()> {
-        let stream = TcpStream::connect(addr).await.map_err(|_| ())?;
-        Ok(Self {
-            transport: Framed::new(stream, Codec),
-        })
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, ConnectionError> {
+        Ok(Self::from_stream(TcpStream::connect(addr).await?))
+    }
+
+    /// Connect over TLS to `host`, which is also used as the server name unless overridden
+    #[cfg(feature = "tls")]
+    pub async fn connect_tls(
+        host: &str,
+        port: u16,
+        config: &crate::tls::TlsConfig,
+    ) -> Result<Self, ConnectionError> {
+        Ok(Self::from_stream(config.connect(host, port).await?))
+    }
+
+    /// Connect to a `ws://` URL, tunneling AMQP through WebSocket messages
+    #[cfg(feature = "websocket")]
+    pub async fn connect_websocket(url: &str) -> Result<Self, ConnectionError> {
+        Ok(Self::from_stream(
+            crate::ws::WsStream::connect_tcp(url).await?,
+        ))
+    }
+
+    /// Connect to a `wss://` URL, tunneling AMQP through WebSocket messages over TLS
+    #[cfg(all(feature = "websocket", feature = "tls"))]
+    pub async fn connect_websocket_tls(
+        url: &str,
+        config: &crate::tls::TlsConfig,
+    ) -> Result<Self, ConnectionError> {
+        let stream = crate::ws::WsStream::connect_tls(url, config).await?;
+        Ok(Self::from_stream(stream))
+    }
+
+    /// Use an already established transport, such as a Unix socket or a proxy tunnel
+    ///
+    /// Nothing is sent until the client logs in or opens the connection. This spawns the
+    /// driver task, so it must be called from within a Tokio runtime.
+    pub fn from_stream<T>(io: T) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        Self {
+            shared: Shared::spawn(io, false),
+        }
+    }
+
+    /// The current state of the connection
+    pub fn state(&self) -> ConnectionState {
+        self.shared.inner.lock().unwrap().state
     }
 
     /// Login with the given username and password
     ///
-    /// Currently this only supports SASL PLAIN login.
-    pub async fn login(&mut self, user: &str, password: &str) -> Result<(), ()> {
-        self.transport
-            .send(&Frame::Header(Protocol::Sasl))
-            .await
-            .map_err(|_| ())?;
-        let _header = self.transport.next().await.ok_or(()).map_err(|_| ())?;
-        let _mechanisms = self.transport.next().await.ok_or(()).map_err(|_| ())?;
-
-        let mut response = vec![0u8];
-        response.extend_from_slice(user.as_bytes());
-        response.push(0);
-        response.extend_from_slice(password.as_bytes());
+    /// SCRAM-SHA-256 and SCRAM-SHA-1 are preferred over PLAIN if the server offers them.
+    pub async fn login(&mut self, user: &str, password: &str) -> Result<(), ConnectionError> {
+        let preference = [
+            sasl::Mechanism::ScramSha256,
+            sasl::Mechanism::ScramSha1,
+            sasl::Mechanism::Plain,
+        ];
+        self.login_with(user, password, &preference).await
+    }
 
+    /// Authenticate with the first mechanism in `preference` that is offered by the server
+    ///
+    /// For `Mechanism::Anonymous` and `Mechanism::External`, the credentials are not used.
+    pub async fn login_with(
+        &mut self,
+        user: &str,
+        password: &str,
+        preference: &[sasl::Mechanism],
+    ) -> Result<(), ConnectionError> {
+        let mut mechanisms = Vec::with_capacity(preference.len());
+        for mechanism in preference {
+            mechanisms.push(match mechanism {
+                sasl::Mechanism::Anonymous => Box::new(sasl::Anonymous::new()) as _,
+                sasl::Mechanism::External => Box::new(sasl::External::new()) as _,
+                sasl::Mechanism::Plain => Box::new(sasl::Plain::new(user, password)) as _,
+                sasl::Mechanism::ScramSha1 => {
+                    Box::new(scram::ScramClient::new(scram::Hash::Sha1, user, password)) as _
+                }
+                sasl::Mechanism::ScramSha256 => {
+                    Box::new(scram::ScramClient::new(scram::Hash::Sha256, user, password)) as _
+                }
+                mechanism => {
+                    return Err(ConnectionError::UnsupportedSaslMechanism(mechanism.clone()))
+                }
+            });
+        }
+        self.authenticate(mechanisms).await
+    }
+
+    /// Authenticate with the first of the given mechanisms that is offered by the server
+    pub async fn authenticate(
+        &mut self,
+        mut mechanisms: Vec<Box<dyn sasl::SaslMechanism>>,
+    ) -> Result<(), ConnectionError> {
+        self.shared.send(&Frame::Header(Protocol::Sasl)).await?;
+        self.shared.expect_header(Protocol::Sasl).await?;
+        let offered = self.shared.recv().await?;
+        let offered = match offered.frame() {
+            Frame::Sasl(sasl::Frame::Mechanisms(mechanisms)) => &mechanisms.sasl_server_mechanisms,
+            frame => return Err(unexpected("sasl-mechanisms", frame)),
+        };
+
+        let mut mechanism = match mechanisms
+            .iter()
+            .position(|m| offered.contains(&m.mechanism()))
+        {
+            Some(i) => mechanisms.swap_remove(i),
+            None => return Err(ConnectionError::SaslMechanisms(offered.clone())),
+        };
+
+        let response = mechanism.initial_response()?;
         let init = Frame::Sasl(sasl::Frame::Init(sasl::Init {
-            mechanism: sasl::Mechanism::Plain,
-            initial_response: Some(Bytes::new(&response)),
+            mechanism: mechanism.mechanism(),
+            initial_response: response.as_deref().map(Bytes::new),
             hostname: None,
         }));
 
-        self.transport.send(&init).await.map_err(|_| ())?;
-        let _outcome = self.transport.next().await.ok_or(()).map_err(|_| ())?;
-        let _header = self.transport.next().await.ok_or(()).map_err(|_| ())?;
-        self.transport
-            .send(&Frame::Header(Protocol::Amqp))
-            .await
-            .map_err(|_| ())
+        self.shared.send(&init).await?;
+        loop {
+            let frame = self.shared.recv().await?;
+            let response = match frame.frame() {
+                Frame::Sasl(sasl::Frame::Challenge(challenge)) => {
+                    mechanism.challenge(challenge.challenge)?
+                }
+                Frame::Sasl(sasl::Frame::Outcome(sasl::Outcome {
+                    code: sasl::Code::Ok,
+                    additional_data,
+                })) => {
+                    mechanism.outcome(additional_data.map(|data| &**data))?;
+                    break;
+                }
+                Frame::Sasl(sasl::Frame::Outcome(outcome)) => {
+                    return Err(ConnectionError::Sasl {
+                        code: outcome.code,
+                        additional_data: outcome.additional_data.map(|data| data.to_vec()),
+                    })
+                }
+                frame => return Err(unexpected("sasl-outcome", frame)),
+            };
+
+            let response = Frame::Sasl(sasl::Frame::Response(sasl::Response {
+                response: Bytes::new(&response),
+            }));
+            self.shared.send(&response).await?;
+        }
+
+        self.shared.send(&Frame::Header(Protocol::Amqp)).await?;
+        self.shared.expect_header(Protocol::Amqp).await
     }
 
-    pub async fn open(&mut self, container_id: &str) -> Result<(), ()> {
-        let open = Frame::Amqp(amqp::Frame {
-            channel: 0,
-            extended_header: None,
-            performative: amqp::Performative::Open(amqp::Open {
-                container_id,
-                ..Default::default()
-            }),
-            message: None,
-        });
+    pub async fn open(&mut self, container_id: &str) -> Result<(), ConnectionError> {
+        let open = self.shared.inner.lock().unwrap().open(container_id);
 
-        self.transport.send(&open).await.map_err(|_| ())?;
-        let _opened = self.transport.next().await.ok_or(()).map_err(|_| ())?;
-        Ok(())
+        // Without a SASL layer, the protocol header is pipelined with the open frame
+        if self.state() == ConnectionState::Start {
+            self.shared.send(&Frame::Header(Protocol::Amqp)).await?;
+        }
+
+        self.shared.send(&amqp_frame(0, open)).await?;
+        if self.state() == ConnectionState::OpenPipe {
+            self.shared.expect_header(Protocol::Amqp).await?;
+        }
+
+        self.shared.opened().await
     }
 
-    pub async fn begin(&mut self) -> Result<(), ()> {
-        let begin = Frame::Amqp(amqp::Frame {
-            channel: 0,
-            extended_header: None,
-            performative: amqp::Performative::Begin(amqp::Begin {
-                remote_channel: None,
-                next_outgoing_id: 1,
-                incoming_window: 8,
-                outgoing_window: 8,
-                ..Default::default()
+    /// The largest frame that can be sent to the peer, as negotiated when opening the connection
+    pub fn max_frame_size(&self) -> u32 {
+        self.shared.inner.lock().unwrap().max_frame_size
+    }
+
+    /// Set the idle timeout to advertise when opening the connection, or `None` to disable it
+    ///
+    /// The connection fails with `ConnectionError::IdleTimeout` (wrapped in
+    /// `ConnectionError::Failed`) if the peer does not send any frames within this interval
+    /// after the connection has been opened.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.shared.inner.lock().unwrap().idle_timeout = timeout;
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.shared.inner.lock().unwrap().idle_timeout
+    }
+
+    /// Begin a new session on the next free channel
+    pub async fn begin(&self) -> Result<Session, ConnectionError> {
+        Session::begin(self.shared.clone()).await
+    }
+
+    /// Close the connection, waiting for the peer to close its side
+    ///
+    /// If all handles are dropped without closing, the connection is closed without waiting.
+    pub async fn close(self) -> Result<(), ConnectionError> {
+        self.shared.close().await
+    }
+}
+
+/// Connection internals shared between the `Client`, its sessions and links, and the driver
+pub(crate) struct Shared {
+    pub(crate) inner: Mutex<Inner>,
+    /// Notified whenever the driver has processed incoming frames
+    received: Notify,
+    /// Wakes up the driver when there are frames to write or the last handle goes away
+    wake: Arc<Notify>,
+    /// Frames left to write for the driver once the last handle is gone
+    remaining: Arc<Mutex<VecDeque<Vec<u8>>>>,
+}
+
+impl Shared {
+    /// Set up the connection state and spawn the driver task for `io`
+    pub(crate) fn spawn<T>(io: T, server: bool) -> Arc<Self>
+    where
+        T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (reader, writer) = io::split(Box::new(io) as Box<dyn Io>);
+        let wake = Arc::new(Notify::new());
+        let remaining = Arc::new(Mutex::new(VecDeque::new()));
+        let shared = Arc::new(Shared {
+            inner: Mutex::new(Inner {
+                state: ConnectionState::Start,
+                channel_max: u16::MAX,
+                max_frame_size: MIN_MAX_FRAME_SIZE,
+                idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+                heartbeat: None,
+                frames: VecDeque::new(),
+                sessions: HashMap::new(),
+                remote_channels: HashMap::new(),
+                outgoing: VecDeque::new(),
+                remote_close: None,
+                error: None,
+                running: true,
+                server,
+                incoming_sessions: VecDeque::new(),
             }),
-            message: None,
+            received: Notify::new(),
+            wake: wake.clone(),
+            remaining: remaining.clone(),
         });
 
-        self.transport.send(&begin).await.map_err(|_| ())?;
-        let _begun = self.transport.next().await.ok_or(()).map_err(|_| ())?;
+        let driver = Driver {
+            shared: Arc::downgrade(&shared),
+            wake,
+            remaining,
+            reader: FramedRead::new(reader, Codec),
+            writer,
+            timers: (None, None),
+            last_received: Instant::now(),
+            last_sent: Instant::now(),
+        };
+        tokio::spawn(driver.run());
+        shared
+    }
+
+    /// Queue a frame to be written by the driver
+    pub(crate) async fn send(&self, frame: &Frame<'_>) -> Result<(), ConnectionError> {
+        self.inner.lock().unwrap().queue(frame)?;
+        self.flush();
         Ok(())
     }
 
-    pub async fn attach(&mut self, attach: amqp::Attach<'_>) -> Result<(), ()> {
-        let is_sender = matches!(attach.role, amqp::Role::Sender);
-        let attach = Frame::Amqp(amqp::Frame {
-            channel: 0,
-            extended_header: None,
-            performative: amqp::Performative::Attach(attach),
-            message: None,
-        });
+    /// Wake up the driver to write out frames queued with `Inner::queue()`
+    pub(crate) fn flush(&self) {
+        self.wake.notify_one();
+    }
 
-        self.transport.send(&attach).await.map_err(|_| ())?;
-        let _attached = self.transport.next().await.ok_or(()).map_err(|_| ())?;
-        if is_sender {
-            let _flow = self.transport.next().await.ok_or(()).map_err(|_| ())?;
+    /// Wake up tasks in `wait()` after changing state they may be waiting on
+    pub(crate) fn notify(&self) {
+        self.received.notify_waiters();
+    }
+
+    /// Receive the next connection-level frame (protocol headers, SASL frames and `Open`)
+    pub(crate) async fn recv(&self) -> Result<BytesFrame, ConnectionError> {
+        self.wait(|inner| Ok(inner.frames.pop_front())).await
+    }
+
+    pub(crate) async fn expect_header(&self, protocol: Protocol) -> Result<(), ConnectionError> {
+        let header = self.recv().await?;
+        match header.frame() {
+            Frame::Header(p) if *p == protocol => Ok(()),
+            frame => Err(unexpected(protocol.name(), frame)),
         }
+    }
 
-        Ok(())
+    /// Wait for the peer's `Open` and apply the limits it announces
+    pub(crate) async fn opened(&self) -> Result<(), ConnectionError> {
+        let opened = self.recv().await?;
+        match opened.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Open(open),
+                ..
+            }) => {
+                self.inner.lock().unwrap().opened(open);
+                // Make sure the driver picks up the heartbeat interval
+                self.flush();
+                Ok(())
+            }
+            frame => Err(unexpected("open", frame)),
+        }
     }
 
-    pub async fn flow(&mut self, flow: amqp::Flow<'_>) -> Result<(), ()> {
-        let flow = Frame::Amqp(amqp::Frame {
-            channel: 0,
-            extended_header: None,
-            performative: amqp::Performative::Flow(flow),
-            message: None,
-        });
+    /// Close the connection, waiting for the peer to close its side
+    pub(crate) async fn close(&self) -> Result<(), ConnectionError> {
+        let close = amqp::Performative::Close(amqp::Close { error: None });
+        self.send(&amqp_frame(0, close)).await?;
+        let error = self
+            .wait(|inner| {
+                Ok(match inner.state {
+                    ConnectionState::End => Some(inner.remote_close.clone().flatten()),
+                    _ => None,
+                })
+            })
+            .await?;
+
+        match error {
+            Some(error) => Err(ConnectionError::RemoteClose(Some(error))),
+            None => Ok(()),
+        }
+    }
+
+    /// Wait until `poll` returns a value, re-polling whenever the driver has processed frames
+    pub(crate) async fn wait<T>(
+        &self,
+        mut poll: impl FnMut(&mut Inner) -> Result<Option<T>, ConnectionError>,
+    ) -> Result<T, ConnectionError> {
+        loop {
+            // Register for notification before polling, so that we can't miss a wake-up
+            let mut received = pin!(self.received.notified());
+            received.as_mut().enable();
+
+            {
+                // Frames received before the connection went down can still be picked up
+                let mut inner = self.inner.lock().unwrap();
+                if let Some(value) = poll(&mut inner)? {
+                    return Ok(value);
+                }
+                inner.check()?;
+            }
+
+            received.await;
+        }
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        // Close the connection on a best-effort basis, unless it is already closing
+        if let Ok(inner) = self.inner.get_mut() {
+            let close = amqp::Performative::Close(amqp::Close { error: None });
+            let _ = inner.queue(&amqp_frame(0, close));
+            if let Ok(mut remaining) = self.remaining.lock() {
+                *remaining = mem::take(&mut inner.outgoing);
+            }
+        }
+        self.wake.notify_one();
+    }
+}
 
-        self.transport.send(&flow).await.map_err(|_| ())?;
+pub(crate) struct Inner {
+    pub(crate) state: ConnectionState,
+    pub(crate) channel_max: u16,
+    pub(crate) max_frame_size: u32,
+    /// Idle timeout we advertise to the peer
+    pub(crate) idle_timeout: Option<Duration>,
+    /// Interval for sending empty frames, derived from the peer's idle timeout
+    heartbeat: Option<Duration>,
+    /// Connection-level frames that have not been picked up yet
+    frames: VecDeque<BytesFrame>,
+    /// Session state, by local channel
+    pub(crate) sessions: HashMap<u16, SessionData>,
+    /// Maps the peer's channel numbers to our own
+    remote_channels: HashMap<u16, u16>,
+    /// Encoded frames waiting to be written by the driver
+    outgoing: VecDeque<Vec<u8>>,
+    /// Set when the peer has closed the connection
+    remote_close: Option<Option<RemoteError>>,
+    /// The error that made the driver stop, returned to every handle from then on
+    error: Option<Arc<ConnectionError>>,
+    /// Whether the driver is still running
+    running: bool,
+    /// Whether the peer initiated the connection, in which case we answer its AMQP header
+    server: bool,
+    /// Sessions begun by the peer that have not been picked up yet, by local channel
+    pub(crate) incoming_sessions: VecDeque<u16>,
+}
+
+impl Inner {
+    /// Process an incoming frame, queueing any frames to send in response
+    fn dispatch(&mut self, frame: BytesFrame) -> Result<(), ConnectionError> {
+        self.state = self.state.received(frame.frame())?;
+        let mut refused = false;
+        let local = match frame.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Close(close),
+                ..
+            }) => {
+                let error = close.error.as_ref().map(RemoteError::from);
+                if self.state == ConnectionState::CloseRcvd {
+                    let close = amqp::Performative::Close(amqp::Close { error: None });
+                    self.queue(&amqp_frame(0, close))?;
+                }
+                self.remote_close = Some(error.clone());
+                return Err(ConnectionError::RemoteClose(error));
+            }
+            // The SASL header is only answered once the server has decided to authenticate
+            Frame::Header(Protocol::Amqp) if self.server => {
+                self.queue(&Frame::Header(Protocol::Amqp))?;
+                self.frames.push_back(frame);
+                return Ok(());
+            }
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Open(_),
+                ..
+            })
+            | Frame::Header(_)
+            | Frame::Sasl(_) => {
+                self.frames.push_back(frame);
+                return Ok(());
+            }
+            Frame::Empty => return Ok(()),
+            Frame::Amqp(amqp::Frame {
+                channel,
+                performative:
+                    amqp::Performative::Begin(amqp::Begin {
+                        remote_channel: Some(local),
+                        ..
+                    }),
+                ..
+            }) if self.sessions.contains_key(local) => {
+                self.remote_channels.insert(*channel, *local);
+                *local
+            }
+            Frame::Amqp(amqp::Frame {
+                channel,
+                performative:
+                    amqp::Performative::Begin(amqp::Begin {
+                        remote_channel: None,
+                        ..
+                    }),
+                ..
+            }) if !self.remote_channels.contains_key(channel) => {
+                // The peer begins a new session, which we answer right away. Clients have no
+                // way to pick it up, so they end it again.
+                let local = self.allocate_channel()?;
+                self.remote_channels.insert(*channel, local);
+                match self.server {
+                    true => self.incoming_sessions.push_back(local),
+                    false => refused = true,
+                }
+                local
+            }
+            Frame::Amqp(amqp::Frame { channel, .. }) => match self.remote_channels.get(channel) {
+                Some(local) => *local,
+                None => return Err(ConnectionError::UnmappedChannel(*channel)),
+            },
+        };
+
+        let mut replies = Vec::new();
+        if let Some(session) = self.sessions.get_mut(&local) {
+            session.received(local, frame, &mut replies)?;
+            if session.refs == 0 {
+                match session.state {
+                    SessionState::Unmapped => self.release_channel(local),
+                    // Released while waiting for the peer's `Begin`, see `release_session()`
+                    SessionState::Mapped => {
+                        session.state = SessionState::EndSent;
+                        let end = amqp::Performative::End(amqp::End { error: None });
+                        replies.push(amqp_frame(local, end));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        for reply in replies {
+            self.queue(&reply)?;
+        }
+        if refused {
+            self.release_session(local);
+        }
         Ok(())
     }
 
-    pub async fn transfer(
+    /// Build our `Open`, advertising our limits and idle timeout
+    pub(crate) fn open<'a>(&self, container_id: &'a str) -> amqp::Performative<'a> {
+        amqp::Performative::Open(amqp::Open {
+            container_id,
+            max_frame_size: Some(MAX_FRAME_SIZE),
+            idle_timeout: self.idle_timeout.map(|timeout| timeout.as_millis() as u32),
+            ..Default::default()
+        })
+    }
+
+    /// Apply the limits announced in the peer's `Open`
+    fn opened(&mut self, open: &amqp::Open<'_>) {
+        self.channel_max = open.channel_max.unwrap_or(u16::MAX);
+        self.max_frame_size = open
+            .max_frame_size
+            .unwrap_or(u32::MAX)
+            .max(MIN_MAX_FRAME_SIZE);
+        // Send heartbeats at half the peer's idle timeout, see section 2.4.5
+        self.heartbeat = open
+            .idle_timeout
+            .filter(|&ms| ms > 0)
+            .map(|ms| Duration::from_millis(u64::from(ms) / 2));
+    }
+
+    /// Update the connection state for an outgoing frame and queue it for the driver
+    pub(crate) fn queue(&mut self, frame: &Frame<'_>) -> Result<(), ConnectionError> {
+        let buf = frame.to_vec()?;
+        self.queue_encoded(frame, vec![buf])
+    }
+
+    /// Queue frames that have already been encoded, such as the frames making up a transfer
+    pub(crate) fn queue_encoded(
         &mut self,
-        transfer: amqp::Transfer,
-        message: amqp::Message<'_>,
-    ) -> Result<(), ()> {
-        let transfer = Frame::Amqp(amqp::Frame {
+        frame: &Frame<'_>,
+        bufs: Vec<Vec<u8>>,
+    ) -> Result<(), ConnectionError> {
+        self.check()?;
+        self.state = self.state.sent(frame)?;
+        self.outgoing.extend(bufs);
+        Ok(())
+    }
+
+    /// The idle timeout to enforce and the heartbeat interval, if any
+    fn timers(&self) -> (Option<Duration>, Option<Duration>) {
+        use ConnectionState::*;
+        match self.state {
+            // The peer only knows about our idle timeout once it has seen our open
+            Start | HdrSent | HdrRcvd | HdrExch | OpenRcvd => (None, self.heartbeat),
+            _ => (self.idle_timeout, self.heartbeat),
+        }
+    }
+
+    /// Check that the connection is still usable
+    fn check(&mut self) -> Result<(), ConnectionError> {
+        if let Some(error) = &self.remote_close {
+            return Err(ConnectionError::RemoteClose(error.clone()));
+        }
+
+        match &self.error {
+            Some(error) => Err(ConnectionError::Failed(error.clone())),
+            None if !self.running => Err(ConnectionError::Disconnected),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn session(&mut self, channel: u16) -> Result<&mut SessionData, ConnectionError> {
+        match self.sessions.get_mut(&channel) {
+            Some(session) => match &session.remote_end {
+                Some(error) => Err(ConnectionError::RemoteEnd(error.clone())),
+                None => Ok(session),
+            },
+            None => Err(ConnectionError::RemoteEnd(None)),
+        }
+    }
+
+    /// Allocate the lowest free channel number for a new session
+    pub(crate) fn allocate_channel(&mut self) -> Result<u16, ConnectionError> {
+        let channel = (0..=self.channel_max)
+            .find(|c| !self.sessions.contains_key(c))
+            .ok_or(ConnectionError::ChannelsExhausted)?;
+        self.sessions.insert(channel, SessionData::new());
+        Ok(channel)
+    }
+
+    /// Drop a reference to a session, ending it once the last handle is gone
+    pub(crate) fn release_session(&mut self, channel: u16) {
+        let session = match self.sessions.get_mut(&channel) {
+            Some(session) => session,
+            None => return,
+        };
+
+        session.refs -= 1;
+        if session.refs > 0 {
+            return;
+        }
+
+        match session.state {
+            SessionState::Mapped => {
+                session.state = SessionState::EndSent;
+                let end = amqp::Performative::End(amqp::End { error: None });
+                let _ = self.queue(&amqp_frame(channel, end));
+            }
+            SessionState::Unmapped => self.release_channel(channel),
+            // The session can only be ended once the peer's `Begin` arrives, see `dispatch()`
+            SessionState::BeginSent => {}
+            _ => {}
+        }
+    }
+
+    pub(crate) fn release_channel(&mut self, channel: u16) {
+        self.sessions.remove(&channel);
+        self.remote_channels.retain(|_, local| *local != channel);
+    }
+}
+
+/// Background task that owns the socket
+///
+/// The driver dispatches incoming frames to the connection, session and link state they belong
+/// to and writes out frames queued by the handles. It stops when the connection fails or when
+/// all handles have been dropped.
+struct Driver {
+    shared: Weak<Shared>,
+    wake: Arc<Notify>,
+    remaining: Arc<Mutex<VecDeque<Vec<u8>>>>,
+    reader: FramedRead<ReadHalf<Box<dyn Io>>, Codec>,
+    writer: WriteHalf<Box<dyn Io>>,
+    /// Idle timeout and heartbeat interval, as of the last time the connection state was checked
+    timers: (Option<Duration>, Option<Duration>),
+    last_received: Instant,
+    last_sent: Instant,
+}
+
+impl Driver {
+    async fn run(mut self) {
+        let error = match self.drive().await {
+            Ok(()) => return,
+            Err(error) => error,
+        };
+
+        if let Some(shared) = self.shared.upgrade() {
+            {
+                let mut inner = shared.inner.lock().unwrap();
+                inner.running = false;
+                if inner.remote_close.is_none() {
+                    inner.error = Some(Arc::new(error));
+                }
+            }
+            shared.received.notify_waiters();
+        }
+    }
+
+    async fn drive(&mut self) -> Result<(), ConnectionError> {
+        loop {
+            let (idle_timeout, heartbeat) = self.timers;
+            let timeout = idle_timeout.map(|timeout| self.last_received + timeout);
+            let heartbeat = heartbeat.map(|interval| self.last_sent + interval);
+            let deadline = match (timeout, heartbeat) {
+                (Some(timeout), Some(heartbeat)) => Some(timeout.min(heartbeat)),
+                (deadline, None) | (None, deadline) => deadline,
+            };
+
+            let frame = tokio::select! {
+                frame = self.reader.next() => match frame {
+                    Some(frame) => {
+                        self.last_received = Instant::now();
+                        Some(frame?)
+                    }
+                    None => return Err(ConnectionError::Disconnected),
+                },
+                _ = self.wake.notified() => None,
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    let now = Instant::now();
+                    if let (Some(timeout), Some(interval)) = (timeout, idle_timeout) {
+                        if now >= timeout {
+                            return Err(self.timed_out(interval).await);
+                        }
+                    }
+
+                    if heartbeat.map(|heartbeat| now >= heartbeat).unwrap_or(false) {
+                        self.writer.write_all(&Frame::Empty.to_vec()?).await?;
+                        self.writer.flush().await?;
+                        self.last_sent = now;
+                    }
+                    continue;
+                }
+            };
+
+            let shared = match self.shared.upgrade() {
+                Some(shared) => shared,
+                None => {
+                    let remaining = mem::take(&mut *self.remaining.lock().unwrap());
+                    for buf in remaining {
+                        self.writer.write_all(&buf).await?;
+                    }
+                    self.writer.flush().await?;
+                    return Ok(());
+                }
+            };
+
+            let (result, outgoing) = {
+                let mut inner = shared.inner.lock().unwrap();
+                let result = match frame {
+                    Some(frame) => inner.dispatch(frame),
+                    None => Ok(()),
+                };
+                self.timers = inner.timers();
+                (result, mem::take(&mut inner.outgoing))
+            };
+
+            // Write out any replies, even if the connection is going down
+            shared.received.notify_waiters();
+            if !outgoing.is_empty() {
+                for buf in outgoing {
+                    self.writer.write_all(&buf).await?;
+                }
+                self.writer.flush().await?;
+                self.last_sent = Instant::now();
+            }
+            result?;
+        }
+    }
+
+    /// Let the peer know why we are closing the connection, as far as possible
+    async fn timed_out(&mut self, timeout: Duration) -> ConnectionError {
+        let close = Frame::Amqp(amqp::Frame {
             channel: 0,
             extended_header: None,
-            performative: amqp::Performative::Transfer(transfer),
-            message: Some(message),
+            performative: amqp::Performative::Close(amqp::Close {
+                error: Some(amqp::Error {
+                    condition: "amqp:resource-limit-exceeded",
+                    description: Some("local-idle-timeout expired"),
+                    info: None,
+                }),
+            }),
+            message: None,
         });
 
-        self.transport.send(&transfer).await.map_err(|_| ())?;
-        let _transferred = self.transport.next().await.ok_or(()).map_err(|_| ())?;
-        Ok(())
+        if let Ok(buf) = close.to_vec() {
+            let _ = self.writer.write_all(&buf).await;
+            let _ = self.writer.flush().await;
+        }
+        ConnectionError::IdleTimeout(timeout)
     }
+}
+
+/// A byte stream that a connection can run over
+pub(crate) trait Io: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
 
-    #[allow(clippy::should_implement_trait)]
-    pub async fn next(&mut self) -> Option<Result<BytesFrame, Error>> {
-        self.transport.next().await
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Io for T {}
+
+pub(crate) fn unexpected(expected: &'static str, frame: &Frame<'_>) -> ConnectionError {
+    ConnectionError::UnexpectedFrame {
+        expected,
+        found: frame.name(),
     }
 }
 
@@ -152,19 +781,28 @@ impl Decoder for Codec {
         }
 
         let length_or_proto_tag = &src[..4];
-        let bytes = if length_or_proto_tag == b"AMQP" && src.len() >= PROTO_HEADER_LENGTH {
+        let bytes = if length_or_proto_tag == b"AMQP" {
+            if src.len() < PROTO_HEADER_LENGTH {
+                return Ok(None);
+            }
             src.split_to(PROTO_HEADER_LENGTH).freeze()
         } else {
-            let len = u32::from_be_bytes((length_or_proto_tag).try_into().unwrap()) as usize;
+            // The length comes from the peer; it must at least cover the frame header
+            let len = u32::from_be_bytes((length_or_proto_tag).try_into().unwrap());
+            if !(FRAME_HEADER_LENGTH..=MAX_FRAME_SIZE).contains(&len) {
+                return Err(Error::InvalidData);
+            }
+
+            let len = len as usize;
             if src.len() >= len {
                 src.split_to(len).freeze().split_off(4)
             } else {
+                src.reserve(len - src.len());
                 return Ok(None);
             }
         };
 
-        let frame = unsafe { mem::transmute(Frame::decode(&bytes)?) };
-        Ok(Some(BytesFrame { bytes, frame }))
+        BytesFrame::new(bytes, None).map(Some)
     }
 }
 
@@ -172,22 +810,60 @@ impl Encoder<&Frame<'_>> for Codec {
     type Error = Error;
 
     fn encode(&mut self, item: &Frame<'_>, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let buf = item.to_vec().unwrap();
+        let buf = item.to_vec()?;
         dst.put(&*buf);
         Ok(())
     }
 }
 
+/// A received frame, together with the buffers its borrowed fields point into
+///
+/// Cloning is cheap, since clones share the same buffers and decoded frame.
+#[derive(Clone)]
 pub struct BytesFrame {
-    #[allow(dead_code)]
-    bytes: bytes::Bytes,
-    frame: Frame<'static>,
+    inner: Arc<Yoke<Parsed<'static>, Box<Buffers>>>,
 }
 
 impl BytesFrame {
+    /// Decode the frame in `bytes`, taking the message from `message` if given
+    fn new(bytes: bytes::Bytes, message: Option<bytes::Bytes>) -> Result<Self, Error> {
+        let buffers = Box::new(Buffers { bytes, message });
+        let inner = Yoke::try_attach_to_cart(buffers, |buffers| {
+            let (mut frame, mut payload) = Frame::parse(&buffers.bytes)?;
+            if let (Frame::Amqp(frame), Some(message)) = (&mut frame, &buffers.message) {
+                frame.message = Some(amqp::Message::decode(message)?);
+                payload = message;
+            }
+            Ok::<_, Error>(Parsed { frame, payload })
+        })?;
+
+        Ok(Self {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Decode the message of a delivery from the complete payload, starting with its first transfer
+    pub(crate) fn assemble(first: BytesFrame, payload: bytes::Bytes) -> Result<Self, Error> {
+        Self::new(first.inner.backing_cart().bytes.clone(), Some(payload))
+    }
+
     #[allow(clippy::needless_lifetimes)]
     pub fn frame<'a>(&'a self) -> &'a Frame<'a> {
-        &self.frame
+        &self.inner.get().frame
+    }
+
+    /// Raw payload following the performative, i.e. the encoded message of a transfer
+    pub fn payload(&self) -> &[u8] {
+        self.inner.get().payload
+    }
+
+    /// The payload as `Bytes`, sharing the frame's buffer
+    pub(crate) fn payload_bytes(&self) -> bytes::Bytes {
+        let buffers = self.inner.backing_cart();
+        match &buffers.message {
+            Some(message) => message.clone(),
+            None => buffers.bytes.slice_ref(self.payload()),
+        }
     }
 
     #[allow(clippy::needless_lifetimes)]
@@ -199,7 +875,7 @@ impl BytesFrame {
             _ => return None,
         };
 
-        match message.body {
+        match &message.body {
             Some(amqp::Body::Data(amqp::Data(data))) => Some(data),
             Some(amqp::Body::Value(amqp::Value(amqp::Any::Bytes(data)))) => Some(data),
             _ => None,
@@ -209,38 +885,63 @@ impl BytesFrame {
 
 impl std::fmt::Debug for BytesFrame {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
-        self.frame.fmt(fmt)
+        self.frame().fmt(fmt)
     }
 }
 
+struct Buffers {
+    bytes: bytes::Bytes,
+    /// The complete message of a delivery, which may span multiple transfers
+    message: Option<bytes::Bytes>,
+}
+
+#[derive(Yokeable)]
+struct Parsed<'a> {
+    frame: Frame<'a>,
+    payload: &'a [u8],
+}
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, PartialEq)]
 pub enum Frame<'a> {
     Amqp(amqp::Frame<'a>),
     Header(Protocol),
     Sasl(sasl::Frame<'a>),
+    Empty,
 }
 
 impl<'a> Frame<'a> {
     pub fn decode(buf: &'a [u8]) -> Result<Self, Error> {
-        if &buf[..4] == b"AMQP" {
-            return Ok(Frame::Header(Protocol::from_bytes(buf)));
+        Self::parse(buf).map(|(frame, _)| frame)
+    }
+
+    pub(crate) fn parse(buf: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
+        // Frames are passed in without their 4-byte size
+        if buf.len() < 4 {
+            return Err(Error::InvalidData);
+        } else if &buf[..4] == b"AMQP" {
+            return Ok((Frame::Header(Protocol::from_bytes(buf)?), &[]));
         }
 
         let doff = buf[0];
-        if doff < 2 {
+        if doff < 2 || doff as usize * 4 - 4 > buf.len() {
             return Err(Error::InvalidData);
         }
 
         let result = match buf[1] {
-            0x00 => Ok(Frame::Amqp(amqp::Frame::decode(doff, &buf[2..])?)),
+            // An empty frame without a performative, used as a heartbeat
+            0x00 if buf.len() == doff as usize * 4 - 4 => Ok((Frame::Empty, &[][..])),
+            0x00 => {
+                let (frame, payload) = amqp::Frame::decode(doff, &buf[2..])?;
+                Ok((Frame::Amqp(frame), payload))
+            }
+            0x01 if buf[2..4] != [0, 0] => Err(Error::InvalidData),
             0x01 => {
-                assert_eq!(&buf[2..4], &[0, 0]);
-                let (sasl, rest) = de::deserialize(&buf[4..])?;
+                let (sasl, rest) = de::deserialize(&buf[doff as usize * 4 - 4..])?;
                 if !rest.is_empty() {
                     return Err(Error::TrailingCharacters);
                 }
-                Ok(Frame::Sasl(sasl))
+                Ok((Frame::Sasl(sasl), &[][..]))
             }
             _ => Err(Error::InvalidData),
         };
@@ -251,6 +952,32 @@ impl<'a> Frame<'a> {
         result
     }
 
+    /// Short name of the frame type, mostly useful for diagnostics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Frame::Amqp(frame) => match frame.performative {
+                amqp::Performative::Open(_) => "open",
+                amqp::Performative::Begin(_) => "begin",
+                amqp::Performative::Attach(_) => "attach",
+                amqp::Performative::Flow(_) => "flow",
+                amqp::Performative::Transfer(_) => "transfer",
+                amqp::Performative::Disposition(_) => "disposition",
+                amqp::Performative::Detach(_) => "detach",
+                amqp::Performative::End(_) => "end",
+                amqp::Performative::Close(_) => "close",
+            },
+            Frame::Header(p) => p.name(),
+            Frame::Empty => "empty",
+            Frame::Sasl(frame) => match frame {
+                sasl::Frame::Mechanisms(_) => "sasl-mechanisms",
+                sasl::Frame::Init(_) => "sasl-init",
+                sasl::Frame::Challenge(_) => "sasl-challenge",
+                sasl::Frame::Response(_) => "sasl-response",
+                sasl::Frame::Outcome(_) => "sasl-outcome",
+            },
+        }
+    }
+
     pub fn to_vec(&self) -> Result<Vec<u8>, Error> {
         let mut buf = vec![0; 8];
 
@@ -259,25 +986,7 @@ impl<'a> Frame<'a> {
                 buf[5] = 0x00;
                 ser::into_bytes(&f.performative, &mut buf)?;
                 if let Some(msg) = &f.message {
-                    if let Some(header) = &msg.header {
-                        ser::into_bytes(header, &mut buf)?;
-                    }
-                    if let Some(da) = &msg.delivery_annotations {
-                        ser::into_bytes(da, &mut buf)?;
-                    }
-                    if let Some(ma) = &msg.message_annotations {
-                        ser::into_bytes(ma, &mut buf)?;
-                    }
-                    if let Some(props) = &msg.properties {
-                        ser::into_bytes(props, &mut buf)?;
-                    }
-                    if let Some(ap) = &msg.application_properties {
-                        ser::into_bytes(ap, &mut buf)?;
-                    }
-                    ser::into_bytes(&msg.body, &mut buf)?;
-                    if let Some(footer) = &msg.footer {
-                        ser::into_bytes(footer, &mut buf)?;
-                    }
+                    msg.encode(&mut buf)?;
                 }
                 buf[6..8].copy_from_slice(&f.channel.to_be_bytes()[..]);
             }
@@ -287,8 +996,9 @@ impl<'a> Frame<'a> {
             }
             Frame::Sasl(f) => {
                 buf[5] = 0x01;
-                ser::into_bytes(f, &mut buf).unwrap();
+                ser::into_bytes(f, &mut buf)?;
             }
+            Frame::Empty => {}
         }
 
         buf[4] = 2; // doff
@@ -296,6 +1006,119 @@ impl<'a> Frame<'a> {
         buf[..4].copy_from_slice(&len.to_be_bytes()[..]);
         Ok(buf)
     }
+
+    /// Split a message payload over transfer frames of at most `max_frame_size` bytes
+    ///
+    /// If the payload does not fit in a single frame, it is split over multiple transfers, all
+    /// but the last of which have `more` set. The first frame is only encoded by
+    /// `Fragments::encode()`, once the delivery id is known; it is sized to fit any id, and a
+    /// delivery tag as long as the one in `transfer`.
+    pub(crate) fn fragment<'b>(
+        channel: u16,
+        mut transfer: amqp::Transfer<'b>,
+        payload: &'b [u8],
+        max_frame_size: usize,
+    ) -> Result<Fragments<'b>, Error> {
+        let header = |transfer: &amqp::Transfer<'_>| transfer_header(channel, transfer);
+        transfer.delivery_id = Some(u32::MAX);
+        let single = header(&transfer)?;
+        if single.len() + payload.len() <= max_frame_size {
+            return Ok(Fragments {
+                channel,
+                transfer,
+                first: payload,
+                rest: Vec::new(),
+            });
+        }
+
+        // Continuation frames only need the handle, the rest carries over from the first frame
+        transfer.more = Some(true);
+        let first = header(&transfer)?;
+        let continuation = header(&amqp::Transfer {
+            handle: transfer.handle,
+            more: Some(true),
+            ..Default::default()
+        })?;
+        let last = header(&amqp::Transfer {
+            handle: transfer.handle,
+            ..Default::default()
+        })?;
+        if first.len().max(continuation.len()) >= max_frame_size {
+            return Err(Error::InvalidData);
+        }
+
+        let (chunk, mut rest) = payload.split_at((max_frame_size - first.len()).min(payload.len()));
+        let mut frames = Vec::new();
+        while last.len() + rest.len() > max_frame_size {
+            let (chunk, tail) =
+                rest.split_at((max_frame_size - continuation.len()).min(rest.len()));
+            frames.push(transfer_frame(&continuation, chunk));
+            rest = tail;
+        }
+
+        frames.push(transfer_frame(&last, rest));
+        Ok(Fragments {
+            channel,
+            transfer,
+            first: chunk,
+            rest: frames,
+        })
+    }
+}
+
+/// The frames making up a delivery, as split up by `Frame::fragment()`
+pub(crate) struct Fragments<'a> {
+    channel: u16,
+    /// The first transfer, still to be numbered
+    transfer: amqp::Transfer<'a>,
+    /// The part of the payload carried by the first frame
+    first: &'a [u8],
+    /// Continuation frames, already encoded
+    rest: Vec<Vec<u8>>,
+}
+
+impl Fragments<'_> {
+    /// The number of frames making up the delivery
+    pub(crate) fn len(&self) -> usize {
+        self.rest.len() + 1
+    }
+
+    /// Encode all frames, identifying the delivery by `delivery_id` and `delivery_tag`
+    pub(crate) fn encode(
+        mut self,
+        delivery_id: u32,
+        delivery_tag: Vec<u8>,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        debug_assert_eq!(
+            self.transfer.delivery_tag.as_ref().map(|tag| tag.len()),
+            Some(delivery_tag.len())
+        );
+        self.transfer.delivery_id = Some(delivery_id);
+        self.transfer.delivery_tag = Some(delivery_tag);
+        let header = transfer_header(self.channel, &self.transfer)?;
+        let mut frames = Vec::with_capacity(self.len());
+        frames.push(transfer_frame(&header, self.first));
+        frames.append(&mut self.rest);
+        Ok(frames)
+    }
+}
+
+/// Encode the start of a transfer frame, up to where the payload goes
+fn transfer_header(channel: u16, transfer: &amqp::Transfer<'_>) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![0, 0, 0, 0, 2, 0x00];
+    buf.extend_from_slice(&channel.to_be_bytes()[..]);
+    ser::into_bytes(transfer, &mut buf)?;
+    Ok(buf)
+}
+
+/// Complete a transfer frame from its header and a chunk of payload
+fn transfer_frame(header: &[u8], chunk: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(header.len() + chunk.len());
+    buf.extend_from_slice(header);
+    buf.extend_from_slice(chunk);
+    let len = buf.len() as u32;
+    buf[..4].copy_from_slice(&len.to_be_bytes()[..]);
+    buf
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -305,11 +1128,18 @@ pub enum Protocol {
 }
 
 impl Protocol {
-    fn from_bytes(bytes: &[u8]) -> Self {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
         match bytes {
-            SASL_PROTO_HEADER => Protocol::Sasl,
-            AMQP_PROTO_HEADER => Protocol::Amqp,
-            p => panic!("invalid protocol header {:?}", p),
+            SASL_PROTO_HEADER => Ok(Protocol::Sasl),
+            AMQP_PROTO_HEADER => Ok(Protocol::Amqp),
+            _ => Err(Error::InvalidData),
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Protocol::Sasl => "SASL header",
+            Protocol::Amqp => "AMQP header",
         }
     }
 
@@ -321,10 +1151,12 @@ impl Protocol {
     }
 }
 
-/*
-
-#[derive(Debug)]
-enum ConnectionState {
+/// Connection states, as defined in section 2.4.6 of the AMQP 1.0 specification
+///
+/// The SASL security layer is negotiated before the AMQP connection starts, so SASL frames are
+/// only accepted in the `Start` state and don't cause any transitions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConnectionState {
     Start,
     HdrRcvd,
     HdrSent,
@@ -341,27 +1173,83 @@ enum ConnectionState {
     End,
 }
 
-struct Session {
-    pub next_incoming_id: u32,
-    pub incoming_window: u32,
-    pub next_outgoing_id: u32,
-    pub outgoing_window: u32,
-    pub remote_incoming_window: u32,
-    pub remote_outgoing_window: u32,
-}
+impl ConnectionState {
+    /// Compute the new state after sending `frame`
+    pub fn sent(self, frame: &Frame<'_>) -> Result<Self, ConnectionError> {
+        use ConnectionState::*;
+        let next = match (self, frame) {
+            (Start, Frame::Header(Protocol::Sasl)) | (Start, Frame::Sasl(_)) => Some(Start),
+            (_, Frame::Header(Protocol::Sasl)) | (_, Frame::Sasl(_)) => None,
+            (Start, Frame::Header(Protocol::Amqp)) => Some(HdrSent),
+            (HdrRcvd, Frame::Header(Protocol::Amqp)) => Some(HdrExch),
+            (_, Frame::Header(Protocol::Amqp)) => None,
+            (_, Frame::Empty) => Some(self),
+            (_, Frame::Amqp(frame)) => match (self, &frame.performative) {
+                (HdrSent, amqp::Performative::Open(_)) => Some(OpenPipe),
+                (HdrExch, amqp::Performative::Open(_)) => Some(OpenSent),
+                (OpenRcvd, amqp::Performative::Open(_)) => Some(Opened),
+                (_, amqp::Performative::Open(_)) => None,
+                (OpenPipe, amqp::Performative::Close(_)) => Some(OcPipe),
+                (OpenSent, amqp::Performative::Close(_)) => Some(ClosePipe),
+                (Opened, amqp::Performative::Close(close)) if close.error.is_some() => {
+                    Some(Discarding)
+                }
+                (Opened, amqp::Performative::Close(_)) => Some(CloseSent),
+                (CloseRcvd, amqp::Performative::Close(_)) => Some(End),
+                (_, amqp::Performative::Close(_)) => None,
+                // Frames on the connection may be pipelined after our open
+                (OpenPipe, _) | (OpenSent, _) | (Opened, _) | (CloseRcvd, _) => Some(self),
+                _ => None,
+            },
+        };
 
-enum SessionState {
-    Unmapped,
-    BeginSent,
-    BeginRcvd,
-    Mapped,
-    EndSent,
-    EndRcvd,
-    Discarding,
-}
+        next.ok_or(ConnectionError::IllegalSend {
+            state: self,
+            frame: frame.name(),
+        })
+    }
+
+    /// Compute the new state after receiving `frame`
+    pub fn received(self, frame: &Frame<'_>) -> Result<Self, ConnectionError> {
+        use ConnectionState::*;
+        let next = match (self, frame) {
+            (Start, Frame::Header(Protocol::Sasl)) | (Start, Frame::Sasl(_)) => Some(Start),
+            (_, Frame::Header(Protocol::Sasl)) | (_, Frame::Sasl(_)) => None,
+            (Start, Frame::Header(Protocol::Amqp)) => Some(HdrRcvd),
+            (HdrSent, Frame::Header(Protocol::Amqp)) => Some(HdrExch),
+            (OpenPipe, Frame::Header(Protocol::Amqp)) => Some(OpenSent),
+            (OcPipe, Frame::Header(Protocol::Amqp)) => Some(ClosePipe),
+            (_, Frame::Header(Protocol::Amqp)) => None,
+            (_, Frame::Empty) => Some(self),
+            (_, Frame::Amqp(frame)) => match (self, &frame.performative) {
+                (HdrExch, amqp::Performative::Open(_)) => Some(OpenRcvd),
+                (OpenSent, amqp::Performative::Open(_)) => Some(Opened),
+                (ClosePipe, amqp::Performative::Open(_)) => Some(CloseSent),
+                (_, amqp::Performative::Open(_)) => None,
+                (Opened, amqp::Performative::Close(_)) => Some(CloseRcvd),
+                (CloseSent, amqp::Performative::Close(_)) => Some(End),
+                (Discarding, amqp::Performative::Close(_)) => Some(End),
+                (_, amqp::Performative::Close(_)) => None,
+                (Opened, _) | (CloseSent, _) | (Discarding, _) => Some(self),
+                _ => None,
+            },
+        };
 
-*/
+        next.ok_or(ConnectionError::IllegalReceive {
+            state: self,
+            frame: frame.name(),
+        })
+    }
+}
 
 pub const AMQP_PROTO_HEADER: &[u8] = b"AMQP\x00\x01\x00\x00";
 pub const SASL_PROTO_HEADER: &[u8] = b"AMQP\x03\x01\x00\x00";
 pub const PROTO_HEADER_LENGTH: usize = 8;
+/// The size of a frame header, which includes the frame's 4-byte size
+pub const FRAME_HEADER_LENGTH: u32 = 8;
+/// The largest frame we accept, as advertised in our `Open`
+pub const MAX_FRAME_SIZE: u32 = 64 * 1024;
+/// Every peer has to accept frames up to this size, see section 2.7.1
+pub const MIN_MAX_FRAME_SIZE: u32 = 512;
+/// The idle timeout advertised in our `Open`, unless overridden with `Client::set_idle_timeout()`
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
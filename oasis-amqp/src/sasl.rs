@@ -1,14 +1,19 @@
+use std::fmt;
+
 use oasis_amqp_macros::amqp;
 use serde::{self, Deserialize, Serialize};
 use serde_bytes::Bytes;
 
-use crate::Described;
+use crate::amqp::Symbol;
+use crate::{ConnectionError, Described};
 
 #[amqp]
 #[derive(Debug, Eq, PartialEq, Serialize)]
 pub enum Frame<'a> {
     Mechanisms(Mechanisms),
     Init(Init<'a>),
+    Challenge(Challenge<'a>),
+    Response(Response<'a>),
     Outcome(Outcome<'a>),
 }
 
@@ -27,12 +32,192 @@ pub struct Init<'a> {
     pub hostname: Option<&'a str>,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[amqp(descriptor("amqp:sasl-challenge:list", 0x0000_0000_0000_0042))]
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Challenge<'a> {
+    #[serde(borrow)]
+    pub challenge: &'a Bytes,
+}
+
+#[amqp(descriptor("amqp:sasl-response:list", 0x0000_0000_0000_0043))]
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Response<'a> {
+    #[serde(borrow)]
+    pub response: &'a Bytes,
+}
+
+/// A SASL mechanism name
+///
+/// Mechanisms not known to this crate are kept as `Other`, so that a server offering them
+/// does not prevent negotiating one of the others.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Mechanism {
     Anonymous,
+    External,
     Plain,
     ScramSha1,
+    ScramSha256,
+    Other(String),
+}
+
+impl Mechanism {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Mechanism::Anonymous => "ANONYMOUS",
+            Mechanism::External => "EXTERNAL",
+            Mechanism::Plain => "PLAIN",
+            Mechanism::ScramSha1 => "SCRAM-SHA-1",
+            Mechanism::ScramSha256 => "SCRAM-SHA-256",
+            Mechanism::Other(name) => name,
+        }
+    }
+}
+
+impl From<&str> for Mechanism {
+    fn from(name: &str) -> Self {
+        match name {
+            "ANONYMOUS" => Mechanism::Anonymous,
+            "EXTERNAL" => Mechanism::External,
+            "PLAIN" => Mechanism::Plain,
+            "SCRAM-SHA-1" => Mechanism::ScramSha1,
+            "SCRAM-SHA-256" => Mechanism::ScramSha256,
+            _ => Mechanism::Other(name.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for Mechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Mechanism {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        Symbol(self.as_str()).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Mechanism {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let Symbol(name) = Symbol::deserialize(deserializer)?;
+        Ok(Mechanism::from(name))
+    }
+}
+
+/// Client side of a SASL mechanism, as used by `Client::authenticate()`
+pub trait SaslMechanism: Send {
+    /// The mechanism name to send in the sasl-init frame
+    fn mechanism(&self) -> Mechanism;
+
+    /// The initial response to send in the sasl-init frame
+    fn initial_response(&mut self) -> Result<Option<Vec<u8>>, ConnectionError>;
+
+    /// Respond to a challenge from the server
+    fn challenge(&mut self, _challenge: &[u8]) -> Result<Vec<u8>, ConnectionError> {
+        Err(ConnectionError::UnexpectedFrame {
+            expected: "sasl-outcome",
+            found: "sasl-challenge",
+        })
+    }
+
+    /// Check the additional data from a successful outcome
+    fn outcome(&mut self, _additional_data: Option<&[u8]>) -> Result<(), ConnectionError> {
+        Ok(())
+    }
+}
+
+/// The PLAIN mechanism (RFC 4616), which sends the password in the clear
+pub struct Plain {
+    user: String,
+    password: String,
+}
+
+impl Plain {
+    pub fn new(user: &str, password: &str) -> Self {
+        Self {
+            user: user.to_owned(),
+            password: password.to_owned(),
+        }
+    }
+}
+
+impl SaslMechanism for Plain {
+    fn mechanism(&self) -> Mechanism {
+        Mechanism::Plain
+    }
+
+    fn initial_response(&mut self) -> Result<Option<Vec<u8>>, ConnectionError> {
+        let mut response = vec![0u8];
+        response.extend_from_slice(self.user.as_bytes());
+        response.push(0);
+        response.extend_from_slice(self.password.as_bytes());
+        Ok(Some(response))
+    }
+}
+
+/// The ANONYMOUS mechanism (RFC 4505)
+#[derive(Default)]
+pub struct Anonymous {
+    trace: Option<String>,
+}
+
+impl Anonymous {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send trace information, such as an email address, along with the request
+    pub fn trace(mut self, trace: &str) -> Self {
+        self.trace = Some(trace.to_owned());
+        self
+    }
+}
+
+impl SaslMechanism for Anonymous {
+    fn mechanism(&self) -> Mechanism {
+        Mechanism::Anonymous
+    }
+
+    fn initial_response(&mut self) -> Result<Option<Vec<u8>>, ConnectionError> {
+        Ok(Some(self.trace.clone().unwrap_or_default().into_bytes()))
+    }
+}
+
+/// The EXTERNAL mechanism (RFC 4422), for credentials established outside of SASL
+///
+/// This is typically used with a TLS client certificate.
+#[derive(Default)]
+pub struct External {
+    authzid: Option<String>,
+}
+
+impl External {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Act as the given authorization identity instead of the one derived from the credentials
+    pub fn authzid(mut self, authzid: &str) -> Self {
+        self.authzid = Some(authzid.to_owned());
+        self
+    }
+}
+
+impl SaslMechanism for External {
+    fn mechanism(&self) -> Mechanism {
+        Mechanism::External
+    }
+
+    fn initial_response(&mut self) -> Result<Option<Vec<u8>>, ConnectionError> {
+        Ok(Some(self.authzid.clone().unwrap_or_default().into_bytes()))
+    }
 }
 
 #[amqp(descriptor("amqp:sasl-outcome:list", 0x0000_0000_0000_0044))]
@@ -43,7 +228,7 @@ pub struct Outcome<'a> {
     pub additional_data: Option<&'a Bytes>,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
 pub enum Code {
     Ok,
     Auth,
@@ -51,3 +236,12 @@ pub enum Code {
     SysPerm,
     SysTemp,
 }
+
+impl Serialize for Code {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u8(*self as u8)
+    }
+}
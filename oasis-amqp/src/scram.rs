@@ -0,0 +1,218 @@
+//! Client side of the SCRAM SASL mechanisms (RFC 5802 and RFC 7677)
+//!
+//! Channel binding is not supported, so the GS2 header is always `n,,`.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::sasl::{self, SaslMechanism};
+use crate::ConnectionError;
+
+/// The hash function used by a SCRAM mechanism
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hash {
+    Sha1,
+    Sha256,
+}
+
+impl Hash {
+    /// The SASL mechanism using this hash function
+    pub fn mechanism(self) -> sasl::Mechanism {
+        match self {
+            Hash::Sha1 => sasl::Mechanism::ScramSha1,
+            Hash::Sha256 => sasl::Mechanism::ScramSha256,
+        }
+    }
+
+    fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Hash::Sha1 => Sha1::digest(data).to_vec(),
+            Hash::Sha256 => Sha256::digest(data).to_vec(),
+        }
+    }
+
+    fn hmac(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Hash::Sha1 => {
+                let mut mac = Hmac::<Sha1>::new_from_slice(key).unwrap();
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Hash::Sha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+
+    /// The `Hi()` function from RFC 5802, which is PBKDF2 with the HMAC as its PRF
+    fn hi(self, password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+        let mut input = salt.to_vec();
+        input.extend_from_slice(&1u32.to_be_bytes());
+        let mut prev = self.hmac(password, &input);
+        let mut result = prev.clone();
+        for _ in 1..iterations {
+            prev = self.hmac(password, &prev);
+            for (r, p) in result.iter_mut().zip(&prev) {
+                *r ^= p;
+            }
+        }
+        result
+    }
+}
+
+/// Client state for a single SCRAM authentication exchange
+pub struct ScramClient {
+    hash: Hash,
+    password: String,
+    client_first_bare: String,
+    nonce: String,
+    state: State,
+}
+
+impl ScramClient {
+    pub fn new(hash: Hash, user: &str, password: &str) -> Self {
+        let mut nonce = [0u8; 18];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        Self::with_nonce(hash, user, password, &BASE64.encode(nonce))
+    }
+
+    /// Use the given client nonce instead of a random one
+    pub fn with_nonce(hash: Hash, user: &str, password: &str, nonce: &str) -> Self {
+        let user = user.replace('=', "=3D").replace(',', "=2C");
+        Self {
+            hash,
+            password: password.to_owned(),
+            client_first_bare: format!("n={},r={}", user, nonce),
+            nonce: nonce.to_owned(),
+            state: State::Initial,
+        }
+    }
+
+    /// The client-first message, sent as the initial response
+    pub fn client_first(&self) -> Vec<u8> {
+        format!("{}{}", GS2_HEADER, self.client_first_bare).into_bytes()
+    }
+
+    fn client_final(&mut self, server_first: &[u8]) -> Result<Vec<u8>, ConnectionError> {
+        let server_first = std::str::from_utf8(server_first)
+            .map_err(|_| scram_error("server-first message is not valid UTF-8"))?;
+
+        let (mut nonce, mut salt, mut iterations) = (None, None, None);
+        for attr in server_first.split(',') {
+            match attr.split_at(attr.find('=').unwrap_or(0)) {
+                ("r", value) => nonce = Some(&value[1..]),
+                ("s", value) => salt = BASE64.decode(&value[1..]).ok(),
+                ("i", value) => iterations = value[1..].parse::<u32>().ok(),
+                ("m", _) => return Err(scram_error("unsupported mandatory extension")),
+                _ => {}
+            }
+        }
+
+        let (nonce, salt, iterations) = match (nonce, salt, iterations) {
+            (Some(nonce), Some(salt), Some(iterations)) if iterations > 0 => {
+                (nonce, salt, iterations)
+            }
+            _ => return Err(scram_error("invalid server-first message")),
+        };
+        if !nonce.starts_with(&self.nonce) || nonce.len() == self.nonce.len() {
+            return Err(scram_error("server nonce does not extend the client nonce"));
+        }
+
+        let without_proof = format!("c={},r={}", BASE64.encode(GS2_HEADER), nonce);
+        let auth_message = format!(
+            "{},{},{}",
+            self.client_first_bare, server_first, without_proof
+        );
+
+        let salted = self.hash.hi(self.password.as_bytes(), &salt, iterations);
+        let client_key = self.hash.hmac(&salted, b"Client Key");
+        let stored_key = self.hash.digest(&client_key);
+        let signature = self.hash.hmac(&stored_key, auth_message.as_bytes());
+        let proof = client_key
+            .iter()
+            .zip(&signature)
+            .map(|(k, s)| k ^ s)
+            .collect::<Vec<_>>();
+
+        let server_key = self.hash.hmac(&salted, b"Server Key");
+        let server_signature = self.hash.hmac(&server_key, auth_message.as_bytes());
+        self.state = State::Final { server_signature };
+        Ok(format!("{},p={}", without_proof, BASE64.encode(proof)).into_bytes())
+    }
+
+    fn verify(&mut self, server_final: &[u8]) -> Result<(), ConnectionError> {
+        let expected = match &self.state {
+            State::Final { server_signature } => server_signature,
+            _ => return Err(scram_error("unexpected server-final message")),
+        };
+
+        let server_final = std::str::from_utf8(server_final)
+            .map_err(|_| scram_error("server-final message is not valid UTF-8"))?;
+        if let Some(error) = server_final.strip_prefix("e=") {
+            return Err(ConnectionError::Scram(format!("server error: {}", error)));
+        }
+
+        let signature = server_final
+            .split(',')
+            .find_map(|attr| attr.strip_prefix("v="))
+            .and_then(|value| BASE64.decode(value).ok());
+        match signature {
+            Some(signature) if &signature == expected => {
+                self.state = State::Verified;
+                Ok(())
+            }
+            _ => Err(scram_error("invalid server signature")),
+        }
+    }
+}
+
+impl SaslMechanism for ScramClient {
+    fn mechanism(&self) -> sasl::Mechanism {
+        self.hash.mechanism()
+    }
+
+    fn initial_response(&mut self) -> Result<Option<Vec<u8>>, ConnectionError> {
+        Ok(Some(self.client_first()))
+    }
+
+    /// The first challenge carries the server-first message, which is answered with the
+    /// client-final message. Some servers send the server-final message as a second challenge
+    /// instead of as additional data in the outcome; that is answered with an empty response.
+    fn challenge(&mut self, data: &[u8]) -> Result<Vec<u8>, ConnectionError> {
+        match &self.state {
+            State::Initial => self.client_final(data),
+            State::Final { .. } => {
+                self.verify(data)?;
+                Ok(Vec::new())
+            }
+            State::Verified => Err(scram_error("unexpected challenge after server-final")),
+        }
+    }
+
+    /// Fails if the server did not prove that it knows the password
+    fn outcome(&mut self, additional_data: Option<&[u8]>) -> Result<(), ConnectionError> {
+        match (&self.state, additional_data) {
+            (State::Final { .. }, Some(data)) => self.verify(data),
+            (State::Verified, None) => Ok(()),
+            _ => Err(scram_error("server did not send its signature")),
+        }
+    }
+}
+
+enum State {
+    Initial,
+    Final { server_signature: Vec<u8> },
+    Verified,
+}
+
+fn scram_error(msg: &str) -> ConnectionError {
+    ConnectionError::Scram(msg.to_owned())
+}
+
+const GS2_HEADER: &str = "n,,";
@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::str::FromStr;
 
 use serde::{ser, Serialize};
@@ -16,7 +17,9 @@ where
     let mut serializer = Serializer {
         output,
         offsets: vec![],
+        arrays: vec![],
         str_as_symbol: false,
+        fixed: None,
     };
     value.serialize(&mut serializer)?;
     Ok(())
@@ -25,7 +28,11 @@ where
 pub struct Serializer<'a> {
     output: &'a mut Vec<u8>,
     offsets: Vec<usize>,
+    /// Start offsets of the elements written so far, for each array being serialized
+    arrays: Vec<Vec<usize>>,
     str_as_symbol: bool,
+    /// Format code for the next value, for AMQP types without a serde counterpart
+    fixed: Option<u8>,
 }
 
 impl ser::Serializer for &'_ mut Serializer<'_> {
@@ -96,7 +103,7 @@ impl ser::Serializer for &'_ mut Serializer<'_> {
     }
 
     fn serialize_i32(self, v: i32) -> Result<()> {
-        if v < 256 {
+        if let Ok(v) = i8::try_from(v) {
             self.output.push(0x54);
             self.output.push(v as u8);
         } else {
@@ -107,7 +114,11 @@ impl ser::Serializer for &'_ mut Serializer<'_> {
     }
 
     fn serialize_i64(self, v: i64) -> Result<()> {
-        if v < 256 {
+        if let Some(code) = self.fixed.take() {
+            // A timestamp
+            self.output.push(code);
+            self.output.extend_from_slice(&v.to_be_bytes()[..]);
+        } else if let Ok(v) = i8::try_from(v) {
             self.output.push(0x55);
             self.output.push(v as u8);
         } else {
@@ -143,7 +154,7 @@ impl ser::Serializer for &'_ mut Serializer<'_> {
                 .push(if self.str_as_symbol { 0xa3 } else { 0xa1 });
             self.output.push(v.len() as u8);
             self.output.extend_from_slice(v.as_bytes());
-        } else if v.len() < std::u32::MAX as usize {
+        } else if v.len() < u32::MAX as usize {
             self.output
                 .push(if self.str_as_symbol { 0xb3 } else { 0xb1 });
             self.output
@@ -158,11 +169,18 @@ impl ser::Serializer for &'_ mut Serializer<'_> {
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<()> {
-        if v.len() < 256 {
+        if let Some(code) = self.fixed.take() {
+            // Decimals and uuids are written as is, without a length
+            if v.len() != fixed_width(code) {
+                return Err(Error::InvalidData);
+            }
+            self.output.push(code);
+            self.output.extend_from_slice(v);
+        } else if v.len() < 256 {
             self.output.push(0xa0);
             self.output.push(v.len() as u8);
             self.output.extend_from_slice(v);
-        } else if v.len() < std::u32::MAX as usize {
+        } else if v.len() < u32::MAX as usize {
             self.output.push(0xb0);
             self.output
                 .extend_from_slice(&(v.len() as u32).to_be_bytes()[..]);
@@ -204,7 +222,7 @@ impl ser::Serializer for &'_ mut Serializer<'_> {
             self.output.push(0xa3);
             self.output.push(v.len() as u8);
             self.output.extend_from_slice(v);
-        } else if v.len() < std::u32::MAX as usize {
+        } else if v.len() < u32::MAX as usize {
             self.output.push(0xb3);
             self.output
                 .extend_from_slice(&(v.len() as u32).to_be_bytes()[..]);
@@ -224,6 +242,19 @@ impl ser::Serializer for &'_ mut Serializer<'_> {
             return value.serialize(self);
         }
 
+        let fixed = match name {
+            "amqp:timestamp" => Some(0x83),
+            "amqp:decimal32" => Some(0x74),
+            "amqp:decimal64" => Some(0x84),
+            "amqp:decimal128" => Some(0x94),
+            "amqp:uuid" => Some(0x98),
+            _ => None,
+        };
+        if fixed.is_some() {
+            self.fixed = fixed;
+            return value.serialize(self);
+        }
+
         self.output.push(0x00);
         let sep = name.find('|').unwrap();
         let (name, code) = name.split_at(sep);
@@ -264,6 +295,7 @@ impl ser::Serializer for &'_ mut Serializer<'_> {
         self.output.extend_from_slice(&[0, 0, 0, 0]);
         let len = len.unwrap() as u32;
         self.output.extend_from_slice(&len.to_be_bytes());
+        self.arrays.push(vec![]);
         Ok(self)
     }
 
@@ -276,13 +308,19 @@ impl ser::Serializer for &'_ mut Serializer<'_> {
         Ok(self)
     }
 
-    // Tuple structs look just like sequences in JSON.
+    // Described values of any type, written as the descriptor followed by the value
     fn serialize_tuple_struct(
         self,
-        _name: &'static str,
+        name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
-        unimplemented!()
+        match name {
+            "amqp:described" => {
+                self.output.push(0x00);
+                Ok(self)
+            }
+            _ => unimplemented!(),
+        }
     }
 
     fn serialize_tuple_variant(
@@ -348,11 +386,33 @@ impl ser::SerializeSeq for &'_ mut Serializer<'_> {
     where
         T: ?Sized + Serialize,
     {
+        let start = self.output.len();
+        self.arrays.last_mut().unwrap().push(start);
         value.serialize(&mut **self)
     }
 
     // Close the sequence.
     fn end(self) -> Result<()> {
+        // Array elements share the constructor of the first element, so drop the others'.
+        // Described elements are left alone for now.
+        let mut starts = self.arrays.pop().unwrap();
+        if let Some(&first) = starts.first() {
+            if !starts.iter().all(|&s| self.output[s] == self.output[first]) {
+                starts = widen(self.output, &starts);
+            }
+
+            let constructor = self.output[first];
+            if !starts.iter().all(|&s| self.output[s] == constructor) {
+                return Err(Error::Serialization(
+                    "array elements must all have the same type".into(),
+                ));
+            } else if constructor != 0x00 {
+                for &start in starts[1..].iter().rev() {
+                    self.output.remove(start);
+                }
+            }
+        }
+
         let offset = self.offsets.pop().unwrap();
         let len = (self.output.len() - offset - 4) as u32;
         let dst = &mut self.output[offset..offset + 4];
@@ -385,11 +445,11 @@ impl ser::SerializeTupleStruct for &'_ mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T>(&mut self, _value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        unimplemented!()
+        value.serialize(&mut **self)
     }
 
     fn end(self) -> Result<()> {
@@ -476,4 +536,59 @@ impl ser::SerializeStructVariant for &'_ mut Serializer<'_> {
     }
 }
 
+/// Re-encode the array elements starting at `starts` in the widest encoding of their type
+///
+/// Values of the same type can have different constructors depending on their size (like
+/// `smalluint` and `uint`), which can't be mixed in an array. Returns the new start offsets.
+fn widen(output: &mut Vec<u8>, starts: &[usize]) -> Vec<usize> {
+    let mut widened = Vec::with_capacity(output.len() - starts[0]);
+    let mut new_starts = Vec::with_capacity(starts.len());
+    for (i, &start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).copied().unwrap_or(output.len());
+        new_starts.push(starts[0] + widened.len());
+        let (constructor, value) = (output[start], &output[start + 1..end]);
+        match (constructor, value) {
+            (0x41, _) => widened.extend_from_slice(&[0x56, 0x01]),
+            (0x42, _) => widened.extend_from_slice(&[0x56, 0x00]),
+            (0x43, _) => widened.extend_from_slice(&[0x70, 0, 0, 0, 0]),
+            (0x52, &[v]) => {
+                widened.push(0x70);
+                widened.extend_from_slice(&u32::from(v).to_be_bytes());
+            }
+            (0x44, _) => widened.extend_from_slice(&[0x80, 0, 0, 0, 0, 0, 0, 0, 0]),
+            (0x53, &[v]) => {
+                widened.push(0x80);
+                widened.extend_from_slice(&u64::from(v).to_be_bytes());
+            }
+            (0x54, &[v]) => {
+                widened.push(0x71);
+                widened.extend_from_slice(&i32::from(v as i8).to_be_bytes());
+            }
+            (0x55, &[v]) => {
+                widened.push(0x81);
+                widened.extend_from_slice(&i64::from(v as i8).to_be_bytes());
+            }
+            (0xa0 | 0xa1 | 0xa3, [len, data @ ..]) => {
+                widened.push(constructor + 0x10);
+                widened.extend_from_slice(&u32::from(*len).to_be_bytes());
+                widened.extend_from_slice(data);
+            }
+            _ => widened.extend_from_slice(&output[start..end]),
+        }
+    }
+
+    output.truncate(starts[0]);
+    output.extend_from_slice(&widened);
+    new_starts
+}
+
+/// Width of the values of fixed-width types written without a length
+fn fixed_width(code: u8) -> usize {
+    match code {
+        0x74 => 4,
+        0x83 | 0x84 => 8,
+        _ => 16,
+    }
+}
+
 type Result<T> = std::result::Result<T, Error>;
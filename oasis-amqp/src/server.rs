@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, ToSocketAddrs};
+
+use crate::proto::{unexpected, ConnectionState, Frame, Protocol, Shared};
+use crate::session::amqp_frame;
+use crate::{sasl, ConnectionError, Session};
+
+/// Accepts incoming AMQP connections over TCP
+pub struct Listener {
+    listener: TcpListener,
+}
+
+impl Listener {
+    pub async fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self, ConnectionError> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, ConnectionError> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Wait for the next client to connect
+    ///
+    /// The handshake is left to `ServerConnection::authenticate()` and `open()`, so that a slow
+    /// client doesn't hold up the accept loop.
+    pub async fn accept(&self) -> Result<(ServerConnection, SocketAddr), ConnectionError> {
+        let (stream, addr) = self.listener.accept().await?;
+        Ok((ServerConnection::from_stream(stream), addr))
+    }
+}
+
+/// A connection initiated by an AMQP peer
+///
+/// Like `Client`, the socket is owned by a driver task. The driver answers the peer's AMQP
+/// header, `Begin` and `Flow` frames itself; sessions and links begun by the peer are surfaced
+/// through `accept_session()` and `Session::accept_link()`.
+#[derive(Clone)]
+pub struct ServerConnection {
+    shared: Arc<Shared>,
+}
+
+impl ServerConnection {
+    /// Serve a connection over an already established transport
+    ///
+    /// This spawns the driver task, so it must be called from within a Tokio runtime.
+    pub fn from_stream<T>(io: T) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        Self {
+            shared: Shared::spawn(io, true),
+        }
+    }
+
+    /// The current state of the connection
+    pub fn state(&self) -> ConnectionState {
+        self.shared.inner.lock().unwrap().state
+    }
+
+    /// Run the SASL exchange, returning the identity established by the `authenticator`
+    ///
+    /// If authentication fails, the outcome is sent to the client and `ConnectionError::Sasl`
+    /// is returned; the connection should be dropped.
+    pub async fn authenticate(
+        &mut self,
+        authenticator: &dyn Authenticator,
+    ) -> Result<String, ConnectionError> {
+        self.shared.expect_header(Protocol::Sasl).await?;
+        self.shared.send(&Frame::Header(Protocol::Sasl)).await?;
+        let offered = authenticator.mechanisms();
+        let mechanisms = Frame::Sasl(sasl::Frame::Mechanisms(sasl::Mechanisms {
+            sasl_server_mechanisms: offered.clone(),
+        }));
+        self.shared.send(&mechanisms).await?;
+
+        let init = self.shared.recv().await?;
+        let result = match init.frame() {
+            Frame::Sasl(sasl::Frame::Init(init)) if offered.contains(&init.mechanism) => {
+                let response = init.initial_response.map(|response| &**response);
+                authenticator.authenticate(&init.mechanism, response)
+            }
+            Frame::Sasl(sasl::Frame::Init(_)) => Err(sasl::Code::Auth),
+            frame => return Err(unexpected("sasl-init", frame)),
+        };
+
+        let code = match &result {
+            Ok(_) => sasl::Code::Ok,
+            Err(code) => *code,
+        };
+        let outcome = Frame::Sasl(sasl::Frame::Outcome(sasl::Outcome {
+            code,
+            additional_data: None,
+        }));
+        self.shared.send(&outcome).await?;
+
+        result.map_err(|code| ConnectionError::Sasl {
+            code,
+            additional_data: None,
+        })
+    }
+
+    /// Wait for the peer's `Open` and answer it with ours
+    ///
+    /// A client asking for SASL without a preceding `authenticate()` is sent the AMQP header,
+    /// telling it which protocol we do support, and refused.
+    pub async fn open(&mut self, container_id: &str) -> Result<(), ConnectionError> {
+        let header = self.shared.recv().await?;
+        match header.frame() {
+            Frame::Header(Protocol::Amqp) => {}
+            Frame::Header(Protocol::Sasl) => {
+                self.shared.send(&Frame::Header(Protocol::Amqp)).await?;
+                return Err(unexpected(Protocol::Amqp.name(), header.frame()));
+            }
+            frame => return Err(unexpected(Protocol::Amqp.name(), frame)),
+        }
+        self.shared.opened().await?;
+        let open = self.shared.inner.lock().unwrap().open(container_id);
+        self.shared.send(&amqp_frame(0, open)).await
+    }
+
+    /// The largest frame that can be sent to the peer, as negotiated when opening the connection
+    pub fn max_frame_size(&self) -> u32 {
+        self.shared.inner.lock().unwrap().max_frame_size
+    }
+
+    /// Set the idle timeout to advertise in our `Open`, or `None` to disable it
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.shared.inner.lock().unwrap().idle_timeout = timeout;
+    }
+
+    /// Wait for the peer to begin a new session
+    pub async fn accept_session(&self) -> Result<Session, ConnectionError> {
+        let channel = self
+            .shared
+            .wait(|inner| Ok(inner.incoming_sessions.pop_front()))
+            .await?;
+        Ok(Session {
+            shared: self.shared.clone(),
+            channel,
+        })
+    }
+
+    /// Close the connection, waiting for the peer to close its side
+    pub async fn close(self) -> Result<(), ConnectionError> {
+        self.shared.close().await
+    }
+}
+
+/// Decides which clients may connect to a `ServerConnection`
+///
+/// Only mechanisms that complete with the initial response are supported, since no challenges
+/// are sent to the client.
+pub trait Authenticator: Send + Sync {
+    /// The mechanisms to offer, in order of preference
+    fn mechanisms(&self) -> Vec<sasl::Mechanism>;
+
+    /// Check the client's initial response, returning the authenticated identity
+    fn authenticate(
+        &self,
+        mechanism: &sasl::Mechanism,
+        response: Option<&[u8]>,
+    ) -> Result<String, sasl::Code>;
+}
+
+/// Authenticates clients against a fixed set of users with PLAIN
+#[derive(Default)]
+pub struct Users {
+    users: HashMap<String, String>,
+    anonymous: bool,
+}
+
+impl Users {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn user(mut self, name: &str, password: &str) -> Self {
+        self.users.insert(name.to_owned(), password.to_owned());
+        self
+    }
+
+    /// Also let clients connect with ANONYMOUS, as the `anonymous` identity
+    pub fn anonymous(mut self) -> Self {
+        self.anonymous = true;
+        self
+    }
+}
+
+impl Authenticator for Users {
+    fn mechanisms(&self) -> Vec<sasl::Mechanism> {
+        let mut mechanisms = vec![sasl::Mechanism::Plain];
+        if self.anonymous {
+            mechanisms.push(sasl::Mechanism::Anonymous);
+        }
+        mechanisms
+    }
+
+    fn authenticate(
+        &self,
+        mechanism: &sasl::Mechanism,
+        response: Option<&[u8]>,
+    ) -> Result<String, sasl::Code> {
+        if *mechanism == sasl::Mechanism::Anonymous && self.anonymous {
+            return Ok("anonymous".to_owned());
+        } else if *mechanism != sasl::Mechanism::Plain {
+            return Err(sasl::Code::Auth);
+        }
+
+        // The response is `authzid NUL authcid NUL passwd`, see RFC 4616
+        let response = response.and_then(|response| std::str::from_utf8(response).ok());
+        let mut parts = response.ok_or(sasl::Code::Auth)?.split('\0');
+        let (authzid, user, password) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(authzid), Some(user), Some(password)) => (authzid, user, password),
+            _ => return Err(sasl::Code::Auth),
+        };
+
+        match self.users.get(user) {
+            Some(expected) if expected == password && (authzid.is_empty() || authzid == user) => {
+                Ok(user.to_owned())
+            }
+            _ => Err(sasl::Code::Auth),
+        }
+    }
+}
@@ -0,0 +1,558 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use crate::link::{
+    Credit, IncomingLink, LinkData, LinkState, Outcome, ReceiverBuilder, SenderBuilder,
+};
+use crate::proto::{BytesFrame, Frame, Shared};
+use crate::{amqp, ConnectionError, RemoteError};
+
+/// A session on an AMQP connection (see section 2.5 of the AMQP 1.0 specification)
+///
+/// Sessions keep track of the transfer ids and windows on their channel. Any number of sessions
+/// can be active on the same connection, up to the negotiated channel maximum.
+///
+/// The session is ended when the last `Session` handle and all of its links have been dropped,
+/// but only `close()` waits for the peer to confirm and reports any error it sends.
+pub struct Session {
+    pub(crate) shared: Arc<Shared>,
+    pub(crate) channel: u16,
+}
+
+impl Session {
+    pub(crate) async fn begin(shared: Arc<Shared>) -> Result<Self, ConnectionError> {
+        let channel = shared.inner.lock().unwrap().allocate_channel()?;
+        // If mapping fails, dropping the session releases the channel
+        let session = Self { shared, channel };
+        session.map().await?;
+        Ok(session)
+    }
+
+    async fn map(&self) -> Result<(), ConnectionError> {
+        {
+            let mut inner = self.shared.inner.lock().unwrap();
+            let session = inner.session(self.channel)?;
+            let begin = amqp::Begin {
+                remote_channel: None,
+                next_outgoing_id: session.next_outgoing_id,
+                incoming_window: session.incoming_window,
+                outgoing_window: session.outgoing_window,
+                handle_max: Some(session.handle_max),
+                ..Default::default()
+            };
+            inner.queue(&amqp_frame(self.channel, amqp::Performative::Begin(begin)))?;
+            inner.session(self.channel)?.state = SessionState::BeginSent;
+        }
+        self.shared.flush();
+
+        let channel = self.channel;
+        self.shared
+            .wait(|inner| Ok((inner.session(channel)?.state == SessionState::Mapped).then_some(())))
+            .await
+    }
+
+    /// The local channel number for this session
+    pub fn channel(&self) -> u16 {
+        self.channel
+    }
+
+    pub fn state(&self) -> SessionState {
+        let inner = self.shared.inner.lock().unwrap();
+        match inner.sessions.get(&self.channel) {
+            Some(session) => session.state,
+            None => SessionState::Unmapped,
+        }
+    }
+
+    /// Build a sending link to the node at `address`
+    pub fn sender<'a>(&'a self, address: &'a str) -> SenderBuilder<'a> {
+        SenderBuilder::new(self, address)
+    }
+
+    /// Build a receiving link from the node at `address`
+    pub fn receiver<'a>(&'a self, address: &'a str) -> ReceiverBuilder<'a> {
+        ReceiverBuilder::new(self, address)
+    }
+
+    /// Build a receiving link from a node the peer creates for it, such as a reply queue
+    ///
+    /// The address of the new node is available from `Receiver::address()` once attached.
+    pub fn dynamic_receiver(&self) -> ReceiverBuilder<'_> {
+        ReceiverBuilder::dynamic(self)
+    }
+
+    /// Wait for the peer to attach a new link to this session
+    ///
+    /// The link has to be accepted or rejected; dropping the `IncomingLink` rejects it.
+    pub async fn accept_link(&self) -> Result<IncomingLink, ConnectionError> {
+        let channel = self.channel;
+        let (handle, frame) = self
+            .shared
+            .wait(|inner| Ok(inner.session(channel)?.incoming.pop_front()))
+            .await?;
+        Ok(IncomingLink::new(self.clone(), handle, frame))
+    }
+
+    /// Attach a new link on a handle previously allocated with `allocate_handle()`
+    pub(crate) async fn attach(&self, attach: amqp::Attach<'_>) -> Result<(), ConnectionError> {
+        let handle = attach.handle;
+        let channel = self.channel;
+        let result = async {
+            self.send(amqp::Performative::Attach(attach)).await?;
+            self.shared
+                .wait(|inner| {
+                    let link = inner.session(channel)?.link(handle)?;
+                    Ok((link.state == LinkState::Attached).then_some(()))
+                })
+                .await
+        }
+        .await;
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                if let Ok(session) = self.shared.inner.lock().unwrap().session(channel) {
+                    session.links.remove(&handle);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Allocate the lowest free handle on this session for a new link
+    ///
+    /// If no `name` is given, a name unique to this connection is generated.
+    pub(crate) fn allocate_handle(
+        &self,
+        role: amqp::Role,
+        name: Option<&str>,
+    ) -> Result<(u32, String), ConnectionError> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        let session = inner.session(self.channel)?;
+        let handle = (0..=session.handle_max)
+            .find(|h| !session.links.contains_key(h))
+            .ok_or(ConnectionError::HandlesExhausted)?;
+
+        let name = match name {
+            Some(name) => name.to_owned(),
+            None => {
+                let role = match role {
+                    amqp::Role::Sender => "sender",
+                    amqp::Role::Receiver => "receiver",
+                };
+                format!("{}-{}-{}", role, self.channel, handle)
+            }
+        };
+
+        session
+            .links
+            .insert(handle, LinkData::new(name.clone(), role));
+        Ok((handle, name))
+    }
+
+    /// End the session, waiting for the peer to end its side
+    pub async fn close(self) -> Result<(), ConnectionError> {
+        let channel = self.channel;
+        self.shared.inner.lock().unwrap().session(channel)?.state = SessionState::EndSent;
+        self.send(amqp::Performative::End(amqp::End { error: None }))
+            .await?;
+
+        // The channel is released once the last handle for the session has been dropped
+        let error = self
+            .shared
+            .wait(|inner| {
+                Ok(match inner.sessions.get(&channel) {
+                    Some(session) if session.state == SessionState::Unmapped => {
+                        Some(session.remote_end.clone().flatten())
+                    }
+                    Some(_) => None,
+                    None => Some(None),
+                })
+            })
+            .await?;
+
+        match error {
+            Some(error) => Err(ConnectionError::RemoteEnd(Some(error))),
+            None => Ok(()),
+        }
+    }
+
+    async fn send(&self, performative: amqp::Performative<'_>) -> Result<(), ConnectionError> {
+        self.shared
+            .send(&Frame::Amqp(amqp::Frame {
+                channel: self.channel,
+                extended_header: None,
+                performative,
+                message: None,
+            }))
+            .await
+    }
+}
+
+impl Clone for Session {
+    fn clone(&self) -> Self {
+        if let Ok(session) = self.shared.inner.lock().unwrap().session(self.channel) {
+            session.refs += 1;
+        }
+
+        Self {
+            shared: self.shared.clone(),
+            channel: self.channel,
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.shared.inner.lock() {
+            inner.release_session(self.channel);
+        }
+        self.shared.flush();
+    }
+}
+
+/// Session state shared between the session and its links
+pub(crate) struct SessionData {
+    pub(crate) state: SessionState,
+    /// The next transfer id we expect from the peer
+    next_incoming_id: u32,
+    /// Number of transfers we're willing to accept from the peer
+    incoming_window: u32,
+    /// The transfer id assigned to the next outgoing transfer
+    pub(crate) next_outgoing_id: u32,
+    outgoing_window: u32,
+    /// The delivery id assigned to the next outgoing delivery
+    pub(crate) next_delivery_id: u32,
+    /// Number of transfers the peer is willing to accept from us
+    pub(crate) remote_incoming_window: u32,
+    remote_outgoing_window: u32,
+    handle_max: u32,
+    /// Link state, by local handle
+    pub(crate) links: HashMap<u32, LinkData>,
+    /// Maps the peer's link handles to our own
+    remote_handles: HashMap<u32, u32>,
+    /// Links attached by the peer that have not been picked up yet, with the peer's `Attach`
+    pub(crate) incoming: VecDeque<(u32, BytesFrame)>,
+    /// Outcomes for unsettled outgoing deliveries, by delivery id
+    pub(crate) deliveries: HashMap<u32, Option<Outcome>>,
+    /// Set while the frames of a delivery are being sent, so that they are not interleaved
+    /// with those of another delivery
+    pub(crate) sending: bool,
+    /// Set when the peer has ended the session
+    pub(crate) remote_end: Option<Option<RemoteError>>,
+    /// Number of `Session` and link handles for this session, starting with the one that began it
+    pub(crate) refs: usize,
+}
+
+impl SessionData {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: SessionState::Unmapped,
+            next_incoming_id: 0,
+            incoming_window: DEFAULT_WINDOW,
+            next_outgoing_id: INITIAL_OUTGOING_ID,
+            outgoing_window: DEFAULT_WINDOW,
+            next_delivery_id: 0,
+            remote_incoming_window: 0,
+            remote_outgoing_window: 0,
+            handle_max: u32::MAX,
+            links: HashMap::new(),
+            remote_handles: HashMap::new(),
+            incoming: VecDeque::new(),
+            deliveries: HashMap::new(),
+            sending: false,
+            remote_end: None,
+            refs: 1,
+        }
+    }
+
+    pub(crate) fn link(&mut self, handle: u32) -> Result<&mut LinkData, ConnectionError> {
+        match self.links.get_mut(&handle) {
+            Some(link) => match &link.remote_detach {
+                Some(error) => Err(ConnectionError::RemoteDetach(error.clone())),
+                None => Ok(link),
+            },
+            None => Err(ConnectionError::RemoteDetach(None)),
+        }
+    }
+
+    /// Update the session and link state for a frame received on this session
+    pub(crate) fn received(
+        &mut self,
+        channel: u16,
+        frame: BytesFrame,
+        replies: &mut Vec<Frame<'static>>,
+    ) -> Result<(), ConnectionError> {
+        let (remote_channel, performative) = match frame.frame() {
+            Frame::Amqp(frame) => (frame.channel, &frame.performative),
+            _ => return Ok(()),
+        };
+
+        match performative {
+            amqp::Performative::Begin(begin) => {
+                self.next_incoming_id = begin.next_outgoing_id;
+                self.remote_incoming_window = begin.incoming_window;
+                self.remote_outgoing_window = begin.outgoing_window;
+                self.handle_max = begin.handle_max.unwrap_or(u32::MAX).min(self.handle_max);
+                if self.state == SessionState::Unmapped {
+                    // The peer began this session, so it is waiting for our `Begin`
+                    replies.push(amqp_frame(
+                        channel,
+                        amqp::Performative::Begin(amqp::Begin {
+                            remote_channel: Some(remote_channel),
+                            next_outgoing_id: self.next_outgoing_id,
+                            incoming_window: self.incoming_window,
+                            outgoing_window: self.outgoing_window,
+                            handle_max: Some(self.handle_max),
+                            ..Default::default()
+                        }),
+                    ));
+                }
+                self.state = SessionState::Mapped;
+            }
+            amqp::Performative::Flow(flow) => {
+                // See section 2.5.6 for the computation of the remote incoming window
+                let next_incoming_id = flow.next_incoming_id.unwrap_or(INITIAL_OUTGOING_ID);
+                self.remote_incoming_window = next_incoming_id
+                    .wrapping_add(flow.incoming_window)
+                    .wrapping_sub(self.next_outgoing_id);
+                self.remote_outgoing_window = flow.outgoing_window;
+                let mut reply = flow.echo == Some(true);
+                let mut link = None;
+                if let Some(remote) = flow.handle {
+                    if let Some(handle) = self.remote_handles.get(&remote).copied() {
+                        if let Some(data) = self.links.get_mut(&handle) {
+                            reply |= data.flow(flow);
+                            link = Some(handle);
+                        }
+                    }
+                }
+
+                if reply {
+                    let link = link.map(|handle| (handle, &self.links[&handle]));
+                    replies.push(self.flow(channel, link));
+                }
+            }
+            amqp::Performative::Transfer(transfer) => {
+                self.next_incoming_id = self.next_incoming_id.wrapping_add(1);
+                self.incoming_window = self.incoming_window.saturating_sub(1);
+                self.remote_outgoing_window = self.remote_outgoing_window.saturating_sub(1);
+                if self.incoming_window < DEFAULT_WINDOW / 2 {
+                    self.incoming_window = DEFAULT_WINDOW;
+                    replies.push(self.flow(channel, None));
+                }
+
+                let remote = transfer.handle;
+                if let Some((handle, link)) = self.remote_link(remote) {
+                    replies.extend(link.transferred(channel, handle, frame)?);
+                }
+            }
+            amqp::Performative::Disposition(disposition)
+                if disposition.role == amqp::Role::Receiver =>
+            {
+                let outcome = match &disposition.state {
+                    Some(state) => Outcome::from_state(state),
+                    // Settled without a terminal state, assume the default outcome
+                    None if disposition.settled == Some(true) => Some(Outcome::Accepted),
+                    None => None,
+                };
+
+                let outcome = match outcome {
+                    Some(outcome) => outcome,
+                    None => return Ok(()),
+                };
+
+                let first = disposition.first;
+                let last = disposition.last.unwrap_or(first);
+                for (id, pending) in self.deliveries.iter_mut() {
+                    if id.wrapping_sub(first) <= last.wrapping_sub(first) {
+                        *pending = Some(outcome.clone());
+                    }
+                }
+
+                // In receiver settle mode second, the receiver waits for us to settle first
+                if disposition.settled != Some(true) {
+                    replies.push(amqp_frame(
+                        channel,
+                        amqp::Performative::Disposition(amqp::Disposition {
+                            role: amqp::Role::Sender,
+                            first,
+                            last: disposition.last,
+                            settled: Some(true),
+                            state: None,
+                            batchable: None,
+                        }),
+                    ));
+                }
+            }
+            amqp::Performative::Attach(attach) => {
+                let pending = self.links.iter_mut().find(|(_, link)| {
+                    link.state == LinkState::AttachSent && link.name == attach.name
+                });
+
+                match pending {
+                    Some((handle, link)) => {
+                        link.attached(attach);
+                        self.remote_handles.insert(attach.handle, *handle);
+                    }
+                    None => {
+                        // The peer attaches a new link, which is answered once it is accepted
+                        let handle = (0..=self.handle_max)
+                            .find(|h| !self.links.contains_key(h))
+                            .ok_or(ConnectionError::HandlesExhausted)?;
+                        let role = match attach.role {
+                            amqp::Role::Sender => amqp::Role::Receiver,
+                            amqp::Role::Receiver => amqp::Role::Sender,
+                        };
+
+                        let mut link = LinkData::new(attach.name.to_owned(), role);
+                        link.attached(attach);
+                        link.state = LinkState::AttachRcvd;
+                        self.links.insert(handle, link);
+                        self.remote_handles.insert(attach.handle, handle);
+                        self.incoming.push_back((handle, frame));
+                    }
+                }
+            }
+            amqp::Performative::Detach(detach) => {
+                if let Some(handle) = self.remote_handles.remove(&detach.handle) {
+                    if let Some(link) = self.links.get_mut(&handle) {
+                        link.remote_detach = Some(detach.error.as_ref().map(RemoteError::from));
+                        if link.state != LinkState::DetachSent {
+                            replies.push(amqp_frame(
+                                channel,
+                                amqp::Performative::Detach(amqp::Detach {
+                                    handle,
+                                    closed: detach.closed,
+                                    error: None,
+                                }),
+                            ));
+                        }
+                        link.state = LinkState::Detached;
+                        // Nobody is waiting for the link anymore, so the handle can be reused
+                        if link.refs == 0 {
+                            self.links.remove(&handle);
+                        }
+                    }
+                }
+            }
+            amqp::Performative::End(end) => {
+                let error = end.error.as_ref().map(RemoteError::from);
+                if self.state != SessionState::EndSent {
+                    self.state = SessionState::EndRcvd;
+                    replies.push(amqp_frame(
+                        channel,
+                        amqp::Performative::End(amqp::End { error: None }),
+                    ));
+                    self.remote_end = Some(error);
+                } else if error.is_some() {
+                    self.remote_end = Some(error);
+                }
+                self.state = SessionState::Unmapped;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Drop a reference to a link, detaching it once the last handle is gone
+    pub(crate) fn release_link(&mut self, channel: u16, handle: u32) -> Option<Frame<'static>> {
+        let link = self.links.get_mut(&handle)?;
+        link.refs -= 1;
+        if link.refs > 0 {
+            return None;
+        }
+
+        if link.state != LinkState::Attached {
+            self.links.remove(&handle);
+            return None;
+        }
+
+        link.state = LinkState::DetachSent;
+        Some(amqp_frame(
+            channel,
+            amqp::Performative::Detach(amqp::Detach {
+                handle,
+                closed: Some(true),
+                error: None,
+            }),
+        ))
+    }
+
+    /// Build a `Flow` frame with the current session state, for the given link if any
+    pub(crate) fn flow(&self, channel: u16, link: Option<(u32, &LinkData)>) -> Frame<'static> {
+        let (handle, delivery_count, link_credit, drain) = match link {
+            Some((handle, link)) => (
+                Some(handle),
+                Some(link.delivery_count),
+                Some(link.link_credit),
+                link.draining.then_some(true),
+            ),
+            None => (None, None, None, None),
+        };
+
+        amqp_frame(
+            channel,
+            amqp::Performative::Flow(amqp::Flow {
+                next_incoming_id: Some(self.next_incoming_id),
+                incoming_window: self.incoming_window,
+                next_outgoing_id: self.next_outgoing_id,
+                outgoing_window: self.outgoing_window,
+                handle,
+                delivery_count,
+                link_credit,
+                drain,
+                ..Default::default()
+            }),
+        )
+    }
+
+    /// Top up credit for a receiving link in prefetch mode, if it has dropped too low
+    pub(crate) fn replenish(&mut self, channel: u16, handle: u32) -> Option<Frame<'static>> {
+        let link = self.links.get_mut(&handle)?;
+        let prefetch = match link.credit {
+            Credit::Prefetch(prefetch) if !link.draining && link.remote_detach.is_none() => {
+                prefetch
+            }
+            _ => return None,
+        };
+
+        let buffered = link.queue.len() as u32;
+        if link.link_credit + buffered > prefetch / 2 {
+            return None;
+        }
+
+        link.link_credit = prefetch.saturating_sub(buffered);
+        Some(self.flow(channel, Some((handle, &self.links[&handle]))))
+    }
+
+    fn remote_link(&mut self, remote: u32) -> Option<(u32, &mut LinkData)> {
+        let handle = *self.remote_handles.get(&remote)?;
+        Some((handle, self.links.get_mut(&handle)?))
+    }
+}
+
+pub(crate) fn amqp_frame(channel: u16, performative: amqp::Performative<'_>) -> Frame<'_> {
+    Frame::Amqp(amqp::Frame {
+        channel,
+        extended_header: None,
+        performative,
+        message: None,
+    })
+}
+
+/// Session states, as defined in section 2.5.5 of the AMQP 1.0 specification
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SessionState {
+    Unmapped,
+    BeginSent,
+    BeginRcvd,
+    Mapped,
+    EndSent,
+    EndRcvd,
+    Discarding,
+}
+
+const DEFAULT_WINDOW: u32 = 2048;
+const INITIAL_OUTGOING_ID: u32 = 0;
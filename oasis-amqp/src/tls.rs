@@ -0,0 +1,103 @@
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+pub use tokio_rustls::rustls::pki_types::{self, CertificateDer, PrivateKeyDer};
+
+use crate::ConnectionError;
+
+/// TLS settings for `Client::connect_tls()`
+///
+/// Only the root certificates added to the configuration are trusted by default, since Corda
+/// networks generally use their own certificate authority. Brokers with a publicly trusted
+/// certificate can be reached by also trusting the standard roots with `webpki_roots()`.
+pub struct TlsConfig {
+    roots: RootCertStore,
+    client_auth: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    server_name: Option<String>,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self {
+            roots: RootCertStore::empty(),
+            client_auth: None,
+            server_name: None,
+        }
+    }
+
+    /// Trust the given (DER-encoded) root certificate
+    pub fn root_certificate(mut self, cert: CertificateDer<'_>) -> Result<Self, ConnectionError> {
+        self.roots.add(cert)?;
+        Ok(self)
+    }
+
+    /// Also trust the Mozilla root certificates, as bundled by the `webpki-roots` crate
+    ///
+    /// Requires the `tls-webpki-roots` feature.
+    #[cfg(feature = "tls-webpki-roots")]
+    pub fn webpki_roots(mut self) -> Self {
+        self.roots
+            .extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        self
+    }
+
+    /// Authenticate with a client certificate chain and the matching private key
+    pub fn client_certificate(
+        mut self,
+        chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Self {
+        self.client_auth = Some((chain, key));
+        self
+    }
+
+    /// Verify the server certificate against `name` rather than the host connected to
+    pub fn server_name(mut self, name: &str) -> Self {
+        self.server_name = Some(name.to_owned());
+        self
+    }
+
+    pub(crate) async fn connect(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> Result<TlsStream<TcpStream>, ConnectionError> {
+        let name = self.server_name.as_deref().unwrap_or(host);
+        let name = ServerName::try_from(name.to_owned())
+            .map_err(|_| ConnectionError::InvalidServerName(name.to_owned()))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(self.roots.clone());
+        let config = match &self.client_auth {
+            Some((chain, key)) => builder.with_client_auth_cert(chain.clone(), key.clone_key())?,
+            None => builder.with_no_client_auth(),
+        };
+
+        let stream = TcpStream::connect((host, port)).await?;
+        let connector = TlsConnector::from(Arc::new(config));
+        connector.connect(name, stream).await.map_err(|e| {
+            // Handshake failures are reported as I/O errors wrapping the rustls error
+            match e
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+            {
+                Some(error) => ConnectionError::Tls(error.clone()),
+                None => ConnectionError::Io(e),
+            }
+        })
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
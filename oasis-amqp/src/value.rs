@@ -0,0 +1,461 @@
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::ser::{SerializeMap, SerializeSeq, SerializeTuple, SerializeTupleStruct};
+use serde::{Deserialize, Serialize};
+use serde_bytes::Bytes;
+
+use crate::amqp::{Any, Map, Symbol, Timestamp};
+
+/// Any AMQP value, including described values, for payloads of unknown shape
+///
+/// Unlike `amqp::Any`, this can represent every valid AMQP encoding, so it can be used to
+/// inspect or forward messages without knowing their schema:
+///
+/// ```
+/// use oasis_amqp::{de, ser, Value};
+///
+/// let value = Value::Described {
+///     descriptor: Box::new(Value::U64(0x77)),
+///     value: Box::new(Value::Str("hello".into())),
+/// };
+/// let mut buf = vec![];
+/// ser::into_bytes(&value, &mut buf).unwrap();
+/// let (decoded, _) = de::deserialize::<Value>(&buf).unwrap();
+/// assert_eq!(decoded, value);
+/// ```
+///
+/// Floating point values compare equal if they have the same bits, as they would in their
+/// encoded form, which makes `Value` usable in types that implement `Eq`.
+#[derive(Clone, Debug)]
+pub enum Value<'a> {
+    Null,
+    Bool(bool),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Decimal32([u8; 4]),
+    Decimal64([u8; 8]),
+    Decimal128([u8; 16]),
+    Char(char),
+    Timestamp(Timestamp),
+    Uuid([u8; 16]),
+    Bytes(Cow<'a, [u8]>),
+    Symbol(Cow<'a, str>),
+    Str(Cow<'a, str>),
+    List(Vec<Value<'a>>),
+    /// Map entries in the order they were encoded; keys can be of any type
+    Map(Vec<(Value<'a>, Value<'a>)>),
+    /// A sequence of values, which must all be of the same type
+    Array(Vec<Value<'a>>),
+    Described {
+        /// Usually a `U64` code or a `Symbol` name
+        descriptor: Box<Value<'a>>,
+        value: Box<Value<'a>>,
+    },
+}
+
+impl Value<'_> {
+    pub fn into_owned(self) -> Value<'static> {
+        match self {
+            Value::Null => Value::Null,
+            Value::Bool(v) => Value::Bool(v),
+            Value::U8(v) => Value::U8(v),
+            Value::U16(v) => Value::U16(v),
+            Value::U32(v) => Value::U32(v),
+            Value::U64(v) => Value::U64(v),
+            Value::I8(v) => Value::I8(v),
+            Value::I16(v) => Value::I16(v),
+            Value::I32(v) => Value::I32(v),
+            Value::I64(v) => Value::I64(v),
+            Value::F32(v) => Value::F32(v),
+            Value::F64(v) => Value::F64(v),
+            Value::Decimal32(v) => Value::Decimal32(v),
+            Value::Decimal64(v) => Value::Decimal64(v),
+            Value::Decimal128(v) => Value::Decimal128(v),
+            Value::Char(v) => Value::Char(v),
+            Value::Timestamp(v) => Value::Timestamp(v),
+            Value::Uuid(v) => Value::Uuid(v),
+            Value::Bytes(v) => Value::Bytes(Cow::Owned(v.into_owned())),
+            Value::Symbol(v) => Value::Symbol(Cow::Owned(v.into_owned())),
+            Value::Str(v) => Value::Str(Cow::Owned(v.into_owned())),
+            Value::List(items) => Value::List(items.into_iter().map(Value::into_owned).collect()),
+            Value::Map(entries) => Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                    .collect(),
+            ),
+            Value::Array(items) => Value::Array(items.into_iter().map(Value::into_owned).collect()),
+            Value::Described { descriptor, value } => Value::Described {
+                descriptor: Box::new(descriptor.into_owned()),
+                value: Box::new(value.into_owned()),
+            },
+        }
+    }
+
+    pub fn to_borrowed(&self) -> Value<'_> {
+        match self {
+            Value::Bytes(v) => Value::Bytes(Cow::Borrowed(v)),
+            Value::Symbol(v) => Value::Symbol(Cow::Borrowed(v)),
+            Value::Str(v) => Value::Str(Cow::Borrowed(v)),
+            Value::List(items) => Value::List(items.iter().map(Value::to_borrowed).collect()),
+            Value::Map(entries) => Value::Map(
+                entries
+                    .iter()
+                    .map(|(k, v)| (k.to_borrowed(), v.to_borrowed()))
+                    .collect(),
+            ),
+            Value::Array(items) => Value::Array(items.iter().map(Value::to_borrowed).collect()),
+            Value::Described { descriptor, value } => Value::Described {
+                descriptor: Box::new(descriptor.to_borrowed()),
+                value: Box::new(value.to_borrowed()),
+            },
+            // All other variants are plain values
+            other => other.clone(),
+        }
+    }
+}
+
+impl PartialEq for Value<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        use Value::*;
+        match (self, other) {
+            (Null, Null) => true,
+            (Bool(a), Bool(b)) => a == b,
+            (U8(a), U8(b)) => a == b,
+            (U16(a), U16(b)) => a == b,
+            (U32(a), U32(b)) => a == b,
+            (U64(a), U64(b)) => a == b,
+            (I8(a), I8(b)) => a == b,
+            (I16(a), I16(b)) => a == b,
+            (I32(a), I32(b)) => a == b,
+            (I64(a), I64(b)) => a == b,
+            (F32(a), F32(b)) => a.to_bits() == b.to_bits(),
+            (F64(a), F64(b)) => a.to_bits() == b.to_bits(),
+            (Decimal32(a), Decimal32(b)) => a == b,
+            (Decimal64(a), Decimal64(b)) => a == b,
+            (Decimal128(a), Decimal128(b)) => a == b,
+            (Char(a), Char(b)) => a == b,
+            (Timestamp(a), Timestamp(b)) => a == b,
+            (Uuid(a), Uuid(b)) => a == b,
+            (Bytes(a), Bytes(b)) => a == b,
+            (Symbol(a), Symbol(b)) => a == b,
+            (Str(a), Str(b)) => a == b,
+            (List(a), List(b)) => a == b,
+            (Map(a), Map(b)) => a == b,
+            (Array(a), Array(b)) => a == b,
+            (
+                Described {
+                    descriptor: d1,
+                    value: v1,
+                },
+                Described {
+                    descriptor: d2,
+                    value: v2,
+                },
+            ) => d1 == d2 && v1 == v2,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value<'_> {}
+
+impl<'a> From<Any<'a>> for Value<'a> {
+    fn from(any: Any<'a>) -> Self {
+        match any {
+            Any::None => Value::Null,
+            Any::Bool(v) => Value::Bool(v),
+            Any::U8(v) => Value::U8(v),
+            Any::U16(v) => Value::U16(v),
+            Any::U32(v) => Value::U32(v),
+            Any::U64(v) => Value::U64(v),
+            Any::I8(v) => Value::I8(v),
+            Any::I16(v) => Value::I16(v),
+            Any::I32(v) => Value::I32(v),
+            Any::I64(v) => Value::I64(v),
+            Any::F32(v) => Value::F32(v),
+            Any::F64(v) => Value::F64(v),
+            Any::Decimal32(v) => Value::Decimal32(v),
+            Any::Decimal64(v) => Value::Decimal64(v),
+            Any::Decimal128(v) => Value::Decimal128(v),
+            Any::Char(v) => Value::Char(v),
+            Any::Timestamp(v) => Value::Timestamp(v),
+            Any::Uuid(v) => Value::Uuid(v),
+            Any::Bytes(v) => Value::Bytes(v),
+            Any::Symbol(v) => Value::Symbol(v),
+            Any::Str(v) => Value::Str(v),
+            Any::List(items) => Value::List(items.into_iter().map(Value::from).collect()),
+            Any::Map(entries) => Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k.into(), v.into()))
+                    .collect(),
+            ),
+            Any::Array(items) => Value::Array(items.into_iter().map(Value::from).collect()),
+        }
+    }
+}
+
+impl Serialize for Value<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Value::Null => serializer.serialize_unit(),
+            Value::Bool(v) => serializer.serialize_bool(*v),
+            Value::U8(v) => serializer.serialize_u8(*v),
+            Value::U16(v) => serializer.serialize_u16(*v),
+            Value::U32(v) => serializer.serialize_u32(*v),
+            Value::U64(v) => serializer.serialize_u64(*v),
+            Value::I8(v) => serializer.serialize_i8(*v),
+            Value::I16(v) => serializer.serialize_i16(*v),
+            Value::I32(v) => serializer.serialize_i32(*v),
+            Value::I64(v) => serializer.serialize_i64(*v),
+            Value::F32(v) => serializer.serialize_f32(*v),
+            Value::F64(v) => serializer.serialize_f64(*v),
+            Value::Decimal32(v) => {
+                serializer.serialize_newtype_struct("amqp:decimal32", Bytes::new(v))
+            }
+            Value::Decimal64(v) => {
+                serializer.serialize_newtype_struct("amqp:decimal64", Bytes::new(v))
+            }
+            Value::Decimal128(v) => {
+                serializer.serialize_newtype_struct("amqp:decimal128", Bytes::new(v))
+            }
+            Value::Char(v) => serializer.serialize_char(*v),
+            Value::Timestamp(v) => v.serialize(serializer),
+            Value::Uuid(v) => serializer.serialize_newtype_struct("amqp:uuid", Bytes::new(v)),
+            Value::Bytes(v) => serializer.serialize_bytes(v),
+            Value::Symbol(v) => serializer.serialize_newtype_struct("amqp:symbol", &**v),
+            Value::Str(v) => serializer.serialize_str(v),
+            Value::List(items) => {
+                let mut list = serializer.serialize_tuple(items.len())?;
+                for item in items {
+                    list.serialize_element(item)?;
+                }
+                list.end()
+            }
+            Value::Map(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+            Value::Array(items) => {
+                let mut array = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    array.serialize_element(item)?;
+                }
+                array.end()
+            }
+            Value::Described { descriptor, value } => {
+                let mut described = serializer.serialize_tuple_struct("amqp:described", 2)?;
+                described.serialize_field(descriptor)?;
+                described.serialize_field(value)?;
+                described.end()
+            }
+        }
+    }
+}
+
+impl<'a, 'de: 'a> Deserialize<'de> for Value<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // The deserializer identifies the variant by the value's format code
+        enum ValueType {
+            Null,
+            Bool,
+            U8,
+            U16,
+            U32,
+            U64,
+            I8,
+            I16,
+            I32,
+            I64,
+            F32,
+            F64,
+            Decimal32,
+            Decimal64,
+            Decimal128,
+            Char,
+            Timestamp,
+            Uuid,
+            Bytes,
+            Symbol,
+            Str,
+            List,
+            Map,
+            Array,
+            Described,
+        }
+
+        struct FieldVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for FieldVisitor {
+            type Value = ValueType;
+            fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Formatter::write_str(fmt, "variant identifier")
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(match value {
+                    0x00 => ValueType::Described,
+                    0x40 => ValueType::Null,
+                    0x41 | 0x42 | 0x56 => ValueType::Bool,
+                    0x50 => ValueType::U8,
+                    0x60 => ValueType::U16,
+                    0x43 | 0x52 | 0x70 => ValueType::U32,
+                    0x44 | 0x53 | 0x80 => ValueType::U64,
+                    0x51 => ValueType::I8,
+                    0x61 => ValueType::I16,
+                    0x54 | 0x71 => ValueType::I32,
+                    0x55 | 0x81 => ValueType::I64,
+                    0x72 => ValueType::F32,
+                    0x82 => ValueType::F64,
+                    0x74 => ValueType::Decimal32,
+                    0x84 => ValueType::Decimal64,
+                    0x94 => ValueType::Decimal128,
+                    0x73 => ValueType::Char,
+                    0x83 => ValueType::Timestamp,
+                    0x98 => ValueType::Uuid,
+                    0xa0 | 0xb0 => ValueType::Bytes,
+                    0xa3 | 0xb3 => ValueType::Symbol,
+                    0xa1 | 0xb1 => ValueType::Str,
+                    0x45 | 0xc0 | 0xd0 => ValueType::List,
+                    0xc1 | 0xd1 => ValueType::Map,
+                    0xe0 | 0xf0 => ValueType::Array,
+                    _ => {
+                        return Err(serde::de::Error::invalid_value(
+                            serde::de::Unexpected::Unsigned(value),
+                            &"constructor code",
+                        ))
+                    }
+                })
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for ValueType {
+            #[inline]
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                serde::Deserializer::deserialize_identifier(deserializer, FieldVisitor)
+            }
+        }
+
+        struct Visitor<'de, 'a> {
+            marker: PhantomData<Value<'a>>,
+            lifetime: PhantomData<&'de ()>,
+        }
+
+        impl<'de: 'a, 'a> serde::de::Visitor<'de> for Visitor<'de, 'a> {
+            type Value = Value<'a>;
+
+            fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Formatter::write_str(fmt, "enum Value")
+            }
+
+            fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::EnumAccess<'de>,
+            {
+                use serde::de::VariantAccess;
+                let (ty, variant) = serde::de::EnumAccess::variant(data)?;
+                Ok(match ty {
+                    ValueType::Null => variant.newtype_variant::<()>().map(|_| Value::Null)?,
+                    ValueType::Bool => Value::Bool(variant.newtype_variant()?),
+                    ValueType::U8 => Value::U8(variant.newtype_variant()?),
+                    ValueType::U16 => Value::U16(variant.newtype_variant()?),
+                    ValueType::U32 => Value::U32(variant.newtype_variant()?),
+                    ValueType::U64 => Value::U64(variant.newtype_variant()?),
+                    ValueType::I8 => Value::I8(variant.newtype_variant()?),
+                    ValueType::I16 => Value::I16(variant.newtype_variant()?),
+                    ValueType::I32 => Value::I32(variant.newtype_variant()?),
+                    ValueType::I64 => Value::I64(variant.newtype_variant()?),
+                    ValueType::F32 => Value::F32(variant.newtype_variant()?),
+                    ValueType::F64 => Value::F64(variant.newtype_variant()?),
+                    ValueType::Decimal32 => Value::Decimal32(fixed(variant.newtype_variant()?)?),
+                    ValueType::Decimal64 => Value::Decimal64(fixed(variant.newtype_variant()?)?),
+                    ValueType::Decimal128 => Value::Decimal128(fixed(variant.newtype_variant()?)?),
+                    ValueType::Char => Value::Char(variant.newtype_variant()?),
+                    ValueType::Timestamp => Value::Timestamp(variant.newtype_variant()?),
+                    ValueType::Uuid => Value::Uuid(fixed(variant.newtype_variant()?)?),
+                    ValueType::Bytes => {
+                        Value::Bytes(Cow::Borrowed(variant.newtype_variant::<&[u8]>()?))
+                    }
+                    ValueType::Symbol => {
+                        Value::Symbol(Cow::Borrowed(variant.newtype_variant::<Symbol<'_>>()?.0))
+                    }
+                    ValueType::Str => Value::Str(Cow::Borrowed(variant.newtype_variant::<&str>()?)),
+                    ValueType::List => Value::List(variant.newtype_variant()?),
+                    ValueType::Map => Value::Map(variant.newtype_variant::<Map<_, _>>()?.0),
+                    ValueType::Array => Value::Array(variant.newtype_variant()?),
+                    ValueType::Described => {
+                        variant.tuple_variant(2, DescribedVisitor(PhantomData))?
+                    }
+                })
+            }
+        }
+
+        fn fixed<E: serde::de::Error, const N: usize>(bytes: &[u8]) -> Result<[u8; N], E> {
+            bytes
+                .try_into()
+                .map_err(|_| E::invalid_length(bytes.len(), &"fixed-width value"))
+        }
+
+        const VARIANTS: &[&str] = &["Value"];
+        serde::Deserializer::deserialize_enum(
+            deserializer,
+            "Value",
+            VARIANTS,
+            Visitor {
+                marker: PhantomData,
+                lifetime: PhantomData,
+            },
+        )
+    }
+}
+
+/// Reads a described value as its descriptor followed by the value itself
+struct DescribedVisitor<'a>(PhantomData<Value<'a>>);
+
+impl<'de: 'a, 'a> serde::de::Visitor<'de> for DescribedVisitor<'a> {
+    type Value = Value<'a>;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Formatter::write_str(fmt, "a described value")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let missing = || serde::de::Error::invalid_length(0, &self);
+        let descriptor = seq.next_element()?.ok_or_else(missing)?;
+        let value = seq.next_element()?.ok_or_else(missing)?;
+        Ok(Value::Described {
+            descriptor: Box::new(descriptor),
+            value: Box::new(value),
+        })
+    }
+}
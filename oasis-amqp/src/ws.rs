@@ -0,0 +1,156 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, Bytes};
+use futures::{ready, Sink, Stream};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::error::UrlError;
+use tokio_tungstenite::tungstenite::handshake::client::Request;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::WebSocketStream;
+
+use crate::ConnectionError;
+
+/// Tunnels AMQP frames through binary WebSocket messages
+///
+/// Incoming messages are read as one continuous byte stream, so frames may be split across
+/// messages (or share one) as allowed by the AMQP WebSocket binding.
+pub struct WsStream<S> {
+    inner: WebSocketStream<S>,
+    read: Bytes,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WsStream<S> {
+    /// Perform the WebSocket upgrade for `url` over `stream`, requiring the `amqp` subprotocol
+    pub async fn connect(stream: S, url: &str) -> Result<Self, ConnectionError> {
+        Self::handshake(stream, request(url)?).await
+    }
+
+    async fn handshake(stream: S, request: Request) -> Result<Self, ConnectionError> {
+        let (inner, _) = tokio_tungstenite::client_async(request, stream).await?;
+        Ok(Self::from(inner))
+    }
+}
+
+impl WsStream<TcpStream> {
+    pub(crate) async fn connect_tcp(url: &str) -> Result<Self, ConnectionError> {
+        let request = request(url)?;
+        let (host, port) = match request.uri().scheme_str() {
+            Some("ws") => address(&request, 80)?,
+            _ => return Err(tungstenite::Error::Url(UrlError::TlsFeatureNotEnabled).into()),
+        };
+        let stream = TcpStream::connect((host.as_str(), port)).await?;
+        Self::handshake(stream, request).await
+    }
+}
+
+#[cfg(feature = "tls")]
+impl WsStream<tokio_rustls::client::TlsStream<TcpStream>> {
+    pub(crate) async fn connect_tls(
+        url: &str,
+        config: &crate::tls::TlsConfig,
+    ) -> Result<Self, ConnectionError> {
+        let request = request(url)?;
+        let (host, port) = address(&request, 443)?;
+        let stream = config.connect(&host, port).await?;
+        Self::handshake(stream, request).await
+    }
+}
+
+impl<S> From<WebSocketStream<S>> for WsStream<S> {
+    fn from(inner: WebSocketStream<S>) -> Self {
+        Self {
+            inner,
+            read: Bytes::new(),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.read.is_empty() {
+            match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => this.read = data,
+                Some(Ok(Message::Text(_))) => {
+                    let err = io::Error::new(io::ErrorKind::InvalidData, "unexpected text message");
+                    return Poll::Ready(Err(err));
+                }
+                // Pings are answered by tungstenite itself
+                Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => {}
+                Some(Ok(Message::Close(_))) | Some(Ok(Message::Frame(_))) | None => {
+                    return Poll::Ready(Ok(()))
+                }
+                Some(Err(tungstenite::Error::ConnectionClosed)) => return Poll::Ready(Ok(())),
+                Some(Err(e)) => return Poll::Ready(Err(io_error(e))),
+            }
+        }
+
+        let len = buf.remaining().min(this.read.len());
+        buf.put_slice(&this.read[..len]);
+        this.read.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut inner = Pin::new(&mut self.get_mut().inner);
+        ready!(inner.as_mut().poll_ready(cx)).map_err(io_error)?;
+        let message = Message::Binary(Bytes::copy_from_slice(buf));
+        inner.start_send(message).map_err(io_error)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner)
+            .poll_flush(cx)
+            .map_err(io_error)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner)
+            .poll_close(cx)
+            .map_err(io_error)
+    }
+}
+
+fn request(url: &str) -> Result<Request, ConnectionError> {
+    let mut request = url.into_client_request()?;
+    request.headers_mut().insert(
+        "Sec-WebSocket-Protocol",
+        HeaderValue::from_static(SUBPROTOCOL),
+    );
+    Ok(request)
+}
+
+fn address(request: &Request, default_port: u16) -> Result<(String, u16), ConnectionError> {
+    let uri = request.uri();
+    let host = match uri.host() {
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => return Err(tungstenite::Error::Url(UrlError::NoHostName).into()),
+    };
+    Ok((host.to_owned(), uri.port_u16().unwrap_or(default_port)))
+}
+
+fn io_error(err: tungstenite::Error) -> io::Error {
+    match err {
+        tungstenite::Error::Io(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+/// The WebSocket subprotocol for AMQP, as registered with IANA
+pub const SUBPROTOCOL: &str = "amqp";
@@ -1,11 +1,19 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::time::Duration;
 
 use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
 use serde_bytes::Bytes;
-use tokio_util::codec::Decoder;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::codec::{Decoder, Framed};
 
-use oasis_amqp::proto::{Codec, Frame, Protocol};
-use oasis_amqp::{amqp, sasl};
+use oasis_amqp::link::LinkState;
+use oasis_amqp::proto::{Codec, ConnectionState, Frame, Protocol};
+use oasis_amqp::sasl::SaslMechanism;
+use oasis_amqp::scram::{Hash, ScramClient};
+use oasis_amqp::{amqp, sasl, Client, ConnectionError, Credit, Outcome, RemoteError, Value};
 
 #[test]
 fn login() {
@@ -55,6 +63,32 @@ fn login() {
     assert_eq!(wrapped.frame(), &Frame::Header(Protocol::Amqp));
 }
 
+#[test]
+fn malformed_frames() {
+    let mut codec = Codec {};
+    let invalid: &[&[u8]] = &[
+        // Sizes that don't cover the frame header
+        b"\x00\x00\x00\x00",
+        b"\x00\x00\x00\x04",
+        b"\x00\x00\x00\x05\x02",
+        // Larger than the maximum frame size we advertise
+        b"\x7f\xff\xff\xff",
+        // A data offset pointing past the end of the frame
+        b"\x00\x00\x00\x08\x03\x00\x00\x00",
+        // SASL frames must be sent on channel 0
+        b"\x00\x00\x00\x08\x02\x01\x00\x01",
+        b"AMQP\x02\x01\x00\x00",
+    ];
+    for bytes in invalid {
+        let mut buf = BytesMut::from(*bytes);
+        assert!(codec.decode(&mut buf).is_err(), "{:x?}", bytes);
+    }
+
+    // A partial protocol header is not mistaken for a frame size
+    let mut buf = BytesMut::from(&b"AMQP\x00"[..]);
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+}
+
 #[test]
 fn setup() {
     let open = Frame::Amqp(amqp::Frame {
@@ -67,7 +101,7 @@ fn setup() {
         message: None,
     });
     assert_eq!(open.to_vec().unwrap(), Vec::from(
-        &b"\x00\x00\x00$\x02\x00\x00\x00\x00S\x10\xd0\x00\x00\x00\x14\x00\x00\x00\t\xa1\x06source@@@@@@@@"[..]
+        &b"\x00\x00\x00%\x02\x00\x00\x00\x00S\x10\xd0\x00\x00\x00\x15\x00\x00\x00\n\xa1\x06source@@@@@@@@@"[..]
     ));
 
     let mut codec = Codec {};
@@ -92,6 +126,13 @@ fn setup() {
                     "SHARED-SUBS",
                     "ANONYMOUS-RELAY"
                 ]),
+                properties: Some(amqp::Map(vec![
+                    (
+                        amqp::Symbol("product"),
+                        Value::Str("apache-activemq-artemis".into())
+                    ),
+                    (amqp::Symbol("version"), Value::Str("2.6.2".into())),
+                ])),
                 ..Default::default()
             }),
             message: None,
@@ -251,11 +292,11 @@ fn transfer() {
             properties: Some(amqp::Properties {
                 message_id: Some(message_id.into()),
                 reply_to: Some("sender".into()),
-                user_id: Some(Bytes::new(b"user1")),
+                user_id: Some(b"user1"[..].into()),
                 ..Default::default()
             }),
             application_properties: Some(amqp::ApplicationProperties(properties)),
-            body: Some(amqp::Body::Data(amqp::Data(body))),
+            body: Some(amqp::Body::Data(amqp::Data(body.into()))),
             ..Default::default()
         }),
     });
@@ -285,3 +326,1863 @@ fn transfer() {
         })
     );
 }
+
+#[test]
+fn owned_frame() {
+    let mut codec = Codec {};
+    let mut buf = BytesMut::from(&transfer_frame(1, 0, false).to_vec().unwrap()[..]);
+    let wrapped = codec.decode(&mut buf).unwrap().unwrap();
+    assert!(buf.is_empty());
+
+    // Clones share the decoded frame, and outlive both the original and the read buffer
+    let cloned = wrapped.clone();
+    drop((wrapped, buf));
+    let handle = std::thread::spawn(move || {
+        assert_eq!(cloned.frame().name(), "transfer");
+        cloned
+    });
+
+    let frame = handle.join().unwrap();
+    match frame.frame() {
+        Frame::Amqp(amqp::Frame {
+            performative: amqp::Performative::Transfer(transfer),
+            ..
+        }) => assert_eq!(transfer.delivery_id, Some(0)),
+        frame => panic!("unexpected frame: {:?}", frame),
+    }
+    let decoded = amqp::Message::decode(frame.payload()).unwrap();
+    assert_eq!(decoded, message());
+}
+
+#[test]
+fn owned_message() {
+    let built = amqp::Message::builder()
+        .header(amqp::Header {
+            durable: Some(true),
+            ..Default::default()
+        })
+        .message_annotation("x-opt-origin", "test")
+        .message_id("foo")
+        .reply_to(String::from("sender"))
+        .user_id(&b"user1"[..])
+        .application_property("count", 3i32)
+        .data(b"baz".to_vec())
+        .build();
+
+    let properties = built.properties.as_ref().unwrap();
+    assert_eq!(properties.message_id.as_deref(), Some("foo"));
+    assert_eq!(properties.user_id.as_deref(), Some(&b"user1"[..]));
+    let application_properties = &built.application_properties.as_ref().unwrap().0;
+    assert_eq!(
+        application_properties.get("count"),
+        Some(&amqp::Any::I32(3))
+    );
+
+    let mut buf = vec![];
+    built.encode(&mut buf).unwrap();
+    let decoded = amqp::Message::decode(&buf).unwrap();
+    assert_eq!(decoded.to_borrowed(), built);
+
+    // Decoded strings refer to the buffer instead of being copied
+    let properties = decoded.properties.as_ref().unwrap();
+    assert!(matches!(properties.message_id, Some(Cow::Borrowed("foo"))));
+    assert!(matches!(properties.reply_to, Some(Cow::Borrowed("sender"))));
+    assert!(matches!(properties.user_id, Some(Cow::Borrowed(b"user1"))));
+    let annotations = &decoded.message_annotations.as_ref().unwrap().0;
+    assert!(annotations
+        .keys()
+        .all(|key| matches!(key, Cow::Borrowed(_))));
+    let application_properties = &decoded.application_properties.as_ref().unwrap().0;
+    assert!(application_properties
+        .keys()
+        .all(|key| matches!(key, Cow::Borrowed(_))));
+
+    // An owned message no longer refers to the buffer, so it can be moved to another task
+    let owned = decoded.into_owned();
+    drop(buf);
+    let handle = std::thread::spawn(move || owned);
+    assert_eq!(handle.join().unwrap(), built);
+}
+
+#[test]
+fn primitives() {
+    let values = vec![
+        amqp::Any::None,
+        amqp::Any::Bool(true),
+        amqp::Any::Bool(false),
+        amqp::Any::U8(200),
+        amqp::Any::U16(60_000),
+        amqp::Any::U32(0),
+        amqp::Any::U32(7),
+        amqp::Any::U32(100_000),
+        amqp::Any::U64(0),
+        amqp::Any::U64(9),
+        amqp::Any::U64(u64::MAX),
+        amqp::Any::I8(-3),
+        amqp::Any::I16(-300),
+        amqp::Any::I32(-5),
+        amqp::Any::I32(-200),
+        amqp::Any::I32(i32::MAX),
+        amqp::Any::I64(-5),
+        amqp::Any::I64(i64::MIN),
+        amqp::Any::F32(1.5),
+        amqp::Any::F64(-2.25),
+        amqp::Any::Decimal32([1, 2, 3, 4]),
+        amqp::Any::Decimal64([1, 2, 3, 4, 5, 6, 7, 8]),
+        amqp::Any::Decimal128([9; 16]),
+        amqp::Any::Char('ß'),
+        amqp::Any::Timestamp(amqp::Timestamp(1_600_000_000_000)),
+        amqp::Any::Uuid([0xab; 16]),
+        amqp::Any::Bytes((&b"bytes"[..]).into()),
+        amqp::Any::Symbol("amqp:symbol".into()),
+        amqp::Any::Str("string".into()),
+        amqp::Any::List(vec![]),
+        amqp::Any::List(vec![amqp::Any::U8(1), "two".into()]),
+        amqp::Any::Map(vec![
+            (amqp::Any::U64(1), "one".into()),
+            ("two".into(), amqp::Any::List(vec![amqp::Any::None])),
+        ]),
+        // Elements are widened to a common encoding
+        amqp::Any::Array(vec![
+            amqp::Any::U32(0),
+            amqp::Any::U32(5),
+            1_000_000u32.into(),
+        ]),
+        amqp::Any::Array(vec![amqp::Any::I64(-1), amqp::Any::I64(i64::MAX)]),
+        amqp::Any::Array(vec!["a".into(), "b".repeat(300).into()]),
+    ];
+
+    for value in values {
+        let mut buf = vec![];
+        oasis_amqp::ser::into_bytes(&value, &mut buf).unwrap();
+        let (decoded, rest) = oasis_amqp::de::deserialize::<amqp::Any>(&buf).unwrap();
+        assert_eq!(decoded, value, "{:x?}", buf);
+        assert!(rest.is_empty());
+    }
+
+    // The smaller encodings are used where possible
+    let mut buf = vec![];
+    oasis_amqp::ser::into_bytes(&amqp::Any::I32(-5), &mut buf).unwrap();
+    assert_eq!(buf, [0x54, 0xfb]);
+
+    let mixed = amqp::Any::Array(vec![amqp::Any::U8(1), amqp::Any::Str("two".into())]);
+    assert!(oasis_amqp::ser::into_bytes(&mixed, &mut vec![]).is_err());
+
+    // Properties carry their times as timestamps
+    let properties = amqp::Properties {
+        creation_time: Some(amqp::Timestamp(42)),
+        ..Default::default()
+    };
+    let mut buf = vec![];
+    oasis_amqp::ser::into_bytes(&properties, &mut buf).unwrap();
+    assert!(buf.windows(9).any(|w| w == [0x83, 0, 0, 0, 0, 0, 0, 0, 42]));
+    let (decoded, _) = oasis_amqp::de::deserialize::<amqp::Properties>(&buf).unwrap();
+    assert_eq!(decoded, properties);
+}
+
+#[test]
+fn values() {
+    let value = Value::Described {
+        descriptor: Box::new(Value::Symbol("com.example:point".into())),
+        value: Box::new(Value::List(vec![
+            Value::I32(-1),
+            Value::Map(vec![
+                (Value::U8(1), Value::Null),
+                (
+                    Value::Str("nested".into()),
+                    Value::Described {
+                        descriptor: Box::new(Value::U64(42)),
+                        value: Box::new(Value::Array(vec![Value::F64(0.5), Value::F64(2.0)])),
+                    },
+                ),
+            ]),
+            Value::Timestamp(amqp::Timestamp(1)),
+        ])),
+    };
+
+    let mut buf = vec![];
+    oasis_amqp::ser::into_bytes(&value, &mut buf).unwrap();
+    let (decoded, rest) = oasis_amqp::de::deserialize::<Value>(&buf).unwrap();
+    assert_eq!(decoded, value);
+    assert!(rest.is_empty());
+    assert_eq!(decoded.into_owned(), value);
+
+    // Floats compare by their bits, so that values (and the types containing them) are `Eq`
+    assert_eq!(Value::F64(f64::NAN), Value::F64(f64::NAN));
+    assert_ne!(Value::F32(0.0), Value::F32(-0.0));
+
+    // An encoded message can be read section by section without knowing its schema
+    let mut buf = vec![];
+    message().encode(&mut buf).unwrap();
+    let mut input = &buf[..];
+    let mut descriptors = vec![];
+    while !input.is_empty() {
+        let (section, rest) = oasis_amqp::de::deserialize::<Value>(input).unwrap();
+        match section {
+            Value::Described { descriptor, .. } => descriptors.push(*descriptor),
+            section => panic!("unexpected section: {:?}", section),
+        }
+        input = rest;
+    }
+    assert_eq!(descriptors, vec![Value::U64(0x74), Value::U64(0x75)]);
+}
+
+#[test]
+fn compound() {
+    let list = amqp::List(vec![Value::U8(1), Value::Str("two".into())]);
+    let mut buf = vec![];
+    oasis_amqp::ser::into_bytes(&list, &mut buf).unwrap();
+    assert_eq!(buf[0], 0xd0);
+    let (decoded, _) = oasis_amqp::de::deserialize::<amqp::List<Value>>(&buf).unwrap();
+    assert_eq!(decoded, list);
+
+    let array = amqp::Array(vec![1u32, 1_000]);
+    let mut buf = vec![];
+    oasis_amqp::ser::into_bytes(&array, &mut buf).unwrap();
+    assert_eq!(buf[0], 0xf0);
+    let (decoded, _) = oasis_amqp::de::deserialize::<amqp::Array<u32>>(&buf).unwrap();
+    assert_eq!(decoded, array);
+
+    let map = amqp::Map(vec![(amqp::Symbol("a"), 1u32), (amqp::Symbol("b"), 2)]);
+    let mut buf = vec![];
+    oasis_amqp::ser::into_bytes(&map, &mut buf).unwrap();
+    assert_eq!(buf[0], 0xd1);
+    let (decoded, _) = oasis_amqp::de::deserialize::<amqp::Map<amqp::Symbol, u32>>(&buf).unwrap();
+    assert_eq!(decoded, map);
+    assert_eq!(decoded.get(&amqp::Symbol("b")), Some(&2));
+
+    // Peers may use the compact encodings
+    let lists: &[(&[u8], &[u8])] = &[
+        (&[0x45], &[]),
+        (&[0xc0, 0x05, 0x02, 0x50, 0x01, 0x50, 0x02], &[1, 2]),
+        (
+            &[0xd0, 0, 0, 0, 0x08, 0, 0, 0, 0x02, 0x50, 0x01, 0x50, 0x02],
+            &[1, 2],
+        ),
+        (&[0xe0, 0x04, 0x02, 0x50, 0x01, 0x02], &[1, 2]),
+        (
+            &[0xf0, 0, 0, 0, 0x07, 0, 0, 0, 0x02, 0x50, 0x01, 0x02],
+            &[1, 2],
+        ),
+    ];
+    for (bytes, expected) in lists {
+        let (decoded, rest) = oasis_amqp::de::deserialize::<amqp::List<u8>>(bytes).unwrap();
+        assert_eq!(decoded.0, *expected, "{:x?}", bytes);
+        assert!(rest.is_empty());
+        let (decoded, _) = oasis_amqp::de::deserialize::<amqp::Array<u8>>(bytes).unwrap();
+        assert_eq!(decoded.0, *expected, "{:x?}", bytes);
+    }
+
+    let maps: &[&[u8]] = &[
+        &[0xc1, 0x06, 0x02, 0xa3, 0x01, b'a', 0x52, 0x01],
+        &[
+            0xd1, 0, 0, 0, 0x09, 0, 0, 0, 0x02, 0xa3, 0x01, b'a', 0x52, 0x01,
+        ],
+    ];
+    for bytes in maps {
+        let (decoded, rest) =
+            oasis_amqp::de::deserialize::<amqp::Map<amqp::Symbol, u32>>(bytes).unwrap();
+        assert_eq!(decoded, amqp::Map(vec![(amqp::Symbol("a"), 1)]));
+        assert!(rest.is_empty());
+    }
+
+    // Sizes too small to hold the count are rejected rather than underflowing
+    let malformed: &[&[u8]] = &[
+        &[0xc0, 0x00, 0x00],
+        &[0xd1, 0, 0, 0, 0x03, 0, 0, 0, 0],
+        &[0xe0, 0x01, 0x00, 0x50],
+        &[0xf0, 0, 0, 0, 0x04, 0, 0, 0, 0, 0x50],
+        &[0xc0],
+    ];
+    for bytes in malformed {
+        assert!(
+            oasis_amqp::de::deserialize::<Value>(bytes).is_err(),
+            "{:x?}",
+            bytes
+        );
+    }
+
+    // Connection properties are a real map of symbols to values
+    let open = amqp::Open {
+        container_id: "client",
+        properties: Some(amqp::Map(vec![(
+            amqp::Symbol("product"),
+            Value::Str("oasis".into()),
+        )])),
+        ..Default::default()
+    };
+    let mut buf = vec![];
+    oasis_amqp::ser::into_bytes(&open, &mut buf).unwrap();
+    let (decoded, _) = oasis_amqp::de::deserialize::<amqp::Open>(&buf).unwrap();
+    assert_eq!(decoded, open);
+}
+
+#[tokio::test]
+async fn login_rejected() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut server = Framed::new(stream, Codec);
+        let header = server.next().await.unwrap().unwrap();
+        assert_eq!(header.frame(), &Frame::Header(Protocol::Sasl));
+        server.get_mut().write_all(
+            b"AMQP\x03\x01\x00\x00\x00\x00\x00\x18\x02\x01\x00\x00\x00S@\xc0\x0b\x01\xe0\x08\x01\xa3\x05PLAIN"
+        ).await.unwrap();
+        let _init = server.next().await.unwrap().unwrap();
+        server
+            .get_mut()
+            .write_all(b"\x00\x00\x00\x16\x02\x01\x00\x00\x00SD\xc0\x09\x02P\x01\xa0\x04nope")
+            .await
+            .unwrap();
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    match client.login("user1", "wrong").await {
+        Err(ConnectionError::Sasl {
+            code: sasl::Code::Auth,
+            additional_data: Some(data),
+        }) => assert_eq!(data, b"nope"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[tokio::test]
+async fn login_mechanisms() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        for offered in &[
+            &["EXTERNAL"][..],
+            &["SCRAM-SHA-256", "PLAIN", "ANONYMOUS"][..],
+        ] {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut server = Framed::new(stream, Codec);
+            let _header = server.next().await.unwrap().unwrap();
+            server.send(&Frame::Header(Protocol::Sasl)).await.unwrap();
+            let mechanisms = mechanisms_frame(offered);
+            server.get_mut().write_all(&mechanisms).await.unwrap();
+
+            let init = match server.next().await {
+                Some(Ok(init)) => init,
+                _ => continue,
+            };
+            match init.frame() {
+                Frame::Sasl(sasl::Frame::Init(init)) => {
+                    assert_eq!(init.mechanism, sasl::Mechanism::Plain);
+                    assert_eq!(&**init.initial_response.unwrap(), b"\x00user1\x00secret");
+                }
+                frame => panic!("unexpected frame {:?}", frame),
+            }
+            let outcome = Frame::Sasl(sasl::Frame::Outcome(sasl::Outcome {
+                code: sasl::Code::Ok,
+                additional_data: None,
+            }));
+            server.send(&outcome).await.unwrap();
+            server.send(&Frame::Header(Protocol::Amqp)).await.unwrap();
+            let header = server.next().await.unwrap().unwrap();
+            assert_eq!(header.frame(), &Frame::Header(Protocol::Amqp));
+        }
+    });
+
+    let preference = [sasl::Mechanism::ScramSha1, sasl::Mechanism::Plain];
+    let mut client = Client::connect(addr).await.unwrap();
+    match client.login_with("user1", "secret", &preference).await {
+        Err(ConnectionError::SaslMechanisms(offered)) => {
+            assert_eq!(offered, vec![sasl::Mechanism::External]);
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+    drop(client);
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client
+        .login_with("user1", "secret", &preference)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn login_scram() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut server = Framed::new(stream, Codec);
+        let _header = server.next().await.unwrap().unwrap();
+        server.send(&Frame::Header(Protocol::Sasl)).await.unwrap();
+        let mechanisms = mechanisms_frame(&["PLAIN", "SCRAM-SHA-1"]);
+        server.get_mut().write_all(&mechanisms).await.unwrap();
+
+        let init = server.next().await.unwrap().unwrap();
+        let client_first = match init.frame() {
+            Frame::Sasl(sasl::Frame::Init(init)) => {
+                assert_eq!(init.mechanism, sasl::Mechanism::ScramSha1);
+                std::str::from_utf8(init.initial_response.unwrap()).unwrap()
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        };
+        let nonce = client_first.strip_prefix("n,,n=user1,r=").unwrap();
+
+        // Compute the expected client proof with the same nonce
+        let server_first = format!("r={}srv,s=QSXCR+Q6sek8bf92,i=4096", nonce);
+        let mut expected = ScramClient::with_nonce(Hash::Sha1, "user1", "secret", nonce);
+        let expected = expected.challenge(server_first.as_bytes()).unwrap();
+
+        let challenge = Frame::Sasl(sasl::Frame::Challenge(sasl::Challenge {
+            challenge: Bytes::new(server_first.as_bytes()),
+        }));
+        server.send(&challenge).await.unwrap();
+        let response = server.next().await.unwrap().unwrap();
+        match response.frame() {
+            Frame::Sasl(sasl::Frame::Response(response)) => {
+                assert_eq!(&**response.response, &*expected);
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+
+        // Claim success without knowing the password
+        let outcome = Frame::Sasl(sasl::Frame::Outcome(sasl::Outcome {
+            code: sasl::Code::Ok,
+            additional_data: Some(Bytes::new(b"v=rmF9pqV8S7suAoZWja4dJRkFsKQ=")),
+        }));
+        server.send(&outcome).await.unwrap();
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    match client.login("user1", "secret").await {
+        Err(ConnectionError::Scram(_)) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[tokio::test]
+async fn login_mechanism() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        for expected in &["EXTERNAL", "X-TOKEN"] {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut server = Framed::new(stream, Codec);
+            let _header = server.next().await.unwrap().unwrap();
+            server.send(&Frame::Header(Protocol::Sasl)).await.unwrap();
+            let mechanisms = mechanisms_frame(&["PLAIN", "EXTERNAL", "X-TOKEN"]);
+            server.get_mut().write_all(&mechanisms).await.unwrap();
+
+            let init = server.next().await.unwrap().unwrap();
+            match init.frame() {
+                Frame::Sasl(sasl::Frame::Init(init)) => {
+                    assert_eq!(init.mechanism.as_str(), *expected);
+                    assert_eq!(&**init.initial_response.unwrap(), b"");
+                }
+                frame => panic!("unexpected frame {:?}", frame),
+            }
+
+            if *expected == "X-TOKEN" {
+                let challenge = Frame::Sasl(sasl::Frame::Challenge(sasl::Challenge {
+                    challenge: Bytes::new(b"nonce"),
+                }));
+                server.send(&challenge).await.unwrap();
+                let response = server.next().await.unwrap().unwrap();
+                match response.frame() {
+                    Frame::Sasl(sasl::Frame::Response(response)) => {
+                        assert_eq!(&**response.response, b"token:nonce");
+                    }
+                    frame => panic!("unexpected frame {:?}", frame),
+                }
+            }
+
+            let outcome = Frame::Sasl(sasl::Frame::Outcome(sasl::Outcome {
+                code: sasl::Code::Ok,
+                additional_data: None,
+            }));
+            server.send(&outcome).await.unwrap();
+            server.send(&Frame::Header(Protocol::Amqp)).await.unwrap();
+            let _header = server.next().await.unwrap().unwrap();
+        }
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    let preference = [sasl::Mechanism::External, sasl::Mechanism::Plain];
+    client.login_with("", "", &preference).await.unwrap();
+
+    let mut client = Client::connect(addr).await.unwrap();
+    let mechanisms: Vec<Box<dyn SaslMechanism>> = vec![
+        Box::new(sasl::Anonymous::new()),
+        Box::new(Token("token")),
+        Box::new(sasl::External::new()),
+    ];
+    client.authenticate(mechanisms).await.unwrap();
+}
+
+/// A custom mechanism answering a single challenge
+struct Token(&'static str);
+
+impl SaslMechanism for Token {
+    fn mechanism(&self) -> sasl::Mechanism {
+        "X-TOKEN".into()
+    }
+
+    fn initial_response(&mut self) -> Result<Option<Vec<u8>>, ConnectionError> {
+        Ok(Some(Vec::new()))
+    }
+
+    fn challenge(&mut self, challenge: &[u8]) -> Result<Vec<u8>, ConnectionError> {
+        let mut response = format!("{}:", self.0).into_bytes();
+        response.extend_from_slice(challenge);
+        Ok(response)
+    }
+}
+
+#[test]
+fn connection_state() {
+    let open = Frame::Amqp(amqp::Frame {
+        channel: 0,
+        extended_header: None,
+        performative: amqp::Performative::Open(amqp::Open {
+            container_id: "source",
+            ..Default::default()
+        }),
+        message: None,
+    });
+    let close = Frame::Amqp(amqp::Frame {
+        channel: 0,
+        extended_header: None,
+        performative: amqp::Performative::Close(amqp::Close { error: None }),
+        message: None,
+    });
+    let header = Frame::Header(Protocol::Amqp);
+
+    // Pipelined header and open, as done by a client without SASL
+    let state = ConnectionState::Start.sent(&header).unwrap();
+    let state = state.sent(&open).unwrap();
+    assert_eq!(state, ConnectionState::OpenPipe);
+    let state = state.received(&header).unwrap();
+    let state = state.received(&open).unwrap();
+    assert_eq!(state, ConnectionState::Opened);
+    let state = state.sent(&close).unwrap();
+    assert_eq!(state, ConnectionState::CloseSent);
+    assert_eq!(state.received(&close).unwrap(), ConnectionState::End);
+
+    // The peer must not send an open before the protocol headers have been exchanged
+    let state = ConnectionState::Start.received(&header).unwrap();
+    assert_eq!(state, ConnectionState::HdrRcvd);
+    match state.received(&open) {
+        Err(ConnectionError::IllegalReceive {
+            state: ConnectionState::HdrRcvd,
+            frame: "open",
+        }) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[tokio::test]
+async fn sessions() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut server = Framed::new(stream, Codec);
+        let header = server.next().await.unwrap().unwrap();
+        assert_eq!(header.frame(), &Frame::Header(Protocol::Amqp));
+        server.send(&Frame::Header(Protocol::Amqp)).await.unwrap();
+        let _open = server.next().await.unwrap().unwrap();
+        let open = amqp::Performative::Open(amqp::Open {
+            container_id: "server",
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, open)).await.unwrap();
+
+        // Map the client's channels 0 and 1 to channels 5 and 7 on our side
+        for remote in &[5, 7] {
+            let begin = server.next().await.unwrap().unwrap();
+            let channel = match begin.frame() {
+                Frame::Amqp(frame) => frame.channel,
+                frame => panic!("unexpected frame {:?}", frame),
+            };
+            let begin = amqp::Performative::Begin(amqp::Begin {
+                remote_channel: Some(channel),
+                next_outgoing_id: 0,
+                incoming_window: 8,
+                outgoing_window: 8,
+                ..Default::default()
+            });
+            server.send(&amqp_frame(*remote, begin)).await.unwrap();
+        }
+
+        let end = server.next().await.unwrap().unwrap();
+        match end.frame() {
+            Frame::Amqp(frame) => assert_eq!(frame.channel, 1),
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+        let end = amqp::Performative::End(amqp::End { error: None });
+        server.send(&amqp_frame(7, end)).await.unwrap();
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    assert_eq!(client.state(), ConnectionState::Opened);
+    let first = client.begin().await.unwrap();
+    let second = client.begin().await.unwrap();
+    assert_eq!(first.channel(), 0);
+    assert_eq!(second.channel(), 1);
+    second.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn cancelled_begin() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (ended_tx, ended_rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut server = Framed::new(stream, Codec);
+        let _header = server.next().await.unwrap().unwrap();
+        server.send(&Frame::Header(Protocol::Amqp)).await.unwrap();
+        let _open = server.next().await.unwrap().unwrap();
+        let open = amqp::Performative::Open(amqp::Open {
+            container_id: "server",
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, open)).await.unwrap();
+
+        // Answer the abandoned `Begin`, which the client should end right away
+        let _begin = server.next().await.unwrap().unwrap();
+        let begin = amqp::Performative::Begin(amqp::Begin {
+            remote_channel: Some(0),
+            next_outgoing_id: 0,
+            incoming_window: 8,
+            outgoing_window: 8,
+            ..Default::default()
+        });
+        server.send(&amqp_frame(3, begin)).await.unwrap();
+        let end = server.next().await.unwrap().unwrap();
+        match end.frame() {
+            Frame::Amqp(amqp::Frame {
+                channel: 0,
+                performative: amqp::Performative::End(_),
+                ..
+            }) => {}
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+        let end = amqp::Performative::End(amqp::End { error: None });
+        server.send(&amqp_frame(3, end)).await.unwrap();
+        ended_tx.send(()).unwrap();
+
+        // Once ended, the channel can be used for a new session
+        let begin = server.next().await.unwrap().unwrap();
+        match begin.frame() {
+            Frame::Amqp(frame) => assert_eq!(frame.channel, 0),
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+        let begin = amqp::Performative::Begin(amqp::Begin {
+            remote_channel: Some(0),
+            next_outgoing_id: 0,
+            incoming_window: 8,
+            outgoing_window: 8,
+            ..Default::default()
+        });
+        server.send(&amqp_frame(4, begin)).await.unwrap();
+        let _close = server.next().await;
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    {
+        let begin = client.begin();
+        tokio::pin!(begin);
+        assert!(futures::poll!(&mut begin).is_pending());
+    }
+
+    // The new session gets channel 0 again once the first one has been ended
+    ended_rx.await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let session = client.begin().await.unwrap();
+    assert_eq!(session.channel(), 0);
+    assert_eq!(session.state(), oasis_amqp::session::SessionState::Mapped);
+}
+
+#[tokio::test]
+async fn peer_session() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut server = Framed::new(stream, Codec);
+        let _header = server.next().await.unwrap().unwrap();
+        server.send(&Frame::Header(Protocol::Amqp)).await.unwrap();
+        let _open = server.next().await.unwrap().unwrap();
+        let open = amqp::Performative::Open(amqp::Open {
+            container_id: "server",
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, open)).await.unwrap();
+
+        // Clients don't accept sessions, so one begun by the server is ended right away
+        let begin = amqp::Performative::Begin(amqp::Begin {
+            remote_channel: None,
+            next_outgoing_id: 0,
+            incoming_window: 8,
+            outgoing_window: 8,
+            ..Default::default()
+        });
+        server.send(&amqp_frame(3, begin)).await.unwrap();
+        let begin = server.next().await.unwrap().unwrap();
+        match begin.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Begin(begin),
+                ..
+            }) => assert_eq!(begin.remote_channel, Some(3)),
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+        let end = server.next().await.unwrap().unwrap();
+        match end.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::End(_),
+                ..
+            }) => {}
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+        let end = amqp::Performative::End(amqp::End { error: None });
+        server.send(&amqp_frame(3, end)).await.unwrap();
+        server
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    let _server = server.await.unwrap();
+}
+
+#[tokio::test]
+async fn from_stream() {
+    let (client, server) = tokio::io::duplex(1024);
+    tokio::spawn(async move {
+        let mut server = Framed::new(server, Codec);
+        let header = server.next().await.unwrap().unwrap();
+        assert_eq!(header.frame(), &Frame::Header(Protocol::Amqp));
+        server.send(&Frame::Header(Protocol::Amqp)).await.unwrap();
+        let _open = server.next().await.unwrap().unwrap();
+        let open = amqp::Performative::Open(amqp::Open {
+            container_id: "server",
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, open)).await.unwrap();
+
+        let close = server.next().await.unwrap().unwrap();
+        match close.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Close(_),
+                ..
+            }) => {}
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+        let close = amqp::Performative::Close(amqp::Close { error: None });
+        server.send(&amqp_frame(0, close)).await.unwrap();
+    });
+
+    let mut client = Client::from_stream(client);
+    client.open("client").await.unwrap();
+    assert_eq!(client.state(), ConnectionState::Opened);
+    client.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn links() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut server = accept_session(listener).await;
+        // Answer both attaches, using handles 3 and 4 on our side
+        assert_eq!(answer_attach(&mut server, 3).await, 0);
+        assert_eq!(answer_attach(&mut server, 4).await, 1);
+
+        let flow = amqp::Performative::Flow(amqp::Flow {
+            next_incoming_id: Some(0),
+            incoming_window: 8,
+            next_outgoing_id: 0,
+            outgoing_window: 8,
+            handle: Some(3),
+            delivery_count: Some(0),
+            link_credit: Some(2),
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, flow)).await.unwrap();
+
+        let flow = server.next().await.unwrap().unwrap();
+        match flow.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Flow(flow),
+                ..
+            }) => {
+                assert_eq!(flow.handle, Some(1));
+                assert_eq!(flow.delivery_count, Some(7));
+                assert_eq!(flow.link_credit, Some(10));
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+
+        let transfer = server.next().await.unwrap().unwrap();
+        let delivery_id = match transfer.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Transfer(transfer),
+                ..
+            }) => {
+                assert_eq!(transfer.handle, 0);
+                transfer.delivery_id.unwrap()
+            }
+            frame => panic!("unexpected frame {:?}", frame),
+        };
+
+        // Deliver to the receiving link before settling the sender's delivery
+        let transfer = amqp::Performative::Transfer(amqp::Transfer {
+            handle: 4,
+            delivery_id: Some(0),
+            delivery_tag: Some(vec![1]),
+            settled: Some(true),
+            ..Default::default()
+        });
+        server
+            .send(&Frame::Amqp(amqp::Frame {
+                channel: 0,
+                extended_header: None,
+                performative: transfer,
+                message: Some(amqp::Message {
+                    application_properties: Some(amqp::ApplicationProperties(HashMap::new())),
+                    body: Some(amqp::Body::Data(amqp::Data(b"hello"[..].into()))),
+                    ..Default::default()
+                }),
+            }))
+            .await
+            .unwrap();
+
+        let disposition = amqp::Performative::Disposition(amqp::Disposition {
+            role: amqp::Role::Receiver,
+            first: delivery_id,
+            last: None,
+            settled: Some(true),
+            state: None,
+            batchable: None,
+        });
+        server.send(&amqp_frame(0, disposition)).await.unwrap();
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    let session = client.begin().await.unwrap();
+    let sender = session.sender("queue").attach().await.unwrap();
+    let receiver = session.receiver("queue").attach().await.unwrap();
+    assert_eq!(sender.handle(), 0);
+    assert_eq!(receiver.handle(), 1);
+    assert_eq!(sender.state(), LinkState::Attached);
+
+    receiver.flow(10).await.unwrap();
+    let task = tokio::spawn({
+        let receiver = receiver.clone();
+        async move { receiver.recv().await.unwrap() }
+    });
+
+    assert_eq!(sender.send(message()).await.unwrap(), Outcome::Accepted);
+    assert_eq!(sender.credit(), 1);
+    assert_eq!(sender.delivery_count(), 1);
+
+    let delivery = task.await.unwrap();
+    assert_eq!(delivery.tag(), &[1]);
+    assert_eq!(delivery.body(), Some(&b"hello"[..]));
+    assert_eq!(receiver.credit(), 9);
+    assert_eq!(receiver.delivery_count(), 8);
+}
+
+#[tokio::test]
+async fn deliveries() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut server = accept_session(listener).await;
+        answer_attach(&mut server, 2).await;
+        let _flow = server.next().await.unwrap().unwrap();
+
+        for id in 0..2 {
+            server.send(&transfer_frame(2, id, false)).await.unwrap();
+        }
+
+        let accepted = server.next().await.unwrap().unwrap();
+        assert_eq!(
+            accepted.frame(),
+            &amqp_frame(
+                0,
+                amqp::Performative::Disposition(amqp::Disposition {
+                    role: amqp::Role::Receiver,
+                    first: 0,
+                    last: None,
+                    settled: Some(true),
+                    state: Some(amqp::DeliveryState::Accepted(amqp::Accepted {})),
+                    batchable: None,
+                })
+            )
+        );
+
+        let rejected = server.next().await.unwrap().unwrap();
+        match rejected.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative:
+                    amqp::Performative::Disposition(amqp::Disposition {
+                        first: 1,
+                        state: Some(amqp::DeliveryState::Rejected(rejected)),
+                        ..
+                    }),
+                ..
+            }) => assert_eq!(rejected.error.as_ref().unwrap().condition, "test:invalid"),
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+
+        let detach = amqp::Performative::Detach(amqp::Detach {
+            handle: 2,
+            closed: Some(true),
+            error: Some(amqp::Error {
+                condition: "amqp:resource-deleted",
+                description: None,
+                info: None,
+            }),
+        });
+        server.send(&amqp_frame(0, detach)).await.unwrap();
+        let _detach = server.next().await.unwrap().unwrap();
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    let session = client.begin().await.unwrap();
+    let mut receiver = session.receiver("queue").attach().await.unwrap();
+    receiver.flow(2).await.unwrap();
+
+    let first = receiver.next().await.unwrap().unwrap();
+    assert_eq!(first.id(), 0);
+    assert!(!first.settled());
+    assert_eq!(
+        first.message().body,
+        Some(amqp::Body::Data(amqp::Data(b"hello"[..].into())))
+    );
+    first.accept().await.unwrap();
+
+    let second = receiver.next().await.unwrap().unwrap();
+    assert_eq!(second.tag(), &[1]);
+    let error = amqp::Error {
+        condition: "test:invalid",
+        description: None,
+        info: None,
+    };
+    second.reject(Some(error)).await.unwrap();
+
+    // The stream yields the error when the peer detaches the link, and ends after that
+    match receiver.next().await {
+        Some(Err(ConnectionError::RemoteDetach(Some(error)))) => {
+            assert_eq!(error.condition, "amqp:resource-deleted")
+        }
+        res => panic!("unexpected result {:?}", res.map(|res| res.map(|d| d.id()))),
+    }
+    assert!(receiver.next().await.is_none());
+    match receiver.recv().await {
+        Err(ConnectionError::RemoteDetach(Some(error))) => {
+            assert_eq!(error.condition, "amqp:resource-deleted")
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn outcomes() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let mut server = accept_session(listener).await;
+        answer_attach(&mut server, 3).await;
+        answer_attach(&mut server, 4).await;
+        for (handle, link_credit) in [(3, 1), (4, 2)] {
+            let flow = amqp::Performative::Flow(amqp::Flow {
+                next_incoming_id: Some(0),
+                incoming_window: 8,
+                next_outgoing_id: 0,
+                outgoing_window: 8,
+                handle: Some(handle),
+                delivery_count: Some(0),
+                link_credit: Some(link_credit),
+                ..Default::default()
+            });
+            server.send(&amqp_frame(0, flow)).await.unwrap();
+        }
+
+        for (handle, settled) in [(0, true), (1, false), (1, false)] {
+            let transfer = server.next().await.unwrap().unwrap();
+            match transfer.frame() {
+                Frame::Amqp(amqp::Frame {
+                    performative: amqp::Performative::Transfer(transfer),
+                    ..
+                }) => {
+                    assert_eq!(transfer.handle, handle);
+                    assert_eq!(transfer.settled, Some(settled));
+                }
+                frame => panic!("unexpected frame {:?}", frame),
+            }
+        }
+
+        // Reject both unsettled deliveries, leaving settlement to the sender
+        let disposition = amqp::Performative::Disposition(amqp::Disposition {
+            role: amqp::Role::Receiver,
+            first: 1,
+            last: Some(2),
+            settled: Some(false),
+            state: Some(amqp::DeliveryState::Rejected(amqp::Rejected {
+                error: Some(amqp::Error {
+                    condition: "test:rejected",
+                    description: None,
+                    info: None,
+                }),
+            })),
+            batchable: None,
+        });
+        server.send(&amqp_frame(0, disposition)).await.unwrap();
+
+        let settle = server.next().await.unwrap().unwrap();
+        assert_eq!(
+            settle.frame(),
+            &amqp_frame(
+                0,
+                amqp::Performative::Disposition(amqp::Disposition {
+                    role: amqp::Role::Sender,
+                    first: 1,
+                    last: Some(2),
+                    settled: Some(true),
+                    state: None,
+                    batchable: None,
+                })
+            )
+        );
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    let session = client.begin().await.unwrap();
+    let presettled = session
+        .sender("queue")
+        .settle_mode(amqp::SenderSettleMode::Settled)
+        .attach()
+        .await
+        .unwrap();
+    let sender = session.sender("queue").attach().await.unwrap();
+
+    assert_eq!(presettled.send(message()).await.unwrap(), Outcome::Accepted);
+    let (first, second) = futures::join!(sender.send(message()), sender.send(message()));
+    let rejected = Outcome::Rejected(Some(RemoteError {
+        condition: "test:rejected".into(),
+        description: None,
+    }));
+    assert_eq!(first.unwrap(), rejected);
+    assert_eq!(second.unwrap(), rejected);
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn credit() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let mut server = accept_session(listener).await;
+        answer_attach(&mut server, 2).await;
+        assert_eq!(next_flow(&mut server).await, (7, 4, None));
+        for id in 0..2 {
+            server.send(&transfer_frame(2, id, true)).await.unwrap();
+        }
+
+        // Topped up after the second delivery has been picked up
+        assert_eq!(next_flow(&mut server).await, (9, 4, None));
+        assert_eq!(next_flow(&mut server).await, (9, 4, Some(true)));
+        let flow = amqp::Performative::Flow(amqp::Flow {
+            next_incoming_id: Some(0),
+            incoming_window: 8,
+            next_outgoing_id: 2,
+            outgoing_window: 8,
+            handle: Some(2),
+            delivery_count: Some(13),
+            link_credit: Some(0),
+            drain: Some(true),
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, flow)).await.unwrap();
+        assert_eq!(next_flow(&mut server).await, (13, 4, None));
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    let session = client.begin().await.unwrap();
+    let receiver = session
+        .receiver("queue")
+        .credit(Credit::Prefetch(4))
+        .attach()
+        .await
+        .unwrap();
+
+    receiver.recv().await.unwrap();
+    receiver.recv().await.unwrap();
+    receiver.drain().await.unwrap();
+    assert_eq!(receiver.delivery_count(), 13);
+    server.await.unwrap();
+}
+
+/// Read the next `Flow` from the client, returning its delivery count, credit and drain flag
+#[tokio::test]
+async fn drain_pending_send() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (pending_tx, pending_rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let mut server = accept_session(listener).await;
+        let handle = answer_attach(&mut server, 0).await;
+
+        // Ask to drain while the client is waiting for credit to send
+        pending_rx.await.unwrap();
+        let flow = amqp::Performative::Flow(amqp::Flow {
+            next_incoming_id: Some(0),
+            incoming_window: 8,
+            next_outgoing_id: 0,
+            outgoing_window: 8,
+            handle: Some(0),
+            delivery_count: Some(0),
+            link_credit: Some(2),
+            drain: Some(true),
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, flow)).await.unwrap();
+
+        // The pending message goes out first, then the remaining credit is used up
+        let transfer = server.next().await.unwrap().unwrap();
+        match transfer.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Transfer(transfer),
+                ..
+            }) => assert_eq!(transfer.handle, handle),
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+        assert_eq!(next_flow(&mut server).await, (2, 0, None));
+
+        let disposition = amqp::Performative::Disposition(amqp::Disposition {
+            role: amqp::Role::Receiver,
+            first: 0,
+            last: None,
+            settled: Some(true),
+            state: Some(amqp::DeliveryState::Accepted(amqp::Accepted {})),
+            batchable: None,
+        });
+        server.send(&amqp_frame(0, disposition)).await.unwrap();
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    let session = client.begin().await.unwrap();
+    let sender = session.sender("queue").attach().await.unwrap();
+
+    let send = sender.send(message());
+    tokio::pin!(send);
+    assert!(futures::poll!(&mut send).is_pending());
+    pending_tx.send(()).unwrap();
+    assert_eq!(send.await.unwrap(), Outcome::Accepted);
+    assert_eq!(sender.credit(), 0);
+    assert_eq!(sender.delivery_count(), 2);
+}
+
+#[tokio::test]
+async fn multi_frame() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut server = accept_session(listener).await;
+        answer_attach(&mut server, 2).await;
+        let _flow = server.next().await.unwrap().unwrap();
+
+        // Split the first delivery over three transfers
+        let header = partial_transfer(2, Some(0), None, false).to_vec().unwrap();
+        let encoded = transfer_frame(2, 0, false).to_vec().unwrap();
+        let payload = &encoded[header.len()..];
+        let (start, end) = (payload.len() / 3, payload.len() * 2 / 3);
+        let io = server.get_mut();
+        io.write_all(&fragment(2, Some(0), Some(true), false, &payload[..start]))
+            .await
+            .unwrap();
+        io.write_all(&fragment(2, None, Some(true), false, &payload[start..end]))
+            .await
+            .unwrap();
+        io.write_all(&fragment(2, None, None, false, &payload[end..]))
+            .await
+            .unwrap();
+
+        // The second delivery is aborted halfway through
+        io.write_all(&fragment(2, Some(1), Some(true), false, &payload[..start]))
+            .await
+            .unwrap();
+        io.write_all(&fragment(2, None, None, true, &[]))
+            .await
+            .unwrap();
+
+        server.send(&transfer_frame(2, 2, true)).await.unwrap();
+        let _disposition = server.next().await.unwrap().unwrap();
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    let session = client.begin().await.unwrap();
+    let mut receiver = session.receiver("queue").attach().await.unwrap();
+    receiver.flow(3).await.unwrap();
+
+    let first = receiver.next().await.unwrap().unwrap();
+    assert_eq!(first.id(), 0);
+    assert_eq!(first.message(), &message());
+    first.accept().await.unwrap();
+
+    let third = receiver.next().await.unwrap().unwrap();
+    assert_eq!(third.id(), 2);
+    assert_eq!(third.body(), Some(&b"hello"[..]));
+    assert_eq!(receiver.credit(), 0);
+    assert_eq!(receiver.delivery_count(), 10);
+}
+
+#[tokio::test]
+async fn invalid_partial_deliveries() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut server = accept_session(listener).await;
+
+        // The delivery outgrows the receiver's maximum message size
+        let handle = answer_attach(&mut server, 2).await;
+        let _flow = server.next().await.unwrap().unwrap();
+        let io = server.get_mut();
+        io.write_all(&fragment(2, Some(0), Some(true), false, &[0; 10]))
+            .await
+            .unwrap();
+        io.write_all(&fragment(2, None, Some(true), false, &[0; 10]))
+            .await
+            .unwrap();
+        let condition = next_detach(&mut server, handle, 2).await;
+        assert_eq!(condition, "amqp:link:message-size-exceeded");
+
+        // A continuation frame claims to belong to another delivery
+        let handle = answer_attach(&mut server, 3).await;
+        let _flow = server.next().await.unwrap().unwrap();
+        let io = server.get_mut();
+        io.write_all(&fragment(3, Some(1), Some(true), false, &[0; 10]))
+            .await
+            .unwrap();
+        io.write_all(&fragment(3, Some(2), None, false, &[0; 10]))
+            .await
+            .unwrap();
+        let condition = next_detach(&mut server, handle, 3).await;
+        assert_eq!(condition, "amqp:invalid-field");
+
+        // The first frame of a delivery does not say which delivery it is
+        let handle = answer_attach(&mut server, 4).await;
+        let _flow = server.next().await.unwrap().unwrap();
+        let io = server.get_mut();
+        io.write_all(&fragment(4, None, Some(true), false, &[0; 10]))
+            .await
+            .unwrap();
+        let condition = next_detach(&mut server, handle, 4).await;
+        assert_eq!(condition, "amqp:invalid-field");
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    let session = client.begin().await.unwrap();
+
+    let receiver = session
+        .receiver("queue")
+        .max_message_size(16)
+        .attach()
+        .await
+        .unwrap();
+    receiver.flow(1).await.unwrap();
+    match receiver.recv().await {
+        Err(ConnectionError::LocalDetach(error)) => {
+            assert_eq!(error.condition, "amqp:link:message-size-exceeded")
+        }
+        res => panic!("unexpected result {:?}", res.map(|delivery| delivery.id())),
+    }
+
+    let receiver = session.receiver("queue").attach().await.unwrap();
+    receiver.flow(1).await.unwrap();
+    match receiver.recv().await {
+        Err(ConnectionError::LocalDetach(error)) => {
+            assert_eq!(error.condition, "amqp:invalid-field")
+        }
+        res => panic!("unexpected result {:?}", res.map(|delivery| delivery.id())),
+    }
+
+    let receiver = session.receiver("queue").attach().await.unwrap();
+    receiver.flow(1).await.unwrap();
+    match receiver.recv().await {
+        Err(ConnectionError::LocalDetach(error)) => {
+            assert_eq!(error.condition, "amqp:invalid-field")
+        }
+        res => panic!("unexpected result {:?}", res.map(|delivery| delivery.id())),
+    }
+}
+
+#[tokio::test]
+async fn fragmentation() {
+    let body = vec![7u8; 1500];
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let expected = body.clone();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut server = Framed::new(stream, Codec);
+        let _header = server.next().await.unwrap().unwrap();
+        server.send(&Frame::Header(Protocol::Amqp)).await.unwrap();
+        let open = server.next().await.unwrap().unwrap();
+        match open.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Open(open),
+                ..
+            }) => assert_eq!(open.max_frame_size, Some(64 * 1024)),
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+
+        let open = amqp::Performative::Open(amqp::Open {
+            container_id: "server",
+            max_frame_size: Some(512),
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, open)).await.unwrap();
+        let _begin = server.next().await.unwrap().unwrap();
+        let begin = amqp::Performative::Begin(amqp::Begin {
+            remote_channel: Some(0),
+            next_outgoing_id: 0,
+            incoming_window: 8,
+            outgoing_window: 8,
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, begin)).await.unwrap();
+        let handle = answer_attach(&mut server, 0).await;
+        let flow = amqp::Performative::Flow(amqp::Flow {
+            next_incoming_id: Some(0),
+            incoming_window: 8,
+            next_outgoing_id: 0,
+            outgoing_window: 8,
+            handle: Some(0),
+            delivery_count: Some(0),
+            link_credit: Some(1),
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, flow)).await.unwrap();
+
+        let mut payload = vec![];
+        let mut frames = 0;
+        loop {
+            let frame = server.next().await.unwrap().unwrap();
+            let more = match frame.frame() {
+                Frame::Amqp(amqp::Frame {
+                    performative: amqp::Performative::Transfer(transfer),
+                    ..
+                }) => {
+                    assert_eq!(transfer.handle, handle);
+                    assert_eq!(transfer.delivery_id.is_some(), frames == 0);
+                    transfer.more == Some(true)
+                }
+                frame => panic!("unexpected frame {:?}", frame),
+            };
+
+            assert!(frame.payload().len() < 512);
+            payload.extend_from_slice(frame.payload());
+            frames += 1;
+            if !more {
+                break;
+            }
+        }
+
+        assert_eq!(frames, 4);
+        let message = amqp::Message::decode(&payload).unwrap();
+        assert_eq!(
+            message.body,
+            Some(amqp::Body::Data(amqp::Data(expected.into())))
+        );
+
+        let disposition = amqp::Performative::Disposition(amqp::Disposition {
+            role: amqp::Role::Receiver,
+            first: 0,
+            last: None,
+            settled: Some(true),
+            state: Some(amqp::DeliveryState::Accepted(amqp::Accepted {})),
+            batchable: None,
+        });
+        server.send(&amqp_frame(0, disposition)).await.unwrap();
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    assert_eq!(client.max_frame_size(), 512);
+    let session = client.begin().await.unwrap();
+    let sender = session.sender("queue").attach().await.unwrap();
+    let message = amqp::Message {
+        application_properties: Some(amqp::ApplicationProperties(HashMap::new())),
+        body: Some(amqp::Body::Data(amqp::Data(body.into()))),
+        ..Default::default()
+    };
+    assert_eq!(sender.send(message).await.unwrap(), Outcome::Accepted);
+}
+
+#[tokio::test]
+async fn fragmentation_window() {
+    let body = vec![7u8; 1500];
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut server = Framed::new(stream, Codec);
+        let _header = server.next().await.unwrap().unwrap();
+        server.send(&Frame::Header(Protocol::Amqp)).await.unwrap();
+        let _open = server.next().await.unwrap().unwrap();
+        let open = amqp::Performative::Open(amqp::Open {
+            container_id: "server",
+            max_frame_size: Some(512),
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, open)).await.unwrap();
+
+        // The delivery takes four frames, but we only accept two at a time
+        let _begin = server.next().await.unwrap().unwrap();
+        let begin = amqp::Performative::Begin(amqp::Begin {
+            remote_channel: Some(0),
+            next_outgoing_id: 0,
+            incoming_window: 2,
+            outgoing_window: 8,
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, begin)).await.unwrap();
+        answer_attach(&mut server, 0).await;
+        let flow = amqp::Performative::Flow(amqp::Flow {
+            next_incoming_id: Some(0),
+            incoming_window: 2,
+            next_outgoing_id: 0,
+            outgoing_window: 8,
+            handle: Some(0),
+            delivery_count: Some(0),
+            link_credit: Some(1),
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, flow)).await.unwrap();
+
+        let mut more = vec![];
+        for next_incoming_id in [2, 4] {
+            for _ in 0..2 {
+                let frame = server.next().await.unwrap().unwrap();
+                match frame.frame() {
+                    Frame::Amqp(amqp::Frame {
+                        performative: amqp::Performative::Transfer(transfer),
+                        ..
+                    }) => more.push(transfer.more == Some(true)),
+                    frame => panic!("unexpected frame {:?}", frame),
+                }
+            }
+
+            let wait = tokio::time::timeout(Duration::from_millis(100), server.next()).await;
+            assert!(wait.is_err(), "transfer sent beyond the incoming window");
+            let flow = amqp::Performative::Flow(amqp::Flow {
+                next_incoming_id: Some(next_incoming_id),
+                incoming_window: 2,
+                next_outgoing_id: 0,
+                outgoing_window: 8,
+                ..Default::default()
+            });
+            server.send(&amqp_frame(0, flow)).await.unwrap();
+        }
+        assert_eq!(more, [true, true, true, false]);
+
+        let disposition = amqp::Performative::Disposition(amqp::Disposition {
+            role: amqp::Role::Receiver,
+            first: 0,
+            last: None,
+            settled: Some(true),
+            state: Some(amqp::DeliveryState::Accepted(amqp::Accepted {})),
+            batchable: None,
+        });
+        server.send(&amqp_frame(0, disposition)).await.unwrap();
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    let session = client.begin().await.unwrap();
+    let sender = session.sender("queue").attach().await.unwrap();
+    let message = amqp::Message {
+        application_properties: Some(amqp::ApplicationProperties(HashMap::new())),
+        body: Some(amqp::Body::Data(amqp::Data(body.into()))),
+        ..Default::default()
+    };
+    assert_eq!(sender.send(message).await.unwrap(), Outcome::Accepted);
+}
+
+#[tokio::test]
+async fn heartbeats() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut server = Framed::new(stream, Codec);
+        let _header = server.next().await.unwrap().unwrap();
+        server.send(&Frame::Header(Protocol::Amqp)).await.unwrap();
+        let _open = server.next().await.unwrap().unwrap();
+        let open = amqp::Performative::Open(amqp::Open {
+            container_id: "server",
+            idle_timeout: Some(200),
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, open)).await.unwrap();
+        server.send(&Frame::Empty).await.unwrap();
+
+        for _ in 0..3 {
+            let frame = tokio::time::timeout(Duration::from_millis(150), server.next());
+            let frame = frame.await.unwrap().unwrap().unwrap();
+            assert_eq!(frame.frame(), &Frame::Empty);
+        }
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    server.await.unwrap();
+    assert_eq!(client.state(), ConnectionState::Opened);
+}
+
+#[tokio::test]
+async fn idle_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut server = Framed::new(stream, Codec);
+        let _header = server.next().await.unwrap().unwrap();
+        server.send(&Frame::Header(Protocol::Amqp)).await.unwrap();
+        let open = server.next().await.unwrap().unwrap();
+        match open.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Open(open),
+                ..
+            }) => assert_eq!(open.idle_timeout, Some(100)),
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+
+        let open = amqp::Performative::Open(amqp::Open {
+            container_id: "server",
+            ..Default::default()
+        });
+        server.send(&amqp_frame(0, open)).await.unwrap();
+
+        // Go silent after the begin, the client should give up on us
+        let _begin = server.next().await.unwrap().unwrap();
+        let close = server.next().await.unwrap().unwrap();
+        match close.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Close(amqp::Close { error: Some(error) }),
+                ..
+            }) => assert_eq!(error.condition, "amqp:resource-limit-exceeded"),
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.set_idle_timeout(Some(Duration::from_millis(100)));
+    client.open("client").await.unwrap();
+    match client.begin().await {
+        Err(ConnectionError::Failed(error)) => match *error {
+            ConnectionError::IdleTimeout(timeout) => {
+                assert_eq!(timeout, Duration::from_millis(100))
+            }
+            ref error => panic!("unexpected error: {:?}", error),
+        },
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    // Later callers see the same cause rather than a bare disconnect
+    match client.begin().await {
+        Err(ConnectionError::Failed(error)) => {
+            assert!(matches!(*error, ConnectionError::IdleTimeout(_)))
+        }
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn close() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut server = accept_session(listener).await;
+        answer_attach(&mut server, 3).await;
+        answer_attach(&mut server, 4).await;
+
+        // Refuse to detach the sender cleanly
+        let detach = server.next().await.unwrap().unwrap();
+        assert_eq!(detach.frame().name(), "detach");
+        let detach = amqp::Performative::Detach(amqp::Detach {
+            handle: 3,
+            closed: Some(true),
+            error: Some(amqp::Error {
+                condition: "amqp:internal-error",
+                description: None,
+                info: None,
+            }),
+        });
+        server.send(&amqp_frame(0, detach)).await.unwrap();
+
+        let detach = server.next().await.unwrap().unwrap();
+        assert_eq!(detach.frame().name(), "detach");
+        let detach = amqp::Performative::Detach(amqp::Detach {
+            handle: 4,
+            closed: Some(true),
+            error: None,
+        });
+        server.send(&amqp_frame(0, detach)).await.unwrap();
+
+        let end = server.next().await.unwrap().unwrap();
+        assert_eq!(end.frame().name(), "end");
+        let end = amqp::Performative::End(amqp::End { error: None });
+        server.send(&amqp_frame(0, end)).await.unwrap();
+
+        let close = server.next().await.unwrap().unwrap();
+        assert_eq!(close.frame().name(), "close");
+        let close = amqp::Performative::Close(amqp::Close { error: None });
+        server.send(&amqp_frame(0, close)).await.unwrap();
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    let session = client.begin().await.unwrap();
+    let sender = session.sender("queue").attach().await.unwrap();
+    let receiver = session.receiver("queue").attach().await.unwrap();
+
+    match sender.close().await {
+        Err(ConnectionError::RemoteDetach(Some(error))) => {
+            assert_eq!(error.condition, "amqp:internal-error")
+        }
+        result => panic!("unexpected result: {:?}", result),
+    }
+    receiver.close().await.unwrap();
+    session.close().await.unwrap();
+    client.clone().close().await.unwrap();
+    assert_eq!(client.state(), ConnectionState::End);
+}
+
+#[tokio::test]
+async fn close_on_drop() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let mut server = accept_session(listener).await;
+        answer_attach(&mut server, 3).await;
+        let mut frames = vec![];
+        while let Some(frame) = server.next().await {
+            frames.push(frame.unwrap().frame().name());
+        }
+        assert_eq!(frames, vec!["detach", "end", "close"]);
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    let session = client.begin().await.unwrap();
+    let sender = session.sender("queue").attach().await.unwrap();
+
+    // Another session handle keeps the session alive while the link goes away
+    let other = session.clone();
+    drop(sender);
+    drop(session);
+    drop(other);
+    drop(client);
+    server.await.unwrap();
+}
+
+/// Encode a transfer carrying a slice of an encoded message
+fn fragment(
+    handle: u32,
+    id: Option<u32>,
+    more: Option<bool>,
+    aborted: bool,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut buf = partial_transfer(handle, id, more, aborted)
+        .to_vec()
+        .unwrap();
+    buf.extend_from_slice(payload);
+    let len = buf.len() as u32;
+    buf[..4].copy_from_slice(&len.to_be_bytes());
+    buf
+}
+
+fn partial_transfer(
+    handle: u32,
+    id: Option<u32>,
+    more: Option<bool>,
+    aborted: bool,
+) -> Frame<'static> {
+    amqp_frame(
+        0,
+        amqp::Performative::Transfer(amqp::Transfer {
+            handle,
+            delivery_id: id,
+            delivery_tag: id.map(|id| vec![id as u8]),
+            settled: id.map(|_| false),
+            more,
+            aborted: aborted.then_some(true),
+            ..Default::default()
+        }),
+    )
+}
+
+/// Encode a sasl-mechanisms frame with the mechanisms as a symbol array
+fn mechanisms_frame(mechanisms: &[&str]) -> Vec<u8> {
+    let mut array = vec![mechanisms.len() as u8, 0xa3];
+    for mechanism in mechanisms {
+        array.push(mechanism.len() as u8);
+        array.extend_from_slice(mechanism.as_bytes());
+    }
+
+    let mut body = vec![0x00, 0x53, 0x40, 0xc0, array.len() as u8 + 3, 0x01, 0xe0];
+    body.push(array.len() as u8);
+    body.extend_from_slice(&array);
+
+    let mut frame = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(&[0x02, 0x01, 0x00, 0x00]);
+    frame.extend_from_slice(&body);
+    frame
+}
+
+async fn next_flow(server: &mut Framed<TcpStream, Codec>) -> (u32, u32, Option<bool>) {
+    let flow = server.next().await.unwrap().unwrap();
+    match flow.frame() {
+        Frame::Amqp(amqp::Frame {
+            performative: amqp::Performative::Flow(flow),
+            ..
+        }) => (
+            flow.delivery_count.unwrap(),
+            flow.link_credit.unwrap(),
+            flow.drain,
+        ),
+        frame => panic!("unexpected frame {:?}", frame),
+    }
+}
+
+fn transfer_frame(handle: u32, id: u32, settled: bool) -> Frame<'static> {
+    Frame::Amqp(amqp::Frame {
+        channel: 0,
+        extended_header: None,
+        performative: amqp::Performative::Transfer(amqp::Transfer {
+            handle,
+            delivery_id: Some(id),
+            delivery_tag: Some(vec![id as u8]),
+            settled: Some(settled),
+            ..Default::default()
+        }),
+        message: Some(message()),
+    })
+}
+
+fn message() -> amqp::Message<'static> {
+    amqp::Message {
+        application_properties: Some(amqp::ApplicationProperties(HashMap::new())),
+        body: Some(amqp::Body::Data(amqp::Data(b"hello"[..].into()))),
+        ..Default::default()
+    }
+}
+
+/// Accept a connection and answer the client's protocol header, `Open` and `Begin`
+async fn accept_session(listener: TcpListener) -> Framed<TcpStream, Codec> {
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut server = Framed::new(stream, Codec);
+    let _header = server.next().await.unwrap().unwrap();
+    server.send(&Frame::Header(Protocol::Amqp)).await.unwrap();
+    let _open = server.next().await.unwrap().unwrap();
+    let open = amqp::Performative::Open(amqp::Open {
+        container_id: "server",
+        ..Default::default()
+    });
+    server.send(&amqp_frame(0, open)).await.unwrap();
+    let _begin = server.next().await.unwrap().unwrap();
+    let begin = amqp::Performative::Begin(amqp::Begin {
+        remote_channel: Some(0),
+        next_outgoing_id: 0,
+        incoming_window: 8,
+        outgoing_window: 8,
+        ..Default::default()
+    });
+    server.send(&amqp_frame(0, begin)).await.unwrap();
+    server
+}
+
+/// Answer the client's next `Attach` using `handle`, returning the client's handle
+async fn answer_attach(server: &mut Framed<TcpStream, Codec>, handle: u32) -> u32 {
+    let attach = server.next().await.unwrap().unwrap();
+    let attach = match attach.frame() {
+        Frame::Amqp(amqp::Frame {
+            performative: amqp::Performative::Attach(attach),
+            ..
+        }) => attach,
+        frame => panic!("unexpected frame {:?}", frame),
+    };
+
+    let role = match attach.role {
+        amqp::Role::Sender => amqp::Role::Receiver,
+        amqp::Role::Receiver => amqp::Role::Sender,
+    };
+    let reply = amqp::Performative::Attach(amqp::Attach {
+        name: attach.name,
+        handle,
+        role,
+        snd_settle_mode: None,
+        rcv_settle_mode: None,
+        source: None,
+        target: None,
+        unsettled: None,
+        incomplete_unsettled: None,
+        initial_delivery_count: Some(7),
+        max_message_size: None,
+        offered_capabilities: None,
+        desired_capabilities: None,
+        properties: None,
+    });
+    server.send(&amqp_frame(0, reply)).await.unwrap();
+    attach.handle
+}
+
+/// Wait for the client to detach `handle` with an error and answer with our `remote` handle
+///
+/// Returns the condition of the client's error.
+async fn next_detach(server: &mut Framed<TcpStream, Codec>, handle: u32, remote: u32) -> String {
+    let condition = loop {
+        let frame = server.next().await.unwrap().unwrap();
+        match frame.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Detach(detach),
+                ..
+            }) => {
+                assert_eq!(detach.handle, handle);
+                break detach.error.as_ref().unwrap().condition.to_owned();
+            }
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Flow(_),
+                ..
+            }) => continue,
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+    };
+
+    let detach = amqp::Performative::Detach(amqp::Detach {
+        handle: remote,
+        closed: Some(true),
+        error: None,
+    });
+    server.send(&amqp_frame(0, detach)).await.unwrap();
+    condition
+}
+
+fn amqp_frame(channel: u16, performative: amqp::Performative) -> Frame {
+    Frame::Amqp(amqp::Frame {
+        channel,
+        extended_header: None,
+        performative,
+        message: None,
+    })
+}
@@ -0,0 +1,76 @@
+use oasis_amqp::sasl::SaslMechanism;
+use oasis_amqp::scram::{Hash, ScramClient};
+use oasis_amqp::ConnectionError;
+
+// Test vectors from RFC 5802, section 5
+#[test]
+fn sha1() {
+    let mut client =
+        ScramClient::with_nonce(Hash::Sha1, "user", "pencil", "fyko+d2lbbFgONRv9qkxdawL");
+    assert_eq!(
+        client.client_first(),
+        b"n,,n=user,r=fyko+d2lbbFgONRv9qkxdawL"
+    );
+
+    let server_first = b"r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096";
+    assert_eq!(
+        client.challenge(server_first).unwrap(),
+        &b"c=biws,r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,p=v0X8v3Bz2T0CJGbJQyF0X+HI4Ts="[..]
+    );
+    client
+        .outcome(Some(b"v=rmF9pqV8S7suAoZWja4dJRkFsKQ="))
+        .unwrap();
+}
+
+// Test vectors from RFC 7677, section 3
+#[test]
+fn sha256() {
+    let mut client =
+        ScramClient::with_nonce(Hash::Sha256, "user", "pencil", "rOprNGfwEbeRWgbNEkqO");
+    assert_eq!(client.client_first(), b"n,,n=user,r=rOprNGfwEbeRWgbNEkqO");
+
+    let server_first =
+        b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+    assert_eq!(
+        client.challenge(server_first).unwrap(),
+        &b"c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="[..]
+    );
+
+    // The server-final message may also be sent as a second challenge
+    let server_final = b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=";
+    assert_eq!(client.challenge(server_final).unwrap(), b"");
+    client.outcome(None).unwrap();
+}
+
+#[test]
+fn invalid() {
+    let new = || ScramClient::with_nonce(Hash::Sha1, "user", "pencil", "fyko+d2lbbFgONRv9qkxdawL");
+    let server_first = b"r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096";
+
+    // The server must extend the client's nonce
+    let mut client = new();
+    match client.challenge(b"r=3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096") {
+        Err(ConnectionError::Scram(_)) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    // The server signature must match
+    let mut client = new();
+    client.challenge(server_first).unwrap();
+    match client.outcome(Some(b"v=AAAApqV8S7suAoZWja4dJRkFsKQ=")) {
+        Err(ConnectionError::Scram(_)) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    // A successful outcome without the server signature does not authenticate the server
+    let mut client = new();
+    client.challenge(server_first).unwrap();
+    match client.outcome(None) {
+        Err(ConnectionError::Scram(_)) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    // User names are escaped
+    let client = ScramClient::with_nonce(Hash::Sha1, "a=b,c", "pencil", "abc");
+    assert_eq!(client.client_first(), b"n,,n=a=3Db=2Cc,r=abc");
+}
@@ -0,0 +1,138 @@
+use oasis_amqp::link::LinkState;
+use oasis_amqp::proto::ConnectionState;
+use oasis_amqp::server::Users;
+use oasis_amqp::{amqp, sasl, Client, ConnectionError, Credit, Listener, Outcome, RemoteError};
+
+#[tokio::test]
+async fn server_login() {
+    let listener = Listener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let users = Users::new().user("user", "secret");
+        let (mut conn, _) = listener.accept().await.unwrap();
+        match conn.authenticate(&users).await {
+            Err(ConnectionError::Sasl {
+                code: sasl::Code::Auth,
+                ..
+            }) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let (mut conn, _) = listener.accept().await.unwrap();
+        let identity = conn.authenticate(&users).await.unwrap();
+        conn.open("server").await.unwrap();
+        assert_eq!(conn.state(), ConnectionState::Opened);
+        match conn.accept_session().await {
+            Err(ConnectionError::RemoteClose(None)) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+        identity
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    match client.login("user", "wrong").await {
+        Err(ConnectionError::Sasl {
+            code: sasl::Code::Auth,
+            ..
+        }) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    drop(client);
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.login("user", "secret").await.unwrap();
+    client.open("client").await.unwrap();
+    assert_eq!(client.state(), ConnectionState::Opened);
+    client.close().await.unwrap();
+    assert_eq!(server.await.unwrap(), "user");
+}
+
+#[tokio::test]
+async fn server_links() {
+    let listener = Listener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut conn, _) = listener.accept().await.unwrap();
+        conn.open("server").await.unwrap();
+        let session = conn.accept_session().await.unwrap();
+
+        // The client sends to "in", and each message is passed on to its receiver on "out"
+        let incoming = session.accept_link().await.unwrap();
+        assert_eq!(incoming.role(), amqp::Role::Receiver);
+        assert_eq!(incoming.target(), Some("in"));
+        let receiver = incoming.accept_receiver(Credit::Prefetch(8)).unwrap();
+
+        let incoming = session.accept_link().await.unwrap();
+        assert_eq!(incoming.role(), amqp::Role::Sender);
+        assert_eq!(incoming.source(), Some("out"));
+        let sender = incoming.accept_sender().unwrap();
+
+        let incoming = session.accept_link().await.unwrap();
+        assert_eq!(incoming.source(), Some("missing"));
+        let error = amqp::Error {
+            condition: "amqp:not-found",
+            description: Some("no such node"),
+            info: None,
+        };
+        incoming.reject(Some(error)).unwrap();
+
+        for _ in 0..2 {
+            let delivery = receiver.recv().await.unwrap();
+            let body = delivery.body().unwrap().to_vec();
+            if body == b"bad" {
+                delivery.reject(None).await.unwrap();
+                continue;
+            }
+
+            delivery.accept().await.unwrap();
+            let message = amqp::Message {
+                body: Some(amqp::Body::Data(amqp::Data(body.into()))),
+                ..Default::default()
+            };
+            assert_eq!(sender.send(message).await.unwrap(), Outcome::Accepted);
+        }
+        conn.close().await.unwrap();
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.open("client").await.unwrap();
+    let session = client.begin().await.unwrap();
+    let sender = session.sender("in").attach().await.unwrap();
+    assert_eq!(sender.state(), LinkState::Attached);
+    let receiver = session.receiver("out").attach().await.unwrap();
+    receiver.flow(1).await.unwrap();
+
+    let refused = match session.receiver("missing").attach().await {
+        Ok(receiver) => receiver.recv().await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    match refused {
+        Err(ConnectionError::RemoteDetach(Some(RemoteError { condition, .. }))) => {
+            assert_eq!(condition, "amqp:not-found")
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    let message = |body: &'static [u8]| amqp::Message {
+        body: Some(amqp::Body::Data(amqp::Data(body.into()))),
+        ..Default::default()
+    };
+    match sender.send(message(b"bad")).await.unwrap() {
+        Outcome::Rejected(_) => {}
+        outcome => panic!("unexpected outcome: {:?}", outcome),
+    }
+    assert_eq!(
+        sender.send(message(b"hello")).await.unwrap(),
+        Outcome::Accepted
+    );
+
+    let delivery = receiver.recv().await.unwrap();
+    assert_eq!(delivery.body(), Some(&b"hello"[..]));
+    delivery.accept().await.unwrap();
+
+    // The server closes the connection once it is done
+    match receiver.recv().await {
+        Err(ConnectionError::RemoteClose(None)) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
@@ -0,0 +1,93 @@
+#![cfg(feature = "tls")]
+
+use std::sync::Arc;
+
+use futures::{SinkExt, StreamExt};
+use rcgen::KeyPair;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{self, RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::codec::Framed;
+
+use oasis_amqp::proto::{Codec, ConnectionState, Frame, Protocol};
+use oasis_amqp::tls::{pki_types::PrivatePkcs8KeyDer, PrivateKeyDer, TlsConfig};
+use oasis_amqp::{amqp, Client, ConnectionError};
+
+#[tokio::test]
+async fn tls() {
+    let server_cert = rcgen::generate_simple_self_signed(vec!["amqp.example".into()]).unwrap();
+    let client_cert = rcgen::generate_simple_self_signed(vec!["client".into()]).unwrap();
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut client_roots = RootCertStore::empty();
+    client_roots.add(client_cert.cert.der().clone()).unwrap();
+    let verifier =
+        WebPkiClientVerifier::builder_with_provider(client_roots.into(), provider.clone())
+            .build()
+            .unwrap();
+    let config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(
+            vec![server_cert.cert.der().clone()],
+            key(&server_cert.key_pair),
+        )
+        .unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let stream = loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            if let Ok(stream) = acceptor.accept(stream).await {
+                break stream;
+            }
+        };
+
+        let mut server = Framed::new(stream, Codec);
+        let _header = server.next().await.unwrap().unwrap();
+        server.send(&Frame::Header(Protocol::Amqp)).await.unwrap();
+        let _open = server.next().await.unwrap().unwrap();
+        let open = Frame::Amqp(amqp::Frame {
+            channel: 0,
+            extended_header: None,
+            performative: amqp::Performative::Open(amqp::Open {
+                container_id: "server",
+                ..Default::default()
+            }),
+            message: None,
+        });
+        server.send(&open).await.unwrap();
+        let _close = server.next().await;
+    });
+
+    // The certificate doesn't match the host, so verification fails without an override
+    let config = || {
+        TlsConfig::new()
+            .root_certificate(server_cert.cert.der().clone())
+            .unwrap()
+            .client_certificate(
+                vec![client_cert.cert.der().clone()],
+                key(&client_cert.key_pair),
+            )
+    };
+    match Client::connect_tls("127.0.0.1", port, &config()).await {
+        Err(ConnectionError::Tls(rustls::Error::InvalidCertificate(_))) => {}
+        Ok(_) => panic!("connected without a matching server name"),
+        Err(e) => panic!("unexpected error: {:?}", e),
+    }
+
+    let config = config().server_name("amqp.example");
+    let mut client = Client::connect_tls("127.0.0.1", port, &config)
+        .await
+        .unwrap();
+    client.open("client").await.unwrap();
+    assert_eq!(client.state(), ConnectionState::Opened);
+}
+
+fn key(key: &KeyPair) -> PrivateKeyDer<'static> {
+    PrivatePkcs8KeyDer::from(key.serialize_der()).into()
+}
@@ -0,0 +1,81 @@
+#![cfg(feature = "websocket")]
+
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_util::codec::Framed;
+
+use oasis_amqp::proto::{Codec, ConnectionState, Frame, Protocol};
+use oasis_amqp::ws::{WsStream, SUBPROTOCOL};
+use oasis_amqp::{amqp, Client, ConnectionError};
+
+#[tokio::test]
+async fn websocket() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/amqp", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        // The first upgrade doesn't confirm the subprotocol, which the client must reject
+        let (stream, _) = listener.accept().await.unwrap();
+        let _ = tokio_tungstenite::accept_async(stream).await;
+
+        let (stream, _) = listener.accept().await.unwrap();
+        #[allow(clippy::result_large_err)] // The error type is defined by tungstenite
+        let callback = |request: &Request, mut response: Response| {
+            assert_eq!(request.uri().path(), "/amqp");
+            let protocol = request.headers().get("Sec-WebSocket-Protocol").unwrap();
+            assert_eq!(protocol, SUBPROTOCOL);
+            response.headers_mut().insert(
+                "Sec-WebSocket-Protocol",
+                HeaderValue::from_static(SUBPROTOCOL),
+            );
+            Ok(response)
+        };
+        let stream = tokio_tungstenite::accept_hdr_async(stream, callback)
+            .await
+            .unwrap();
+
+        let mut server = Framed::new(WsStream::from(stream), Codec);
+        let header = server.next().await.unwrap().unwrap();
+        assert_eq!(header.frame(), &Frame::Header(Protocol::Amqp));
+        server.send(&Frame::Header(Protocol::Amqp)).await.unwrap();
+        let _open = server.next().await.unwrap().unwrap();
+        let open = Frame::Amqp(amqp::Frame {
+            channel: 0,
+            extended_header: None,
+            performative: amqp::Performative::Open(amqp::Open {
+                container_id: "server",
+                ..Default::default()
+            }),
+            message: None,
+        });
+        server.send(&open).await.unwrap();
+
+        let close = server.next().await.unwrap().unwrap();
+        match close.frame() {
+            Frame::Amqp(amqp::Frame {
+                performative: amqp::Performative::Close(_),
+                ..
+            }) => {}
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+        let close = Frame::Amqp(amqp::Frame {
+            channel: 0,
+            extended_header: None,
+            performative: amqp::Performative::Close(amqp::Close { error: None }),
+            message: None,
+        });
+        server.send(&close).await.unwrap();
+    });
+
+    match Client::connect_websocket(&url).await {
+        Err(ConnectionError::WebSocket(_)) => {}
+        Ok(_) => panic!("connected without the amqp subprotocol"),
+        Err(e) => panic!("unexpected error: {:?}", e),
+    }
+
+    let mut client = Client::connect_websocket(&url).await.unwrap();
+    client.open("client").await.unwrap();
+    assert_eq!(client.state(), ConnectionState::Opened);
+    client.close().await.unwrap();
+}